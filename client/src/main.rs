@@ -30,7 +30,7 @@ fn main() {
                 .window_size((400.0, 400.0));
 
             AppLauncher::with_window(main_window)
-                .launch(froggi::layout::OwnedPage::from(page))
+                .launch(page.into_owned())
                 .unwrap();
         }
 
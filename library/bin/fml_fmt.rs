@@ -0,0 +1,125 @@
+use froggi::markup::diagnostic::Diagnostic;
+use froggi::markup::{format, page_files};
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: fml_fmt [--check] [--width N] [<file or directory>...]";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+/// Format `source`, printing its errors if it has any.
+fn format(name: &str, source: &str, width: usize) -> Option<String> {
+    match format::format(source, width) {
+        Ok(formatted) => Some(formatted),
+        Err(errors) => {
+            for diagnostic in Diagnostic::all(&errors, source) {
+                eprintln!("{}", diagnostic.render(name, source));
+            }
+            None
+        }
+    }
+}
+
+fn main() {
+    let mut check = false;
+    let mut width = format::WIDTH;
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--width" => {
+                width = match args.next().and_then(|width| width.parse().ok()) {
+                    Some(width) => width,
+                    None => usage(),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-" => paths.push(PathBuf::from(arg)),
+            _ if arg.starts_with('-') => usage(),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    // with no files, format stdin to stdout
+    if paths.is_empty() || paths == [PathBuf::from("-")] {
+        let mut source = String::new();
+        if let Err(error) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("error: could not read stdin - {}", error);
+            std::process::exit(2);
+        }
+
+        let formatted = match format("<stdin>", &source, width) {
+            Some(formatted) => formatted,
+            None => std::process::exit(1),
+        };
+
+        if check {
+            if formatted != source {
+                println!("<stdin> is not formatted");
+                std::process::exit(1);
+            }
+        } else {
+            std::io::stdout()
+                .write_all(formatted.as_bytes())
+                .expect("could not write to stdout");
+        }
+
+        return;
+    }
+
+    let mut files = Vec::new();
+    for path in &paths {
+        if let Err(error) = page_files(path, &mut files) {
+            eprintln!("error: could not read {} - {}", path.display(), error);
+            std::process::exit(2);
+        }
+    }
+
+    let mut failed = false;
+    for file in &files {
+        let name = file.display().to_string();
+        let source = match std::fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("error: could not read {} - {}", name, error);
+                failed = true;
+                continue;
+            }
+        };
+
+        let formatted = match format(&name, &source, width) {
+            Some(formatted) => formatted,
+            None => {
+                failed = true;
+                continue;
+            }
+        };
+
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("{} is not formatted", name);
+            failed = true;
+        } else if let Err(error) = std::fs::write(file, formatted) {
+            eprintln!("error: could not write {} - {}", name, error);
+            failed = true;
+        } else {
+            println!("formatted {}", name);
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -1,18 +1,113 @@
-use std::fs::File;
-use std::io::Read;
+use froggi::markup::diagnostic::Diagnostic;
+use froggi::markup::page_files;
+
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "usage: verify_markup [--format human|json] <file or directory>...";
+
+#[derive(PartialEq)]
+enum Format {
+    Human,
+    Json,
+}
+
+/// The problems with the page at `path`, if any.
+fn verify(path: &Path) -> (String, Vec<Diagnostic>) {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            let diagnostic = Diagnostic {
+                message: format!("could not read file - {}", error),
+                span: None,
+                notes: Vec::new(),
+                hint: None,
+            };
+            return (String::new(), vec![diagnostic]);
+        }
+    };
+
+    let diagnostics = match froggi::markup::parse::parse(&source) {
+        Ok(_) => Vec::new(),
+        Err(errors) => Diagnostic::all(&errors, &source),
+    };
+
+    (source, diagnostics)
+}
 
 fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
-    if args.len() < 2 {
-        println!("no filename");
-        return;
+    let mut format = Format::Human;
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "--format" => args.next(),
+            _ if arg.starts_with("--format=") => Some(arg["--format=".len()..].into()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {
+                paths.push(PathBuf::from(arg));
+                continue;
+            }
+        };
+
+        format = match value.as_deref() {
+            Some("human") => Format::Human,
+            Some("json") => Format::Json,
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        };
     }
 
-    let mut file = File::open(&args[1]).unwrap();
-    let mut page = String::new();
-    file.read_to_string(&mut page).unwrap();
-    let page = froggi::markup::parse::parse(&page).unwrap();
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let mut files = Vec::new();
+    for path in &paths {
+        if let Err(error) = page_files(path, &mut files) {
+            eprintln!("error: could not read {} - {}", path.display(), error);
+            std::process::exit(2);
+        }
+    }
+
+    let mut failed = 0;
+    let mut json = Vec::new();
+    for file in &files {
+        let (source, diagnostics) = verify(file);
+        let name = file.display().to_string();
+
+        if !diagnostics.is_empty() {
+            failed += 1;
+        }
 
-    dbg!(page);
-    println!("page ok");
+        match format {
+            Format::Human if diagnostics.is_empty() => println!("{}: ok", name),
+            Format::Human => {
+                for diagnostic in &diagnostics {
+                    eprintln!("{}", diagnostic.render(&name, &source));
+                }
+            }
+            Format::Json => json.extend(
+                diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.to_json(&name)),
+            ),
+        }
+    }
+
+    if format == Format::Json {
+        println!("[{}]", json.join(",\n"));
+    } else if failed != 0 {
+        eprintln!("{} of {} pages have errors", failed, files.len());
+    }
+
+    if failed != 0 {
+        std::process::exit(1);
+    }
 }
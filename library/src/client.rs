@@ -0,0 +1,425 @@
+//! Sending requests on behalf of a user.
+//!
+//! With the `tls` feature, a client can also connect over TLS, presenting one of the
+//! identities the user keeps for each host and remembering the certificate each host
+//! presented the first time it was visited.
+
+use crate::request::{Request, Upload};
+use crate::response::Response;
+use crate::FroggiError;
+
+#[cfg(feature = "tls")]
+use crate::tls::{self, Identity};
+
+#[cfg(feature = "tls")]
+use std::convert::TryFrom;
+use std::io::Write;
+use std::net::TcpStream;
+
+/// Sends requests, attaching the credentials the user has for each host.
+///
+/// Tokens and identities are only ever sent to the host they were added for.
+#[derive(Debug, Default, Clone)]
+pub struct Client {
+    tokens: Vec<(String, String)>,
+    #[cfg(feature = "tls")]
+    identities: Vec<HostIdentities>,
+    #[cfg(feature = "tls")]
+    servers: Vec<(String, String)>,
+}
+
+/// The identities a user has for one host, and which of them they're using.
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+struct HostIdentities {
+    host: String,
+    identities: Vec<Identity>,
+    active: Option<String>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    /// Send `token` with every request to `host`, replacing any token it already had.
+    pub fn token(mut self, host: &str, token: impl ToString) -> Self {
+        self.set_token(host, token);
+        self
+    }
+
+    pub fn set_token(&mut self, host: &str, token: impl ToString) {
+        let token = token.to_string();
+        match self
+            .tokens
+            .iter_mut()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        {
+            Some(entry) => entry.1 = token,
+            None => self.tokens.push((host.into(), token)),
+        }
+    }
+
+    /// Stop sending a token to `host`.
+    pub fn remove_token(&mut self, host: &str) {
+        self.tokens
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(host));
+    }
+
+    /// The token sent to `host`, if there is one.
+    pub fn token_for(&self, host: &str) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+            .map(|(_, token)| token.as_str())
+    }
+
+    /// Build a request for a page on `host`, with its credentials.
+    pub fn request(&self, host: &str, path: &str) -> Result<Request, FroggiError> {
+        self.with_credentials(host, Request::new(path)?)
+    }
+
+    /// Build a request to publish a page at `path` on `host`, with its credentials.
+    pub fn publish_request(
+        &self,
+        host: &str,
+        path: &str,
+        upload: Upload,
+    ) -> Result<Request, FroggiError> {
+        self.with_credentials(host, Request::publish(path, upload)?)
+    }
+
+    fn with_credentials(&self, host: &str, mut request: Request) -> Result<Request, FroggiError> {
+        request.set_metadata("host", host)?;
+        if let Some(token) = self.token_for(host) {
+            request.set_metadata("token", token)?;
+        }
+
+        Ok(request)
+    }
+
+    /// Request a page from `host` and return the response.
+    pub fn send(&self, host: &str, port: u16, path: &str) -> Result<Response, FroggiError> {
+        send_request(host, port, self.request(host, path)?)
+    }
+
+    /// Publish a page at `path` on `host`. The response's status says whether the
+    /// server accepted it.
+    pub fn publish(
+        &self,
+        host: &str,
+        port: u16,
+        path: &str,
+        upload: Upload,
+    ) -> Result<Response, FroggiError> {
+        send_request(host, port, self.publish_request(host, path, upload)?)
+    }
+}
+
+fn send_request(host: &str, port: u16, request: Request) -> Result<Response, FroggiError> {
+    let mut stream = TcpStream::connect((host, port))?;
+    stream.write_all(&request.into_bytes())?;
+
+    Response::from_bytes(&mut stream)
+}
+
+#[cfg(feature = "tls")]
+impl Client {
+    fn host_identities(&mut self, host: &str) -> &mut HostIdentities {
+        let i = match self
+            .identities
+            .iter()
+            .position(|entry| entry.host.eq_ignore_ascii_case(host))
+        {
+            Some(i) => i,
+            None => {
+                self.identities.push(HostIdentities {
+                    host: host.into(),
+                    identities: Vec::new(),
+                    active: None,
+                });
+                self.identities.len() - 1
+            }
+        };
+
+        &mut self.identities[i]
+    }
+
+    /// Keep an identity for `host`, replacing any with the same name. It isn't
+    /// presented until it's chosen with [`Client::use_identity`].
+    pub fn add_identity(&mut self, host: &str, identity: Identity) {
+        let entry = self.host_identities(host);
+        entry
+            .identities
+            .retain(|existing| existing.name() != identity.name());
+        entry.identities.push(identity);
+    }
+
+    /// Make a new identity for `host` and start presenting it.
+    pub fn generate_identity(&mut self, host: &str, name: &str) -> Result<&Identity, FroggiError> {
+        self.add_identity(host, Identity::generate(name)?);
+        self.use_identity(host, name);
+        Ok(self.identity_for(host).expect("identity was just added"))
+    }
+
+    /// Present the identity called `name` to `host`. Returns false if there is no such
+    /// identity.
+    pub fn use_identity(&mut self, host: &str, name: &str) -> bool {
+        let entry = self.host_identities(host);
+        let exists = entry
+            .identities
+            .iter()
+            .any(|identity| identity.name() == name);
+        if exists {
+            entry.active = Some(name.into());
+        }
+
+        exists
+    }
+
+    /// Visit `host` anonymously, keeping its identities for later.
+    pub fn stop_using_identity(&mut self, host: &str) {
+        self.host_identities(host).active = None;
+    }
+
+    /// Forget the identity called `name` for `host`.
+    pub fn remove_identity(&mut self, host: &str, name: &str) {
+        let entry = self.host_identities(host);
+        entry.identities.retain(|identity| identity.name() != name);
+        if entry.active.as_deref() == Some(name) {
+            entry.active = None;
+        }
+    }
+
+    /// Every identity kept for `host`.
+    pub fn identities(&self, host: &str) -> &[Identity] {
+        self.identities
+            .iter()
+            .find(|entry| entry.host.eq_ignore_ascii_case(host))
+            .map(|entry| entry.identities.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The identity presented to `host`, if there is one.
+    pub fn identity_for(&self, host: &str) -> Option<&Identity> {
+        let entry = self
+            .identities
+            .iter()
+            .find(|entry| entry.host.eq_ignore_ascii_case(host))?;
+        let active = entry.active.as_deref()?;
+        entry
+            .identities
+            .iter()
+            .find(|identity| identity.name() == active)
+    }
+
+    /// Expect `host` to present the certificate with `fingerprint`.
+    pub fn trust_server(&mut self, host: &str, fingerprint: impl ToString) {
+        self.forget_server(host);
+        self.servers.push((host.into(), fingerprint.to_string()));
+    }
+
+    /// Accept whatever certificate `host` presents next.
+    pub fn forget_server(&mut self, host: &str) {
+        self.servers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(host));
+    }
+
+    /// The fingerprint of the certificate `host` is expected to present.
+    pub fn server_fingerprint(&self, host: &str) -> Option<&str> {
+        self.servers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+            .map(|(_, fingerprint)| fingerprint.as_str())
+    }
+
+    /// Request a page from `host` over TLS, presenting the identity in use for it.
+    ///
+    /// The first certificate a host presents is trusted from then on, and the
+    /// request isn't sent if a host presents a different one later.
+    pub fn send_tls(&mut self, host: &str, port: u16, path: &str) -> Result<Response, FroggiError> {
+        let request = self.request(host, path)?;
+        self.send_request_tls(host, port, request)
+    }
+
+    /// Publish a page at `path` on `host` over TLS, like [`Client::send_tls`].
+    pub fn publish_tls(
+        &mut self,
+        host: &str,
+        port: u16,
+        path: &str,
+        upload: Upload,
+    ) -> Result<Response, FroggiError> {
+        let request = self.publish_request(host, path, upload)?;
+        self.send_request_tls(host, port, request)
+    }
+
+    fn send_request_tls(
+        &mut self,
+        host: &str,
+        port: u16,
+        request: Request,
+    ) -> Result<Response, FroggiError> {
+        let config = tls::client_config(self.identity_for(host))?;
+        let server_name = rustls::ServerName::try_from(host).map_err(tls::error)?;
+        let mut connection =
+            rustls::ClientConnection::new(config, server_name).map_err(tls::error)?;
+
+        let mut socket = TcpStream::connect((host, port))?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut socket)?;
+        }
+
+        let fingerprint = connection
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .map(|certificate| tls::fingerprint(&certificate.0))
+            .ok_or_else(|| tls::error("the server presented no certificate"))?;
+
+        match self.server_fingerprint(host) {
+            Some(known) if known != fingerprint => {
+                return Err(tls::error(format!(
+                    "{} presented a different certificate than before, {} instead of {}",
+                    host, fingerprint, known
+                )));
+            }
+            Some(_) => {}
+            None => self.trust_server(host, &fingerprint),
+        }
+
+        let mut stream = rustls::Stream::new(&mut connection, &mut socket);
+        stream.write_all(&request.into_bytes())?;
+        stream.flush()?;
+
+        Response::from_bytes(&mut stream)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::response::Status;
+    use crate::server::Server;
+
+    #[test]
+    fn tokens_go_to_their_host() {
+        let client = Client::new()
+            .token("froggi.io", "secret")
+            .token("FROGGI.io", "newer");
+
+        let request = client.request("froggi.io", "/").unwrap();
+        assert_eq!(request.token(), Some("newer"));
+        assert_eq!(request.host(), Some("froggi.io"));
+
+        let request = client.request("example.com", "/").unwrap();
+        assert_eq!(request.token(), None);
+    }
+
+    #[test]
+    fn send() {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addrs()[0].port();
+        std::thread::spawn(move || {
+            server.serve(|request: &Request| match request.token() {
+                Some("secret") => Response::new(String::from("(\"welcome\")"), Vec::new()),
+                _ => Response::error(Status::Unauthorized, "who are you?"),
+            })
+        });
+
+        let mut client = Client::new();
+        let response = client.send("127.0.0.1", port, "/").unwrap();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        client.set_token("127.0.0.1", "secret");
+        let response = client.send("127.0.0.1", port, "/").unwrap();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.page(), "(\"welcome\")");
+    }
+
+    #[test]
+    fn publish() {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addrs()[0].port();
+        std::thread::spawn(move || {
+            server.serve(|request: &Request| match request.upload() {
+                Some(upload) => Response::new(upload.page().into(), upload.items().to_vec()),
+                None => Response::error(Status::BadRequest, "nothing to publish"),
+            })
+        });
+
+        let client = Client::new();
+        let upload = Upload::new(String::from("(\"frog\")"), Vec::new());
+        let response = client
+            .publish("127.0.0.1", port, "/frog.fml", upload)
+            .unwrap();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.page(), "(\"frog\")");
+
+        let response = client.send("127.0.0.1", port, "/frog.fml").unwrap();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn identities() {
+        let mut client = Client::new();
+        let first = client
+            .generate_identity("froggi.io", "frog")
+            .unwrap()
+            .clone();
+        client.add_identity("froggi.io", Identity::generate("toad").unwrap());
+        assert_eq!(client.identities("FROGGI.IO").len(), 2);
+        assert_eq!(
+            client.identity_for("froggi.io").unwrap().fingerprint(),
+            first.fingerprint()
+        );
+        assert!(client.identity_for("example.com").is_none());
+
+        assert!(client.use_identity("froggi.io", "toad"));
+        assert!(!client.use_identity("froggi.io", "newt"));
+        assert_eq!(client.identity_for("froggi.io").unwrap().name(), "toad");
+
+        client.remove_identity("froggi.io", "toad");
+        assert!(client.identity_for("froggi.io").is_none());
+        assert_eq!(client.identities("froggi.io").len(), 1);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn send_tls() {
+        let server_identity = Identity::generate("server").unwrap();
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .tls(&server_identity)
+            .unwrap();
+        let port = server.local_addrs()[0].port();
+        std::thread::spawn(move || {
+            server.serve(|request: &Request| {
+                let page = format!(
+                    "(\"{}\")",
+                    request.client_fingerprint().unwrap_or("anonymous")
+                );
+                Response::new(page, Vec::new())
+            })
+        });
+
+        let mut client = Client::new();
+        let response = client.send_tls("127.0.0.1", port, "/").unwrap();
+        assert_eq!(response.page(), "(\"anonymous\")");
+        assert_eq!(
+            client.server_fingerprint("127.0.0.1"),
+            Some(server_identity.fingerprint().as_str())
+        );
+
+        let identity = client
+            .generate_identity("127.0.0.1", "frog")
+            .unwrap()
+            .clone();
+        let response = client.send_tls("127.0.0.1", port, "/").unwrap();
+        assert_eq!(response.page(), format!("(\"{}\")", identity.fingerprint()));
+
+        client.trust_server("127.0.0.1", "sha256:00");
+        assert!(client.send_tls("127.0.0.1", port, "/").is_err());
+    }
+}
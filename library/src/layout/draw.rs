@@ -1,102 +1,6 @@
 use crate::markup::scan::TokenKind;
-use crate::markup::{InlineStyle, ItemPayload, PageItem, PageStyles};
-
-use std::collections::HashSet;
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum FontType {
-    Mono,
-    Serif,
-    Sans,
-}
-
-#[derive(Debug, PartialEq, Clone, Copy, Default)]
-pub struct FontStyle {
-    pub bold: bool,
-    pub italic: bool,
-    pub underline: bool,
-    pub strike: bool,
-}
-
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub struct Style {
-    pub font_type: FontType,
-    pub font_style: FontStyle,
-    pub background: (u8, u8, u8),
-    pub foreground: (u8, u8, u8),
-    pub fill: u8,
-    pub size: usize,
-}
-
-impl Style {
-    fn new() -> Style {
-        Style {
-            font_type: FontType::Serif,
-            font_style: FontStyle::default(),
-            background: (0xff, 0xff, 0xff),
-            foreground: (0x00, 0x00, 0x00),
-            fill: 1,
-            size: 12,
-        }
-    }
-
-    fn set_font_type(&mut self, font_type: FontType) {
-        self.font_type = font_type;
-    }
-
-    fn set_bold(&mut self) {
-        self.font_style.bold = true;
-    }
-
-    fn set_italic(&mut self) {
-        self.font_style.italic = true;
-    }
-
-    fn set_underline(&mut self) {
-        self.font_style.underline = true;
-    }
-
-    fn set_strike(&mut self) {
-        self.font_style.strike = true;
-    }
-
-    fn set_background(&mut self, background: (u8, u8, u8)) {
-        self.background = background;
-    }
-
-    fn set_foreground(&mut self, foreground: (u8, u8, u8)) {
-        self.foreground = foreground;
-    }
-
-    fn set_fill(&mut self, fill: u8) {
-        self.fill = fill;
-    }
-
-    fn set_size(&mut self, size: usize) {
-        self.size = size;
-    }
-}
-
-fn inline_styles_to_style(styles: &[InlineStyle], page_styles: &PageStyles, style: &mut Style) {
-    for inline_style in styles {
-        match inline_style {
-            InlineStyle::Mono { .. } => style.set_font_type(FontType::Mono),
-            InlineStyle::Serif { .. } => style.set_font_type(FontType::Serif),
-            InlineStyle::Sans { .. } => style.set_font_type(FontType::Sans),
-            InlineStyle::Bold { .. } => style.set_bold(),
-            InlineStyle::Italic { .. } => style.set_italic(),
-            InlineStyle::Underline { .. } => style.set_underline(),
-            InlineStyle::Strike { .. } => style.set_strike(),
-            InlineStyle::Fg { arg, .. } => style.set_foreground(*arg),
-            InlineStyle::Bg { arg, .. } => style.set_background(*arg),
-            InlineStyle::Fill { arg, .. } => style.set_fill(*arg),
-            InlineStyle::Size { arg, .. } => style.set_size(*arg),
-            InlineStyle::UserDefined { token, .. } => {
-                inline_styles_to_style(page_styles.get(token).unwrap(), page_styles, style)
-            }
-        }
-    }
-}
+use crate::markup::style::{self, ComputedStyle};
+use crate::markup::{ItemPayload, PageItem, PageStyles};
 
 // available width: 700px
 // find out how many child box items there are, evenly distribute dependent on fill
@@ -105,25 +9,40 @@ fn inline_styles_to_style(styles: &[InlineStyle], page_styles: &PageStyles, styl
 // in order to actually draw anything, we'll need to figure out bounding boxes for items.
 // we'll do that here and then stick everything into DrawItems.
 
+/// Draw an item at the top of a page.
 pub fn draw_item(
     item: &PageItem,
     page_styles: &PageStyles,
     start_point: (usize, usize),
     max_width: usize,
+) -> usize {
+    let style = style::item_style(&[], &ComputedStyle::default(), item, page_styles);
+    draw(&[], item, &style, page_styles, start_point, max_width)
+}
+
+/// Draw an item inside `ancestors`, outermost first, which has already been given its
+/// `style`, so that its children inherit from it.
+fn draw<'a, 'b>(
+    ancestors: &[&'b PageItem<'a>],
+    item: &'b PageItem<'a>,
+    style: &ComputedStyle,
+    page_styles: &PageStyles,
+    start_point: (usize, usize),
+    max_width: usize,
 ) -> usize {
     use super::DrawItem;
     println!("{:?} {:?} --- {:?}", start_point, max_width, item.builtin,);
-    let mut style = Style::new();
-    inline_styles_to_style(&item.styles, page_styles, &mut style);
 
     let dy = match &item.payload {
         ItemPayload::Children { children, .. } => match item.builtin.kind() {
             TokenKind::Box => {
+                let mut inside = ancestors.to_vec();
+                inside.push(item);
+
                 let mut total_units = 0;
                 let mut draw_items = Vec::new();
                 for child in children {
-                    let mut child_style = Style::new();
-                    inline_styles_to_style(&child.styles, page_styles, &mut child_style);
+                    let child_style = style::item_style(&inside, style, child, page_styles);
                     total_units += child_style.fill;
                     draw_items.push(DrawItem {
                         item: child,
@@ -138,8 +57,10 @@ pub fn draw_item(
                 let mut largest_dy = 0;
                 for child in draw_items {
                     let child_max_width = width_per_unit * child.style.fill as usize;
-                    let dy = draw_item(
+                    let dy = draw(
+                        &inside,
                         child.item,
+                        &child.style,
                         page_styles,
                         (start_point.0 + current_x, start_point.1),
                         child_max_width,
@@ -195,85 +116,10 @@ pub fn draw_item(
             println!("anchor");
             1
         }
+
+        ItemPayload::Error => 0,
     };
 
     println!("dy: {}", dy);
     dy
 }
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn font_style() {
-        let page = r#"{(a italic) (b bold) (c mono)} ({a b c underline} "")"#;
-        let page = crate::markup::parse::parse(page).unwrap();
-        let mut style = Style::new();
-        inline_styles_to_style(&page.items[0].styles, &page.styles, &mut style);
-
-        assert_eq!(
-            style,
-            Style {
-                font_type: FontType::Mono,
-                font_style: FontStyle {
-                    italic: true,
-                    bold: true,
-                    underline: true,
-                    ..FontStyle::default()
-                },
-                background: (0xff, 0xff, 0xff),
-                foreground: (0x00, 0x00, 0x00),
-                fill: 1,
-                size: 12,
-            }
-        );
-    }
-
-    #[test]
-    fn bg_fg_application_order() {
-        let page = r#"
-        {(a (bg "b11111"))
-         (b (fg "f22222") (bg "baaaad"))
-         (c (bg "b33333"))}
-
-        ({(fg "f11111") a (bg "b22222") b (fg "f33333") c} "")
-        "#;
-
-        let page = crate::markup::parse::parse(page).unwrap();
-        let mut style = Style::new();
-        inline_styles_to_style(&page.items[0].styles, &page.styles, &mut style);
-
-        assert_eq!(
-            style,
-            Style {
-                font_type: FontType::Serif,
-                font_style: FontStyle::default(),
-                background: (0xb3, 0x33, 0x33),
-                foreground: (0xf3, 0x33, 0x33),
-                fill: 1,
-                size: 12,
-            }
-        );
-    }
-
-    #[test]
-    fn font_type_application_order() {
-        let page = r#"{(a sans) (b serif) (c mono)} ({a b c} "")"#;
-        let page = crate::markup::parse::parse(page).unwrap();
-        let mut style = Style::new();
-        inline_styles_to_style(&page.items[0].styles, &page.styles, &mut style);
-
-        assert_eq!(
-            style,
-            Style {
-                font_type: FontType::Mono,
-                font_style: FontStyle::default(),
-                background: (0xff, 0xff, 0xff),
-                foreground: (0x00, 0x00, 0x00),
-                fill: 1,
-                size: 12,
-            }
-        );
-    }
-}
@@ -9,11 +9,15 @@ pub mod layout;
 #[cfg(feature = "layout")]
 pub extern crate druid;
 
+pub mod client;
 pub mod markup;
 pub mod request;
 pub mod response;
+pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
 
-pub const FROGGI_VERSION: u8 = 0;
+pub const FROGGI_VERSION: u8 = 3;
 
 /// Send a froggi request to a server and return its response.
 pub fn send_request(to: impl ToSocketAddrs, path: &str) -> Result<response::Response, FroggiError> {
@@ -23,6 +27,17 @@ pub fn send_request(to: impl ToSocketAddrs, path: &str) -> Result<response::Resp
     Ok(response::Response::from_bytes(&mut stream)?)
 }
 
+/// Send a froggi request for a page on a particular host and return its response.
+///
+/// Use a [`client::Client`] to send credentials along with the request.
+pub fn send_request_to_host(
+    host: &str,
+    port: u16,
+    path: &str,
+) -> Result<response::Response, FroggiError> {
+    client::Client::new().send(host, port, path)
+}
+
 /// Serialize a usize into a little-endian pair of bytes.
 pub fn serialize_to_bytes(bytes: usize) -> (u8, u8) {
     assert!(bytes <= u16::MAX as usize);
@@ -66,6 +81,7 @@ pub fn deserialize_four_bytes(bytes: &[u8]) -> usize {
 #[derive(Debug)]
 pub enum ScanError {
     UnknownEscapeCode { code: char },
+    InvalidCodepoint { escape: String },
     UnterminatedString { start_line: usize },
 }
 
@@ -73,6 +89,13 @@ impl fmt::Display for ScanError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ScanError::UnknownEscapeCode { code } => write!(f, "unknown escape code: {}", code),
+            ScanError::InvalidCodepoint { escape } => {
+                write!(
+                    f,
+                    "{} is not a unicode codepoint, expected e.g. \\u{{1f438}}",
+                    escape
+                )
+            }
             ScanError::UnterminatedString { start_line } => {
                 write!(f, "unterminated string starting on line {}", start_line)
             }
@@ -80,7 +103,7 @@ impl fmt::Display for ScanError {
     }
 }
 
-use markup::scan::TokenKind;
+use markup::scan::{Span, TokenKind};
 
 #[derive(Debug)]
 pub enum ParseError {
@@ -115,14 +138,43 @@ impl fmt::Display for ParseError {
     }
 }
 
+/// A page from a [`markup::builder::PageBuilder`] that wouldn't parse.
+#[derive(Debug)]
+pub enum BuildError {
+    InvalidClass { class: String },
+    UnknownClass { class: String },
+    ClassInPageStyle { class: String },
+    EmptySelector,
+}
+
+#[rustfmt::skip]
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::InvalidClass { class }
+                => write!(f, "{:?} can't be used as the name of a style", class),
+            BuildError::UnknownClass { class }
+                => write!(f, "style {:?} is not in the page style", class),
+            BuildError::ClassInPageStyle { class }
+                => write!(f, "page styles can't use other page styles, like {:?}", class),
+            BuildError::EmptySelector
+                => write!(f, "page style rules need a selector"),
+        }
+    }
+}
+
 /// Errors that are possible in the froggi protocol.
 #[derive(Debug)]
 pub enum ErrorKind {
     EncodingError { error: str::Utf8Error },
     RequestFormatError,
+    ResponseFormatError,
+    HandlerPanic,
+    TlsError,
     IOError { error: io::Error },
-    ScanError { error: ScanError, line: usize },
-    ParseError { error: ParseError, line: usize },
+    ScanError { error: ScanError, span: Span },
+    ParseError { error: ParseError, span: Span },
+    BuildError { error: BuildError },
 }
 
 #[rustfmt::skip]
@@ -133,12 +185,20 @@ impl fmt::Display for ErrorKind {
                 => write!(f, "encoding error - {}", error),
             ErrorKind::RequestFormatError
                 => write!(f, "request format error - {:?}", self),
+            ErrorKind::ResponseFormatError
+                => write!(f, "response format error - {:?}", self),
+            ErrorKind::HandlerPanic
+                => write!(f, "handler panicked"),
+            ErrorKind::TlsError
+                => write!(f, "tls error"),
             ErrorKind::IOError { error }
                 => write!(f, "io error - {}", error),
-            ErrorKind::ScanError { error, line }
-                => write!(f, "scan error on line {} - {}", line, error),
-            ErrorKind::ParseError { error, line }
-                => write!(f, "parse error on line {} - {}", line, error),
+            ErrorKind::ScanError { error, span }
+                => write!(f, "scan error on line {} - {}", span.start.line, error),
+            ErrorKind::ParseError { error, span }
+                => write!(f, "parse error on line {} - {}", span.start.line, error),
+            ErrorKind::BuildError { error }
+                => write!(f, "build error - {}", error),
         }
     }
 }
@@ -146,50 +206,85 @@ impl fmt::Display for ErrorKind {
 #[derive(Debug)]
 pub struct FroggiError {
     error: ErrorKind,
-    msg: Option<String>,
+    msg: Vec<String>,
 }
 
 impl FroggiError {
     pub fn new(error: ErrorKind) -> FroggiError {
-        FroggiError { error, msg: None }
+        FroggiError {
+            error,
+            msg: Vec::new(),
+        }
     }
 
     pub fn kind(&self) -> &ErrorKind {
         &self.error
     }
 
-    pub fn scan(error: ScanError, line: usize) -> FroggiError {
+    /// The context added to the error as it was passed along, oldest first.
+    pub fn notes(&self) -> &[String] {
+        &self.msg
+    }
+
+    /// The line of markup the error is on, if it came from markup.
+    pub fn line(&self) -> Option<usize> {
+        self.span().map(|span| span.start.line)
+    }
+
+    /// The markup the error is about, if it came from markup.
+    pub fn span(&self) -> Option<Span> {
+        match self.error {
+            ErrorKind::ScanError { span, .. } | ErrorKind::ParseError { span, .. } => Some(span),
+            _ => None,
+        }
+    }
+
+    /// Move the error to another line, for markup that was put together from several
+    /// sources. The span keeps its length in lines, and its offsets still point into
+    /// the markup that was parsed.
+    pub fn set_line(&mut self, new_line: usize) {
+        match &mut self.error {
+            ErrorKind::ScanError { span, .. } | ErrorKind::ParseError { span, .. } => {
+                span.end.line = new_line + (span.end.line - span.start.line);
+                span.start.line = new_line;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn scan(error: ScanError, span: Span) -> FroggiError {
         FroggiError {
-            error: ErrorKind::ScanError { error, line },
-            msg: None,
+            error: ErrorKind::ScanError { error, span },
+            msg: Vec::new(),
         }
     }
 
     pub fn io(error: io::Error) -> FroggiError {
         FroggiError {
             error: ErrorKind::IOError { error },
-            msg: None,
+            msg: Vec::new(),
+        }
+    }
+
+    pub fn parse(error: ParseError, span: Span) -> FroggiError {
+        FroggiError {
+            error: ErrorKind::ParseError { error, span },
+            msg: Vec::new(),
         }
     }
 
-    pub fn parse(error: ParseError, line: usize) -> FroggiError {
+    pub fn build(error: BuildError) -> FroggiError {
         FroggiError {
-            error: ErrorKind::ParseError { error, line },
-            msg: None,
+            error: ErrorKind::BuildError { error },
+            msg: Vec::new(),
         }
     }
 }
 
 impl AddMsg for FroggiError {
     fn msg(mut self, msg: String) -> FroggiError {
-        match self.msg {
-            Some(ref mut message) => {
-                message.push_str(&format!(", {}", msg));
-            }
-            None => self.msg = Some(msg),
-        }
-
-        FroggiError { ..self }
+        self.msg.push(msg);
+        self
     }
 
     fn msg_str(self, msg: &str) -> FroggiError {
@@ -202,9 +297,13 @@ impl Error for FroggiError {
         match &self.error {
             ErrorKind::EncodingError { error } => error.source(),
             ErrorKind::RequestFormatError => None,
+            ErrorKind::ResponseFormatError => None,
+            ErrorKind::HandlerPanic => None,
+            ErrorKind::TlsError => None,
             ErrorKind::IOError { error } => error.source(),
             ErrorKind::ScanError { .. } => None,
             ErrorKind::ParseError { .. } => None,
+            ErrorKind::BuildError { .. } => None,
         }
     }
 }
@@ -215,9 +314,9 @@ impl fmt::Display for FroggiError {
             f,
             "{}{}{}{}",
             self.error,
-            if self.msg.is_some() { " (" } else { "" },
-            self.msg.clone().unwrap_or(String::new()),
-            if self.msg.is_some() { ")" } else { "" },
+            if self.msg.is_empty() { "" } else { " (" },
+            self.msg.join(", "),
+            if self.msg.is_empty() { "" } else { ")" },
         )
     }
 }
@@ -247,7 +346,7 @@ impl From<str::Utf8Error> for FroggiError {
     fn from(error: str::Utf8Error) -> FroggiError {
         FroggiError {
             error: ErrorKind::EncodingError { error },
-            msg: Some(String::from("could not decode text from utf8 to &str")),
+            msg: vec![String::from("could not decode text from utf8 to &str")],
         }
     }
 }
@@ -258,7 +357,7 @@ impl From<std::string::FromUtf8Error> for FroggiError {
             error: ErrorKind::EncodingError {
                 error: error.utf8_error(),
             },
-            msg: Some(String::from("could not decode text from utf8 to String")),
+            msg: vec![String::from("could not decode text from utf8 to String")],
         }
     }
 }
@@ -0,0 +1,667 @@
+//! Putting pages together in code, instead of with `format!`.
+//!
+//! ```
+//! use froggi::markup::builder::{ItemBuilder, PageBuilder, Selector, Style};
+//!
+//! let fml = PageBuilder::new()
+//!     .style(Selector::class("shout"), vec![Style::Bold, Style::Size(30)])
+//!     .item(ItemBuilder::text("say \"ribbit\"").style(Style::class("shout")))
+//!     .item(ItemBuilder::box_(vec![
+//!         ItemBuilder::link("frgi://example.com/pond.fml", "the pond"),
+//!         ItemBuilder::blob("frog.png", "a frog"),
+//!     ]))
+//!     .to_fml()
+//!     .unwrap();
+//!
+//! assert!(froggi::markup::parse::parse(&fml).is_ok());
+//! ```
+
+use super::parse;
+use super::scan::{escape, Scanner, TokenKind};
+use super::{OwnedPage, Page};
+use crate::{BuildError, FroggiError};
+
+/// A style for an item or a page style rule.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Style {
+    Mono,
+    Serif,
+    Sans,
+    Bold,
+    Italic,
+    Underline,
+    Strike,
+    Fg(u8, u8, u8),
+    Bg(u8, u8, u8),
+    Fill(u8),
+    Size(usize),
+    /// A style from the page style.
+    Class(String),
+}
+
+impl Style {
+    pub fn class(name: impl Into<String>) -> Style {
+        Style::Class(name.into())
+    }
+
+    fn to_fml(&self) -> String {
+        match self {
+            Style::Mono => String::from("mono"),
+            Style::Serif => String::from("serif"),
+            Style::Sans => String::from("sans"),
+            Style::Bold => String::from("bold"),
+            Style::Italic => String::from("italic"),
+            Style::Underline => String::from("underline"),
+            Style::Strike => String::from("strike"),
+            Style::Fg(r, g, b) => format!("(fg \"{:02x}{:02x}{:02x}\")", r, g, b),
+            Style::Bg(r, g, b) => format!("(bg \"{:02x}{:02x}{:02x}\")", r, g, b),
+            Style::Fill(fill) => format!("(fill \"{}\")", fill),
+            Style::Size(size) => format!("(size \"{}\")", size),
+            Style::Class(class) => class.clone(),
+        }
+    }
+}
+
+/// What a page style rule applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Selector {
+    /// Text, with or without the `text` builtin.
+    Text,
+    Box,
+    VBox,
+    Inline,
+    Link,
+    Blob,
+    /// Items with the style of this name.
+    Class(String),
+    /// Items that match the last selector, inside items that match each of the ones
+    /// before it.
+    Descendant(Vec<Selector>),
+}
+
+impl Selector {
+    pub fn class(name: impl Into<String>) -> Selector {
+        Selector::Class(name.into())
+    }
+
+    pub fn descendant(selectors: impl IntoIterator<Item = Selector>) -> Selector {
+        Selector::Descendant(selectors.into_iter().collect())
+    }
+
+    /// Every selector that isn't a descendant selector, outermost first.
+    fn parts(&self) -> Vec<&Selector> {
+        match self {
+            Selector::Descendant(selectors) => selectors.iter().flat_map(Selector::parts).collect(),
+            selector => vec![selector],
+        }
+    }
+
+    /// The selector an item has to match for the rule to apply to it.
+    fn subject(&self) -> Option<&Selector> {
+        self.parts().pop()
+    }
+
+    fn to_fml(&self) -> String {
+        let parts = self
+            .parts()
+            .into_iter()
+            .map(|part| match part {
+                Selector::Text => "text",
+                Selector::Box => "box",
+                Selector::VBox => "vbox",
+                Selector::Inline => "inline",
+                Selector::Link => "^",
+                Selector::Blob => "&",
+                Selector::Class(class) => class,
+                Selector::Descendant(_) => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        parts.join(" ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Content {
+    Text {
+        text: Vec<String>,
+    },
+    Children {
+        builtin: TokenKind,
+        children: Vec<ItemBuilder>,
+    },
+    Link {
+        link: String,
+        text: Vec<String>,
+    },
+    Blob {
+        name: String,
+        alt: Vec<String>,
+    },
+    Anchor {
+        anchor: String,
+    },
+}
+
+/// An item of a page. Text is given as it should appear, and is escaped when the page
+/// is turned into markup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemBuilder {
+    styles: Vec<Style>,
+    content: Content,
+}
+
+impl ItemBuilder {
+    fn new(content: Content) -> ItemBuilder {
+        ItemBuilder {
+            styles: Vec::new(),
+            content,
+        }
+    }
+
+    /// Some text, like `("text")`.
+    pub fn text(text: impl Into<String>) -> ItemBuilder {
+        ItemBuilder::new(Content::Text {
+            text: vec![text.into()],
+        })
+    }
+
+    /// A box, which lays its children out side by side.
+    pub fn box_(children: impl IntoIterator<Item = ItemBuilder>) -> ItemBuilder {
+        ItemBuilder::children(TokenKind::Box, children)
+    }
+
+    /// A vertical box, which lays its children out one above the other.
+    pub fn vbox(children: impl IntoIterator<Item = ItemBuilder>) -> ItemBuilder {
+        ItemBuilder::children(TokenKind::VBox, children)
+    }
+
+    /// An inline item, which lays its children out on one line.
+    pub fn inline(children: impl IntoIterator<Item = ItemBuilder>) -> ItemBuilder {
+        ItemBuilder::children(TokenKind::Inline, children)
+    }
+
+    fn children(
+        builtin: TokenKind,
+        children: impl IntoIterator<Item = ItemBuilder>,
+    ) -> ItemBuilder {
+        ItemBuilder::new(Content::Children {
+            builtin,
+            children: children.into_iter().collect(),
+        })
+    }
+
+    /// A link. Empty text leaves it out, and the link is shown instead.
+    pub fn link(link: impl Into<String>, text: impl Into<String>) -> ItemBuilder {
+        ItemBuilder::new(Content::Link {
+            link: link.into(),
+            text: non_empty(text.into()),
+        })
+    }
+
+    /// A blob, which is sent along with the page. Empty alt text leaves it out.
+    pub fn blob(name: impl Into<String>, alt: impl Into<String>) -> ItemBuilder {
+        ItemBuilder::new(Content::Blob {
+            name: name.into(),
+            alt: non_empty(alt.into()),
+        })
+    }
+
+    /// An anchor, which can be linked to with `#anchor`.
+    pub fn anchor(anchor: impl Into<String>) -> ItemBuilder {
+        ItemBuilder::new(Content::Anchor {
+            anchor: anchor.into(),
+        })
+    }
+
+    /// Add a style to the item. Anchors can't be styled, so their styles are left out
+    /// of the markup.
+    pub fn style(mut self, style: Style) -> ItemBuilder {
+        self.styles.push(style);
+        self
+    }
+
+    pub fn styles(mut self, styles: impl IntoIterator<Item = Style>) -> ItemBuilder {
+        self.styles.extend(styles);
+        self
+    }
+
+    /// Add a child to a box, vertical box or inline item.
+    ///
+    /// # Panics
+    ///
+    /// If the item can't have children.
+    pub fn child(mut self, child: ItemBuilder) -> ItemBuilder {
+        match &mut self.content {
+            Content::Children { children, .. } => children.push(child),
+            _ => panic!("only boxes and inline items have children"),
+        }
+        self
+    }
+
+    fn validate(&self, page: &PageBuilder) -> Result<(), FroggiError> {
+        if !matches!(self.content, Content::Anchor { .. }) {
+            for style in &self.styles {
+                if let Style::Class(class) = style {
+                    validate_class(class)?;
+                    let selector = Selector::Class(class.clone());
+                    if !page
+                        .styles
+                        .iter()
+                        .any(|(rule, _)| rule.parts().contains(&&selector))
+                    {
+                        return Err(FroggiError::build(BuildError::UnknownClass {
+                            class: class.clone(),
+                        }));
+                    }
+                }
+            }
+        }
+
+        if let Content::Children { children, .. } = &self.content {
+            for child in children {
+                child.validate(page)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn to_fml(&self) -> String {
+        let mut parts = Vec::new();
+        let strings = |strings: &[String]| {
+            strings
+                .iter()
+                .map(|string| quoted(string))
+                .collect::<Vec<_>>()
+        };
+
+        match &self.content {
+            Content::Text { .. } => {}
+            Content::Children { builtin, .. } => parts.push(String::from(match builtin {
+                TokenKind::Box => "box",
+                TokenKind::VBox => "vbox",
+                _ => "inline",
+            })),
+            Content::Link { link, .. } => parts.push(format!("^ {}", quoted(link))),
+            Content::Blob { name, .. } => parts.push(format!("& {}", quoted(name))),
+            Content::Anchor { anchor } => return format!("(# {})", quoted(anchor)),
+        }
+
+        if !self.styles.is_empty() {
+            let styles = self.styles.iter().map(Style::to_fml).collect::<Vec<_>>();
+            parts.push(format!("{{{}}}", styles.join(" ")));
+        }
+
+        match &self.content {
+            Content::Text { text }
+            | Content::Link { text, .. }
+            | Content::Blob { alt: text, .. } => parts.extend(strings(text)),
+            Content::Children { children, .. } => {
+                parts.extend(children.iter().map(ItemBuilder::to_fml))
+            }
+            Content::Anchor { .. } => {}
+        }
+
+        format!("({})", parts.join(" "))
+    }
+}
+
+/// A page, with its page style and items.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PageBuilder {
+    styles: Vec<(Selector, Vec<Style>)>,
+    items: Vec<ItemBuilder>,
+}
+
+impl PageBuilder {
+    pub fn new() -> PageBuilder {
+        PageBuilder::default()
+    }
+
+    /// Add a rule to the page style. A later rule for the same selector replaces the
+    /// earlier one, like it does in markup.
+    pub fn style(
+        mut self,
+        selector: Selector,
+        styles: impl IntoIterator<Item = Style>,
+    ) -> PageBuilder {
+        let styles = styles.into_iter().collect();
+        match self
+            .styles
+            .iter_mut()
+            .find(|(existing, _)| existing == &selector)
+        {
+            Some((_, existing)) => *existing = styles,
+            None => self.styles.push((selector, styles)),
+        }
+        self
+    }
+
+    pub fn item(mut self, item: ItemBuilder) -> PageBuilder {
+        self.items.push(item);
+        self
+    }
+
+    pub fn items(mut self, items: impl IntoIterator<Item = ItemBuilder>) -> PageBuilder {
+        self.items.extend(items);
+        self
+    }
+
+    /// Check that the page would parse: styles have to be in the page style before
+    /// they're used, names have to be identifiers that aren't built in, and selectors
+    /// can't be empty.
+    pub fn validate(&self) -> Result<(), FroggiError> {
+        for (selector, styles) in &self.styles {
+            if selector.subject().is_none() {
+                return Err(FroggiError::build(BuildError::EmptySelector));
+            }
+
+            for part in selector.parts() {
+                if let Selector::Class(class) = part {
+                    validate_class(class)?;
+                }
+            }
+
+            for style in styles {
+                if let Style::Class(class) = style {
+                    return Err(FroggiError::build(BuildError::ClassInPageStyle {
+                        class: class.clone(),
+                    }));
+                }
+            }
+        }
+
+        for item in &self.items {
+            item.validate(self)?;
+        }
+
+        Ok(())
+    }
+
+    /// The page as markup, with the page style first and each item on its own line.
+    pub fn to_fml(&self) -> Result<String, FroggiError> {
+        self.validate()?;
+
+        let mut fml = String::new();
+        if !self.styles.is_empty() {
+            let rules = self
+                .styles
+                .iter()
+                .map(|(selector, styles)| {
+                    let mut rule = format!("({}", selector.to_fml());
+                    for style in styles {
+                        rule.push(' ');
+                        rule.push_str(&style.to_fml());
+                    }
+                    rule.push(')');
+                    rule
+                })
+                .collect::<Vec<_>>();
+
+            fml.push('{');
+            fml.push_str(&rules.join("\n "));
+            fml.push_str("}\n");
+        }
+
+        for item in &self.items {
+            fml.push_str(&item.to_fml());
+            fml.push('\n');
+        }
+
+        Ok(fml)
+    }
+
+    /// The page, parsed from its markup.
+    pub fn build(&self) -> Result<OwnedPage, FroggiError> {
+        let fml = self.to_fml()?;
+        parse::parse(&fml)
+            .map(Page::into_owned)
+            .map_err(|mut errors| errors.remove(0))
+    }
+}
+
+fn non_empty(text: String) -> Vec<String> {
+    if text.is_empty() {
+        Vec::new()
+    } else {
+        vec![text]
+    }
+}
+
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", escape(text))
+}
+
+/// Check that a style's name scans as a single identifier.
+fn validate_class(class: &str) -> Result<(), FroggiError> {
+    match Scanner::new(class).next_token() {
+        Ok(token) if token.kind() == TokenKind::Identifier && token.lexeme() == class => Ok(()),
+        _ => Err(FroggiError::build(BuildError::InvalidClass {
+            class: class.into(),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markup::parse::parse;
+    use crate::markup::{InlineStyle, ItemPayload, PageItem};
+
+    fn same_styles(built: &[Style], parsed: &[InlineStyle]) -> bool {
+        built.len() == parsed.len()
+            && built
+                .iter()
+                .zip(parsed)
+                .all(|(built, parsed)| match (built, parsed) {
+                    (Style::Fg(r, g, b), InlineStyle::Fg { arg, .. })
+                    | (Style::Bg(r, g, b), InlineStyle::Bg { arg, .. }) => *arg == (*r, *g, *b),
+                    (Style::Fill(fill), InlineStyle::Fill { arg, .. }) => fill == arg,
+                    (Style::Size(size), InlineStyle::Size { arg, .. }) => size == arg,
+                    _ => built.to_fml() == parsed.token().lexeme(),
+                })
+    }
+
+    fn same_text(built: &[String], parsed: &[crate::markup::scan::Token]) -> bool {
+        built.len() == parsed.len()
+            && built
+                .iter()
+                .zip(parsed)
+                .all(|(built, parsed)| built == &parsed.decoded())
+    }
+
+    fn same_item(built: &ItemBuilder, parsed: &PageItem) -> bool {
+        let styles = match &built.content {
+            Content::Anchor { .. } => parsed.styles.is_empty(),
+            _ => same_styles(&built.styles, &parsed.styles),
+        };
+
+        styles
+            && match (&built.content, &parsed.payload) {
+                (Content::Text { text }, ItemPayload::Text { text: parsed_text }) => {
+                    parsed.builtin.kind() == TokenKind::ImplicitText && same_text(text, parsed_text)
+                }
+                (
+                    Content::Children { builtin, children },
+                    ItemPayload::Children {
+                        children: parsed_children,
+                        ..
+                    },
+                ) => {
+                    *builtin == parsed.builtin.kind()
+                        && children.len() == parsed_children.len()
+                        && children
+                            .iter()
+                            .zip(parsed_children)
+                            .all(|(built, parsed)| same_item(built, parsed))
+                }
+                (
+                    Content::Link { link, text },
+                    ItemPayload::Link {
+                        link: parsed_link,
+                        text: parsed_text,
+                    },
+                ) => link == &parsed_link.decoded() && same_text(text, parsed_text),
+                (
+                    Content::Blob { name, alt },
+                    ItemPayload::Blob {
+                        name: parsed_name,
+                        alt: parsed_alt,
+                    },
+                ) => name == &parsed_name.decoded() && same_text(alt, parsed_alt),
+                (Content::Anchor { anchor }, ItemPayload::Anchor { anchor: parsed }) => {
+                    anchor == &parsed.decoded()
+                }
+                _ => false,
+            }
+    }
+
+    #[test]
+    fn round_trip() {
+        let page = PageBuilder::new()
+            .style(
+                Selector::Text,
+                vec![Style::Serif, Style::Fg(0x30, 0x30, 0x30)],
+            )
+            .style(
+                Selector::class("quote-box"),
+                vec![Style::Bg(0xff, 0xf8, 0xdc)],
+            )
+            .style(Selector::Link, vec![Style::Underline])
+            .style(Selector::Inline, vec![Style::Italic])
+            .style(Selector::Blob, vec![Style::Size(20)])
+            .style(
+                Selector::descendant(vec![
+                    Selector::class("quote-box"),
+                    Selector::descendant(vec![Selector::VBox, Selector::Text]),
+                ]),
+                vec![Style::Strike],
+            )
+            .style(
+                Selector::descendant(vec![Selector::Box, Selector::class("aside")]),
+                vec![Style::Sans],
+            )
+            .item(
+                ItemBuilder::vbox(vec![
+                    ItemBuilder::text("say \"ribbit\" \\ (croak) ; {not a comment}")
+                        .style(Style::Bold),
+                    ItemBuilder::anchor("here"),
+                    ItemBuilder::text("sidebar").style(Style::class("aside")),
+                ])
+                .style(Style::class("quote-box"))
+                .child(ItemBuilder::inline(vec![
+                    ItemBuilder::text("frogs\nand toads")
+                        .styles(vec![Style::Size(14), Style::Fill(2)]),
+                    ItemBuilder::text("🐸"),
+                ])),
+            )
+            .item(ItemBuilder::link("frgi://example.com/a \"b\".fml", ""))
+            .item(ItemBuilder::link("#here", "back up"))
+            .item(ItemBuilder::blob("frog.png", "").style(Style::Mono))
+            .item(ItemBuilder::box_(Vec::new()));
+
+        let fml = page.to_fml().unwrap();
+        let parsed = parse(&fml).unwrap();
+
+        assert_eq!(parsed.items.len(), page.items.len());
+        for (built, parsed) in page.items.iter().zip(&parsed.items) {
+            assert!(same_item(built, parsed), "{:?} != {:?}", built, parsed);
+        }
+
+        assert_eq!(parsed.styles.len(), page.styles.len());
+        for (selector, styles) in &page.styles {
+            let (_, parsed_styles) = parsed
+                .styles
+                .iter()
+                .find(|(parsed, _)| {
+                    let lexemes = parsed.tokens.iter().map(|token| token.lexeme());
+                    lexemes.collect::<Vec<_>>().join(" ") == selector.to_fml()
+                })
+                .unwrap();
+            assert!(same_styles(styles, parsed_styles));
+        }
+    }
+
+    #[test]
+    fn build() {
+        let page = PageBuilder::new()
+            .item(ItemBuilder::text("a \"frog\""))
+            .build()
+            .unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].builtin.kind(), TokenKind::ImplicitText);
+        match &page.items[0].payload {
+            crate::markup::OwnedItemPayload::Text { text } => {
+                assert_eq!(text[0].decoded(), "a \"frog\"")
+            }
+            _ => panic!("not text"),
+        }
+    }
+
+    #[test]
+    fn replaced_rules() {
+        let fml = PageBuilder::new()
+            .style(Selector::class("a"), vec![Style::Bold])
+            .style(Selector::Box, vec![Style::Sans])
+            .style(Selector::class("a"), vec![Style::Italic])
+            .to_fml()
+            .unwrap();
+        assert_eq!(fml, "{(a italic)\n (box sans)}\n");
+    }
+
+    #[test]
+    fn invalid() {
+        let unknown = PageBuilder::new()
+            .item(ItemBuilder::text("a").style(Style::class("missing")))
+            .to_fml();
+        assert!(matches!(
+            unknown.unwrap_err().kind(),
+            crate::ErrorKind::BuildError {
+                error: BuildError::UnknownClass { .. }
+            }
+        ));
+
+        for class in &["", "two words", "bold", "box", "a(b", "\"a\""] {
+            let invalid = PageBuilder::new()
+                .style(Selector::class(*class), vec![Style::Bold])
+                .to_fml();
+            assert!(
+                matches!(
+                    invalid.unwrap_err().kind(),
+                    crate::ErrorKind::BuildError {
+                        error: BuildError::InvalidClass { .. }
+                    }
+                ),
+                "{:?}",
+                class
+            );
+        }
+
+        let empty = PageBuilder::new()
+            .style(Selector::descendant(Vec::new()), vec![Style::Bold])
+            .to_fml();
+        assert!(matches!(
+            empty.unwrap_err().kind(),
+            crate::ErrorKind::BuildError {
+                error: BuildError::EmptySelector
+            }
+        ));
+
+        let recursive = PageBuilder::new()
+            .style(Selector::class("a"), vec![Style::Bold])
+            .style(Selector::class("b"), vec![Style::class("a")])
+            .to_fml();
+        assert!(matches!(
+            recursive.unwrap_err().kind(),
+            crate::ErrorKind::BuildError {
+                error: BuildError::ClassInPageStyle { .. }
+            }
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn children_of_text() {
+        let _ = ItemBuilder::text("a").child(ItemBuilder::text("b"));
+    }
+}
@@ -0,0 +1,359 @@
+//! Errors in markup, described the way a compiler would: with the markup they're
+//! about underlined, and a hint at how to fix them when there's an obvious one.
+
+use super::scan::{Position, Scanner, Span, TokenKind};
+use crate::{ErrorKind, FroggiError, ParseError, ScanError};
+
+const BUILTIN_STYLES: &[&str] = &[
+    "mono",
+    "serif",
+    "sans",
+    "bold",
+    "italic",
+    "underline",
+    "strike",
+    "fg",
+    "bg",
+    "fill",
+    "size",
+];
+
+/// An error in some markup, ready to be shown to whoever wrote it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+    /// The context the error picked up on its way out of the parser.
+    pub notes: Vec<String>,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    /// Describe an error from parsing `source`.
+    pub fn new(error: &FroggiError, source: &str) -> Diagnostic {
+        let message = match error.kind() {
+            ErrorKind::ScanError { error, .. } => error.to_string(),
+            ErrorKind::ParseError { error, .. } => error.to_string(),
+            kind => kind.to_string(),
+        };
+
+        Diagnostic {
+            message,
+            // spans of tokens that were never scanned only know their line
+            span: error.span().filter(|span| span.start.column != 0),
+            notes: error.notes().to_vec(),
+            hint: hint(error, source),
+        }
+    }
+
+    pub fn all(errors: &[FroggiError], source: &str) -> Vec<Diagnostic> {
+        errors
+            .iter()
+            .map(|error| Diagnostic::new(error, source))
+            .collect()
+    }
+
+    /// Render the diagnostic with the line of `source` it's about, which came from
+    /// `path`.
+    ///
+    /// ```text
+    /// error: unknown style "quote-txt"
+    ///  --> page.fml:2:3
+    ///   |
+    /// 2 | ({quote-txt} "frogs")
+    ///   |   ^^^^^^^^^
+    ///   = help: did you mean `quote-text`?
+    /// ```
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut rendered = format!("error: {}\n", self.message);
+
+        let gutter = match self.span {
+            Some(span) => {
+                let number = span.start.line.to_string();
+                let gutter = " ".repeat(number.len());
+
+                // tabs are replaced so that the carets line up with the characters
+                let line = source
+                    .lines()
+                    .nth(span.start.line - 1)
+                    .unwrap_or_default()
+                    .replace('\t', " ");
+
+                let length = line.chars().count() + 1;
+                let end = if span.end.line == span.start.line {
+                    span.end.column.min(length)
+                } else {
+                    length
+                };
+                let carets = "^".repeat(end.saturating_sub(span.start.column).max(1));
+
+                rendered.push_str(&format!(
+                    "{gutter}--> {}:{}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}\n",
+                    path,
+                    span.start.line,
+                    span.start.column,
+                    number,
+                    line.trim_end(),
+                    " ".repeat(span.start.column - 1),
+                    carets,
+                    gutter = gutter,
+                ));
+
+                gutter
+            }
+
+            None => {
+                rendered.push_str(&format!(" --> {}\n", path));
+                String::new()
+            }
+        };
+
+        for note in &self.notes {
+            rendered.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+
+        if let Some(hint) = &self.hint {
+            rendered.push_str(&format!("{} = help: {}\n", gutter, hint));
+        }
+
+        rendered
+    }
+
+    /// The diagnostic as a JSON object, for editors.
+    pub fn to_json(&self, path: &str) -> String {
+        let position = |position: &Position| {
+            format!(
+                r#"{{"offset":{},"line":{},"column":{}}}"#,
+                position.offset, position.line, position.column
+            )
+        };
+
+        let span = match &self.span {
+            Some(span) => format!(
+                r#"{{"start":{},"end":{}}}"#,
+                position(&span.start),
+                position(&span.end)
+            ),
+            None => String::from("null"),
+        };
+
+        let notes = self
+            .notes
+            .iter()
+            .map(|note| json_string(note))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let hint = match &self.hint {
+            Some(hint) => json_string(hint),
+            None => String::from("null"),
+        };
+
+        format!(
+            r#"{{"file":{},"severity":"error","message":{},"span":{},"notes":[{}],"hint":{}}}"#,
+            json_string(path),
+            json_string(&self.message),
+            span,
+            notes,
+            hint
+        )
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn hint(error: &FroggiError, source: &str) -> Option<String> {
+    match error.kind() {
+        ErrorKind::ParseError { error, .. } => match error {
+            ParseError::UnknownStyle { style } => {
+                let names = page_style_names(source);
+                let candidates = names
+                    .iter()
+                    .map(String::as_str)
+                    .chain(BUILTIN_STYLES.iter().copied());
+
+                Some(match closest(style, candidates) {
+                    Some(name) => format!("did you mean `{}`?", name),
+                    None => format!("add it to the page style, like {{({} italic)}}", style),
+                })
+            }
+
+            ParseError::RecursiveStyle { .. } => {
+                Some(String::from("page styles can only use built-in styles"))
+            }
+
+            ParseError::ExpectedItem { got } if got == "{" => {
+                Some(String::from("the page style has to come before every item"))
+            }
+
+            ParseError::ExpectedItem { .. } => Some(String::from(
+                "items are surrounded by parentheses, like (\"text\")",
+            )),
+
+            ParseError::UnexpectedToken {
+                expected: TokenKind::String,
+                ..
+            } => Some(String::from("text goes in double quotes, like \"text\"")),
+
+            ParseError::UnbalancedParentheses => {
+                Some(String::from("this `)` doesn't close anything"))
+            }
+
+            _ => None,
+        },
+
+        ErrorKind::ScanError { error, .. } => match error {
+            ScanError::UnknownEscapeCode { .. } => {
+                Some(String::from("a backslash on its own is written `\\\\`"))
+            }
+
+            ScanError::UnterminatedString { .. } => Some(String::from("strings end with a `\"`")),
+
+            ScanError::InvalidCodepoint { .. } => None,
+        },
+
+        _ => None,
+    }
+}
+
+/// The user-defined styles in the page style of some markup, as far as it scans.
+fn page_style_names(source: &str) -> Vec<String> {
+    let mut scanner = Scanner::new(source);
+    let mut names = Vec::new();
+
+    if !matches!(scanner.next_token(), Ok(token) if token.kind() == TokenKind::LeftBrace) {
+        return names;
+    }
+
+    let mut in_selector = false;
+    while let Ok(token) = scanner.next_token() {
+        match token.kind() {
+            TokenKind::RightBrace | TokenKind::End => break,
+            TokenKind::Identifier
+                if in_selector && !names.iter().any(|name| name == token.lexeme()) =>
+            {
+                names.push(token.clone_lexeme())
+            }
+            _ => {}
+        }
+        in_selector = token.kind() == TokenKind::LeftParen
+            || in_selector && super::parse::is_selector(token.kind());
+    }
+
+    names
+}
+
+/// The candidate closest to `name`, if any are close enough to be a typo.
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let most = (name.chars().count() / 3).max(1);
+    candidates
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= most)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The edit distance between two strings.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == *b { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markup::parse::parse;
+
+    #[test]
+    fn render() {
+        let source = "{(quote-text italic)}\n(\"frogs\")\n\t({quote-txt} \"more frogs\")\n";
+        let errors = parse(source).unwrap_err();
+        let diagnostic = Diagnostic::new(&errors[0], source);
+        assert_eq!(
+            diagnostic.hint.as_deref(),
+            Some("did you mean `quote-text`?")
+        );
+        assert_eq!(
+            diagnostic.render("page.fml", source),
+            concat!(
+                "error: unknown style \"quote-txt\"\n",
+                " --> page.fml:3:4\n",
+                "  |\n",
+                "3 |  ({quote-txt} \"more frogs\")\n",
+                "  |    ^^^^^^^^^\n",
+                "  = help: did you mean `quote-text`?\n",
+            )
+        );
+
+        let source = "(box {(fg)})";
+        let errors = parse(source).unwrap_err();
+        let rendered = Diagnostic::new(&errors[0], source).render("page.fml", source);
+        assert!(rendered.contains("= note: expected an argument for the style fg\n"));
+        assert!(rendered.contains("= help: text goes in double quotes"));
+
+        let source = "(\"unterminated\n\n";
+        let errors = parse(source).unwrap_err();
+        let rendered = Diagnostic::new(&errors[0], source).render("page.fml", source);
+        assert!(rendered.contains("1 | (\"unterminated\n  |  ^^^^^^^^^^^^^\n"));
+    }
+
+    #[test]
+    fn hints() {
+        assert_eq!(
+            closest("italc", BUILTIN_STYLES.iter().copied()),
+            Some("italic")
+        );
+        assert_eq!(closest("frog", BUILTIN_STYLES.iter().copied()), None);
+        assert_eq!(distance("kitten", "sitting"), 3);
+
+        let source = "{(footnote underline) (aside (fg \"303030\"))}(\"a\")";
+        assert_eq!(page_style_names(source), vec!["footnote", "aside"]);
+
+        let source = "{(quote-box text aside italic) (aside bold)}";
+        assert_eq!(page_style_names(source), vec!["quote-box", "aside"]);
+    }
+
+    #[test]
+    fn json() {
+        let source = "(\"a\\q\")";
+        let errors = parse(source).unwrap_err();
+        let json = Diagnostic::new(&errors[0], source).to_json("dir/\"quoted\".fml");
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"file":"dir/\"quoted\".fml","severity":"error","#,
+                r#""message":"unknown escape code: q","#,
+                r#""span":{"start":{"offset":3,"line":1,"column":4},"#,
+                r#""end":{"offset":5,"line":1,"column":6}},"#,
+                r#""notes":[],"hint":"a backslash on its own is written `\\\\`"}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,431 @@
+//! Printing pages back out as markup, in one canonical layout.
+//!
+//! Items that fit on a line are kept on one. Longer ones are broken up with their
+//! children, or the strings after the first, on their own lines one space further in,
+//! and the closing parenthesis after the last of them. Styles keep the order they were
+//! written in, since later ones win over earlier ones. Strings are printed as they were
+//! written, so neither their escapes nor how they were split up changes.
+
+use super::parse;
+use super::scan::{Scanner, Token, TokenKind};
+use super::{InlineStyle, ItemPayload, Page, PageItem, Selector};
+use crate::FroggiError;
+
+/// The line width that formatted markup tries to stay within.
+pub const WIDTH: usize = 80;
+
+/// Format some markup, keeping its comments and the blank lines between its top-level
+/// items.
+pub fn format(source: &str, width: usize) -> Result<String, Vec<FroggiError>> {
+    let page = parse::parse(source)?;
+    let mut printer = Printer::new(source, width);
+    printer.page(&page);
+    Ok(printer.finish())
+}
+
+/// Print a page as markup. Comments aren't part of a page, so there are none.
+pub fn to_source(page: &Page, width: usize) -> String {
+    let mut printer = Printer::new("", width);
+    printer.page(page);
+    printer.finish()
+}
+
+/// A `;` comment, up to the end of its line.
+struct Comment {
+    start: usize,
+    end: usize,
+    /// Whether it's on the same line as the token before it.
+    trailing: bool,
+}
+
+/// The comments in some markup, and where its page style starts and ends.
+fn comments(source: &str) -> (Vec<Comment>, Option<(usize, usize)>) {
+    let mut comments = Vec::new();
+    let mut style_block = None;
+
+    let mut scanner = Scanner::new(source);
+    let mut previous_end = None;
+    while let Ok(token) = scanner.next_token() {
+        let span = token.span();
+        let gap_start = previous_end.unwrap_or(0);
+        let gap = &source[gap_start..span.start.offset];
+
+        let mut i = 0;
+        while let Some(semicolon) = gap[i..].find(';') {
+            let start = i + semicolon;
+            let end = gap[start..].find('\n').map_or(gap.len(), |end| start + end);
+            comments.push(Comment {
+                start: gap_start + start,
+                end: gap_start + end,
+                trailing: previous_end.is_some() && !gap[..start].contains('\n'),
+            });
+            i = end;
+        }
+
+        match token.kind() {
+            TokenKind::End => break,
+            TokenKind::LeftBrace if previous_end.is_none() => {
+                style_block = Some((span.start.offset, source.len()));
+            }
+            TokenKind::RightBrace => {
+                if let Some((start, end)) = style_block {
+                    if end == source.len() {
+                        style_block = Some((start, span.end.offset));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        previous_end = Some(span.end.offset);
+    }
+
+    (comments, style_block)
+}
+
+struct Printer<'s> {
+    source: &'s str,
+    width: usize,
+    comments: Vec<Comment>,
+    /// The first comment that hasn't been printed yet.
+    next_comment: usize,
+    style_block: Option<(usize, usize)>,
+    lines: Vec<String>,
+    /// Whether the last line ends in a comment, so nothing can go after it.
+    commented: bool,
+    /// Where the last thing printed at the top level ended in the source.
+    last_end: usize,
+}
+
+impl<'s> Printer<'s> {
+    fn new(source: &'s str, width: usize) -> Printer<'s> {
+        let (comments, style_block) = comments(source);
+        Printer {
+            source,
+            width,
+            comments,
+            next_comment: 0,
+            style_block,
+            lines: Vec::new(),
+            commented: false,
+            last_end: 0,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.comments_before(self.source.len() + 1, 0, true);
+
+        let mut formatted = self.lines.join("\n");
+        formatted.push('\n');
+        formatted
+    }
+
+    fn page(&mut self, page: &Page) {
+        let mut rules = page.styles.iter().collect::<Vec<_>>();
+        rules.sort_by_key(|(selector, _)| (selector.span().start.offset, joined(selector)));
+
+        if !rules.is_empty() {
+            let (start, end) = self.style_block.unwrap_or_default();
+            self.comments_before(start, 0, true);
+            self.blank_line_before(start);
+            self.line(0, "{");
+
+            for (i, (selector, styles)) in rules.into_iter().enumerate() {
+                self.comments_before(selector.span().start.offset, 1, false);
+                let rule = rule(selector, styles);
+                self.put(1, "", &rule, i == 0);
+            }
+
+            self.close(0, "}");
+            self.last_end = end;
+        }
+
+        for item in &page.items {
+            self.comments_before(item.span.start.offset, 0, true);
+            self.blank_line_before(item.span.start.offset);
+            self.item(item, 0);
+            self.last_end = item.span.end.offset;
+        }
+    }
+
+    fn item(&mut self, item: &PageItem, indent: usize) {
+        let flat = flat(item);
+        if indent + flat.chars().count() <= self.width && !self.has_comments(item) {
+            self.line(indent, &flat);
+            return;
+        }
+
+        let head = head(item);
+        let header = format!("({}", head.join(" "));
+        let separator = if head.is_empty() { "" } else { " " };
+        self.line(indent, &header);
+
+        match &item.payload {
+            ItemPayload::Text { text }
+            | ItemPayload::Link { text, .. }
+            | ItemPayload::Blob { alt: text, .. } => {
+                for (i, string) in text.iter().enumerate() {
+                    self.comments_before(string.span().start.offset, indent + 1, false);
+                    self.put(indent + 1, separator, &quoted(string), i == 0);
+                }
+            }
+
+            ItemPayload::Children { children, .. } => {
+                for child in children {
+                    self.comments_before(child.span.start.offset, indent + 1, false);
+                    self.item(child, indent + 1);
+                }
+            }
+
+            ItemPayload::Anchor { .. } | ItemPayload::Error => {}
+        }
+
+        self.close(indent, ")");
+    }
+
+    fn line(&mut self, indent: usize, text: &str) {
+        self.lines.push(format!("{}{}", " ".repeat(indent), text));
+        self.commented = false;
+    }
+
+    /// Put `text` after the last line if it can go there, or on a new line.
+    fn put(&mut self, indent: usize, separator: &str, text: &str, same_line: bool) {
+        match self.lines.last_mut() {
+            Some(last) if same_line && !self.commented => {
+                last.push_str(separator);
+                last.push_str(text);
+            }
+            _ => self.line(indent, text),
+        }
+    }
+
+    fn close(&mut self, indent: usize, text: &str) {
+        self.put(indent, "", text, true);
+    }
+
+    /// Print the comments that come before `offset`. Comments that were on the same
+    /// line as the token before them stay at the end of the line.
+    fn comments_before(&mut self, offset: usize, indent: usize, top_level: bool) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.start >= offset {
+                break;
+            }
+
+            let (start, end, trailing) = (comment.start, comment.end, comment.trailing);
+            let text = self.source[start..end].trim_end().to_string();
+            match self.lines.last_mut() {
+                Some(last) if trailing && !self.commented => {
+                    last.push(' ');
+                    last.push_str(&text);
+                }
+                _ => {
+                    if top_level {
+                        self.blank_line_before(start);
+                    }
+                    self.line(indent, &text);
+                }
+            }
+
+            self.commented = true;
+            self.next_comment += 1;
+            if top_level {
+                self.last_end = end;
+            }
+        }
+    }
+
+    /// Keep a blank line from the source between top-level items and comments.
+    fn blank_line_before(&mut self, offset: usize) {
+        let blank = self
+            .source
+            .get(self.last_end..offset)
+            .is_some_and(|gap| gap.matches('\n').count() >= 2);
+
+        if blank && !self.lines.is_empty() {
+            self.lines.push(String::new());
+            self.commented = false;
+        }
+    }
+
+    fn has_comments(&self, item: &PageItem) -> bool {
+        let range = item.span.range();
+        self.comments[self.next_comment..]
+            .iter()
+            .take_while(|comment| comment.start < range.end)
+            .any(|comment| comment.start >= range.start)
+    }
+}
+
+/// An item on one line.
+fn flat(item: &PageItem) -> String {
+    let mut parts = head(item);
+    match &item.payload {
+        ItemPayload::Text { text }
+        | ItemPayload::Link { text, .. }
+        | ItemPayload::Blob { alt: text, .. } => parts.extend(text.iter().map(quoted)),
+        ItemPayload::Children { children, .. } => parts.extend(children.iter().map(flat)),
+        ItemPayload::Anchor { .. } | ItemPayload::Error => {}
+    }
+
+    format!("({})", parts.join(" "))
+}
+
+/// Everything in an item that comes before its text or children.
+fn head(item: &PageItem) -> Vec<String> {
+    let mut head = Vec::new();
+    if item.builtin.kind() != TokenKind::ImplicitText {
+        head.push(item.builtin.lexeme().to_string());
+    }
+
+    match &item.payload {
+        ItemPayload::Link { link: string, .. }
+        | ItemPayload::Blob { name: string, .. }
+        | ItemPayload::Anchor { anchor: string } => head.push(quoted(string)),
+        _ => {}
+    }
+
+    if !item.styles.is_empty() {
+        let styles = item.styles.iter().map(style).collect::<Vec<_>>();
+        head.push(format!("{{{}}}", styles.join(" ")));
+    }
+
+    head
+}
+
+/// A rule in the page style.
+fn rule(selector: &Selector, styles: &[InlineStyle]) -> String {
+    let mut rule = format!("({}", joined(selector));
+    for inline_style in styles {
+        rule.push(' ');
+        rule.push_str(&style(inline_style));
+    }
+    rule.push(')');
+    rule
+}
+
+fn joined(selector: &Selector) -> String {
+    let tokens = selector
+        .tokens
+        .iter()
+        .map(|token| token.lexeme())
+        .collect::<Vec<_>>();
+    tokens.join(" ")
+}
+
+fn style(inline_style: &InlineStyle) -> String {
+    let name = inline_style.token().lexeme().to_string();
+    match inline_style {
+        InlineStyle::Fg { arg, .. } | InlineStyle::Bg { arg, .. } => {
+            format!("({} \"{:02x}{:02x}{:02x}\")", name, arg.0, arg.1, arg.2)
+        }
+        InlineStyle::Fill { arg, .. } => format!("({} \"{}\")", name, arg),
+        InlineStyle::Size { arg, .. } => format!("({} \"{}\")", name, arg),
+        _ => name,
+    }
+}
+
+fn quoted(string: &Token) -> String {
+    format!("\"{}\"", string.lexeme())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markup::style;
+
+    #[test]
+    fn layout() {
+        let source = r#"; the page style
+{(quote-text sans (fg "606060"))   (text (fg "303030") serif)}
+
+
+(box ({(size "26") bold}"Lorem ipsum example") (& "red_toy_small.png" {} "alt"))
+   (# "anchor") ; an anchor
+(box {(fill "2") quote-text}
+      ; a child
+  ({quote-text} "Contrary to popular belief, Lorem Ipsum is not simply"
+                " random text. It has roots in a piece of classical Latin literature")
+  (vbox ("one") ("two \"2\"")))
+; the end
+"#;
+
+        let expected = r#"; the page style
+{(quote-text sans (fg "606060"))
+ (text (fg "303030") serif)}
+
+(box ({(size "26") bold} "Lorem ipsum example") (& "red_toy_small.png" "alt"))
+(# "anchor") ; an anchor
+(box {(fill "2") quote-text}
+ ; a child
+ ({quote-text} "Contrary to popular belief, Lorem Ipsum is not simply"
+  " random text. It has roots in a piece of classical Latin literature")
+ (vbox ("one") ("two \"2\"")))
+; the end
+"#;
+
+        let formatted = format(source, WIDTH).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted, WIDTH).unwrap(), formatted);
+    }
+
+    #[test]
+    fn comments_inside_items() {
+        let source = "(\"a\" ; first\n \"b\") (box (\"c\")\n; last child\n(\"d\"))";
+        let expected = "(\"a\" ; first\n \"b\")\n(box\n (\"c\")\n ; last child\n (\"d\"))\n";
+        assert_eq!(format(source, WIDTH).unwrap(), expected);
+    }
+
+    #[test]
+    fn pages_are_unchanged() {
+        let pages = [
+            include_str!("../../../server/pages/index.fml"),
+            include_str!("../../../server/pages/test_markup.fml"),
+            include_str!("../../../server/pages/new_test_markup.fml"),
+            include_str!("../../../server/pages/smile.fml"),
+            include_str!("../../../server/pages/long.fml"),
+        ];
+
+        for source in pages.iter() {
+            let formatted = format(source, WIDTH).unwrap();
+            assert_eq!(format(&formatted, WIDTH).unwrap(), formatted);
+
+            // the same page, just laid out differently
+            let original = parse::parse(source).unwrap();
+            let reformatted = parse::parse(&formatted).unwrap();
+            assert_eq!(to_source(&original, WIDTH), to_source(&reformatted, WIDTH));
+            assert_eq!(computed(&original), computed(&reformatted));
+
+            let comments = |source: &str| source.matches(';').count();
+            assert_eq!(comments(source), comments(&formatted));
+        }
+    }
+
+    #[test]
+    fn style_order_is_kept() {
+        let source = r#"{(a sans) (blue (fg "0000ff"))} ({mono a} "y") ({(fg "ff0000") blue} "z")"#;
+        let expected = r#"{(a sans)
+ (blue (fg "0000ff"))}
+({mono a} "y")
+({(fg "ff0000") blue} "z")
+"#;
+
+        let formatted = format(source, WIDTH).unwrap();
+        assert_eq!(formatted, expected);
+
+        let original = parse::parse(source).unwrap();
+        let reformatted = parse::parse(&formatted).unwrap();
+        assert_eq!(computed(&original), computed(&reformatted));
+    }
+
+    fn computed(page: &Page) -> Vec<(Vec<usize>, style::ComputedStyle)> {
+        style::compute(page)
+            .into_iter()
+            .map(|(entry, style)| (entry.path, style))
+            .collect()
+    }
+
+    #[test]
+    fn errors() {
+        assert!(format("(box", WIDTH).is_err());
+    }
+}
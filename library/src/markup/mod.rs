@@ -1,9 +1,20 @@
+pub mod builder;
+pub mod diagnostic;
+pub mod format;
+pub mod owned;
 pub mod parse;
 pub mod scan;
+pub mod style;
+pub mod visit;
 
+pub use owned::{
+    OwnedInlineStyle, OwnedItemPayload, OwnedPage, OwnedPageItem, OwnedPageStyles, OwnedSelector,
+};
+pub use scan::{escape, unescape, OwnedToken, Position, Span};
 use scan::{Token, TokenKind};
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, PartialEq)]
 pub struct Page<'a> {
@@ -11,10 +22,36 @@ pub struct Page<'a> {
     pub items: Vec<PageItem<'a>>,
 }
 
-pub type PageStyles<'a> = HashMap<Token<'a>, Vec<InlineStyle<'a>>>;
+pub type PageStyles<'a> = HashMap<Selector<'a>, Vec<InlineStyle<'a>>>;
+
+/// What a page style rule applies to. A selector like `(quote-box text ...)` applies to
+/// items that match its last token, inside items that match each of the ones before it.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Selector<'a> {
+    pub tokens: Vec<Token<'a>>,
+}
+
+impl<'a> Selector<'a> {
+    /// The token an item has to match for the rule to apply to it.
+    pub fn subject(&self) -> Token<'a> {
+        self.tokens[self.tokens.len() - 1]
+    }
+
+    /// The tokens an item's ancestors have to match, outermost first.
+    pub fn context(&self) -> &[Token<'a>] {
+        &self.tokens[..self.tokens.len() - 1]
+    }
+
+    /// Where the selector is in the markup.
+    pub fn span(&self) -> Span {
+        self.tokens[0].span().to(self.subject().span())
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct PageItem<'a> {
+    /// From the item's opening parenthesis to its closing one.
+    pub span: Span,
     pub builtin: Token<'a>,
     pub styles: Vec<InlineStyle<'a>>,
     pub payload: ItemPayload<'a>,
@@ -40,6 +77,8 @@ pub enum ItemPayload<'a> {
     Anchor {
         anchor: Token<'a>,
     },
+    /// An item that couldn't be parsed, in a page from [`parse::parse_partial`].
+    Error,
 }
 
 #[derive(Debug, PartialEq)]
@@ -58,6 +97,54 @@ pub enum InlineStyle<'a> {
     UserDefined { token: Token<'a> },
 }
 
+impl<'a> InlineStyle<'a> {
+    /// The style's name.
+    pub fn token(&self) -> Token<'a> {
+        match self {
+            InlineStyle::Mono { token }
+            | InlineStyle::Serif { token }
+            | InlineStyle::Sans { token }
+            | InlineStyle::Bold { token }
+            | InlineStyle::Italic { token }
+            | InlineStyle::Underline { token }
+            | InlineStyle::Strike { token }
+            | InlineStyle::Fg { token, .. }
+            | InlineStyle::Bg { token, .. }
+            | InlineStyle::Fill { token, .. }
+            | InlineStyle::Size { token, .. }
+            | InlineStyle::UserDefined { token } => *token,
+        }
+    }
+
+    /// Where the style's name is in the markup.
+    pub fn span(&self) -> Span {
+        self.token().span()
+    }
+}
+
+/// Every .fml file under `path`, in order, or `path` itself if it's a file.
+pub fn page_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.into());
+        return Ok(());
+    }
+
+    let mut entries = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            page_files(&entry, files)?;
+        } else if entry.extension().map(|ext| ext == "fml").unwrap_or(false) {
+            files.push(entry);
+        }
+    }
+
+    Ok(())
+}
+
 pub fn to_html(page: &Page) -> String {
     let mut html = String::from(
         r#"
@@ -82,34 +169,27 @@ body {
 "#,
     );
 
-    for (selector, page_styles) in &page.styles {
-        match selector.kind() {
-            TokenKind::Identifier => {
-                html.push_str(&format!(".{} {{\n", selector.lexeme()));
-            }
-
-            TokenKind::Blob => {
-                html.push_str("img {\n");
-            }
-
-            TokenKind::Link => {
-                html.push_str("a {\n");
-            }
-
-            TokenKind::Anchor => {
-                // n/a
-            }
-
-            TokenKind::Box | TokenKind::VBox => {
-                html.push_str("div {\n");
-            }
-
-            TokenKind::Text | TokenKind::ImplicitText | TokenKind::Inline => {
-                html.push_str("span {\n");
-            }
-
-            _ => unreachable!(),
-        }
+    // in the order they apply, as in style::item_style: rules for builtins before rules
+    // for user-defined styles, then shorter selectors first, then in written order
+    let mut rules = page.styles.iter().collect::<Vec<_>>();
+    rules.sort_by_key(|(selector, _)| {
+        (
+            selector.subject().kind() == TokenKind::Identifier,
+            selector.tokens.len(),
+            selector.span().start.offset,
+        )
+    });
+
+    for (selector, page_styles) in rules {
+        let css = selector
+            .tokens
+            .iter()
+            .map(|token| match token.kind() {
+                TokenKind::Identifier => format!(".{}", token.lexeme()),
+                kind => format!(".{}", builtin_class(kind)),
+            })
+            .collect::<Vec<_>>();
+        html.push_str(&format!("{} {{\n", css.join(" ")));
 
         for inline_style in page_styles {
             html.push_str(&format!("    {}\n", inline_style_to_html(inline_style)));
@@ -143,15 +223,9 @@ fn page_item_to_html(item: &PageItem, child_of_inline: bool) -> String {
     match &item.payload {
         ItemPayload::Text { text } => {
             html.push_str("<span");
-
-            if !item.styles.is_empty() {
-                html.push_str(&style_list_to_html(item, not_flex_column));
-            }
-
+            html.push_str(&style_list_to_html(item, not_flex_column));
             html.push_str(">");
-            html.push_str(&text.iter().fold(String::new(), |acc, next| {
-                format!("{}{}", acc, next.lexeme())
-            }));
+            html.push_str(&html_text(text));
 
             html.push_str(&format!(
                 "</span>{} <!-- text {} -->\n",
@@ -192,33 +266,25 @@ fn page_item_to_html(item: &PageItem, child_of_inline: bool) -> String {
 
         ItemPayload::Link { link, text } => {
             html.push_str("<div");
-
-            if !item.styles.is_empty() {
-                html.push_str(&style_list_to_html(item, not_flex_column));
-            }
-
+            html.push_str(&style_list_to_html(item, not_flex_column));
             html.push_str(">");
-            html.push_str(&format!("<a href=\"{}\">", link.lexeme()));
+            html.push_str(&format!("<a href=\"{}\">", html_escape(&link.decoded())));
             if !text.is_empty() {
-                html.push_str(&text.iter().fold(String::new(), |acc, next| {
-                    format!("{}{}", acc, next.lexeme())
-                }));
+                html.push_str(&html_text(text));
             } else {
-                html.push_str(link.lexeme());
+                html.push_str(&html_escape(&link.decoded()));
             }
 
             html.push_str("</a></div>\n");
         }
 
         ItemPayload::Blob { name, alt } => {
-            // TODO: style
             // <embed>? image type?
-            html.push_str(&format!("<img src=\"{}\"", name.lexeme()));
+            html.push_str(&format!("<img src=\"{}\"", html_escape(&name.decoded())));
+            html.push_str(&style_list_to_html(item, not_flex_column));
             if !alt.is_empty() {
                 html.push_str(" alt=\"");
-                html.push_str(&alt.iter().fold(String::new(), |acc, next| {
-                    format!("{}{}", acc, next.lexeme())
-                }));
+                html.push_str(&html_text(alt));
                 html.push_str("\"");
             }
             html.push_str(">\n");
@@ -226,18 +292,51 @@ fn page_item_to_html(item: &PageItem, child_of_inline: bool) -> String {
 
         ItemPayload::Anchor { anchor } => {
             html.push_str(&format!(
-                "<div id=\"{}\" style=\"display:hidden;\"></div>\n",
-                anchor.lexeme()
+                "<div id=\"{}\" class=\"{}\" style=\"display:hidden;\"></div>\n",
+                html_escape(&anchor.decoded()),
+                builtin_class(item.builtin.kind()),
             ));
         }
+
+        ItemPayload::Error => {
+            html.push_str(&format!("<!-- error {} -->\n", item.builtin.line()));
+        }
     }
 
     html
 }
 
+/// The decoded text of some strings, ready to go in HTML.
+fn html_text(text: &[Token]) -> String {
+    let text = text.iter().map(|token| token.decoded()).collect::<String>();
+    html_escape(&text)
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The class of the elements an item built with `kind` turns into, so page style rules
+/// for a builtin only select items made with it.
+fn builtin_class(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Text | TokenKind::ImplicitText => "fml-text",
+        TokenKind::Inline => "fml-inline",
+        TokenKind::Box => "fml-box",
+        TokenKind::VBox => "fml-vbox",
+        TokenKind::Link => "fml-link",
+        TokenKind::Blob => "fml-blob",
+        TokenKind::Anchor => "fml-anchor",
+        _ => unreachable!(),
+    }
+}
+
 fn style_list_to_html(item: &PageItem, flex_column: bool) -> String {
     let mut html = String::new();
-    let mut classes = Vec::new();
+    let mut classes = vec![builtin_class(item.builtin.kind())];
     let mut styles = Vec::new();
 
     for style in &item.styles {
@@ -249,16 +348,7 @@ fn style_list_to_html(item: &PageItem, flex_column: bool) -> String {
         }
     }
 
-    if !classes.is_empty() {
-        html.push_str(" class=\"");
-        for (i, class) in classes.iter().enumerate() {
-            html.push_str(class);
-            if i + 1 < classes.len() {
-                html.push(' ');
-            }
-        }
-        html.push_str("\"");
-    }
+    html.push_str(&format!(" class=\"{}\"", classes.join(" ")));
 
     if !styles.is_empty() {
         html.push_str(" style=\"");
@@ -301,3 +391,68 @@ fn inline_style_to_html(style: &InlineStyle) -> String {
         InlineStyle::UserDefined { .. } => unreachable!(),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn html_builtin_classes() {
+        let page = parse::parse(
+            r#"{(box text italic) (inline bold) (text mono) (^ underline) (quote serif)}
+(box ({quote} "a") (inline ("b")) (^ "c" "d") (& "e" "f") (# "g"))
+(vbox ("h"))"#,
+        )
+        .unwrap();
+        let html = to_html(&page);
+
+        // builtins first, then shorter selectors first, then in the order they're written
+        let rules = [
+            ".fml-inline {",
+            ".fml-text {",
+            ".fml-link {",
+            ".fml-box .fml-text {",
+            ".quote {",
+        ];
+        let offsets = rules
+            .iter()
+            .map(|rule| html.find(rule).unwrap())
+            .collect::<Vec<_>>();
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+
+        for element in [
+            r#"<div class="fml-box">"#,
+            r#"<span class="fml-text quote">a</span>"#,
+            r#"<span class="fml-inline">"#,
+            r#"<span class="fml-text">b</span>"#,
+            r#"<div class="fml-link"><a href="c">d</a></div>"#,
+            r#"<img src="e" class="fml-blob" alt="f">"#,
+            r#"<div id="g" class="fml-anchor""#,
+            r#"<div class="fml-vbox" style="flex-direction: column;">"#,
+        ]
+        .iter()
+        {
+            assert!(html.contains(element), "{} in {}", element, html);
+        }
+    }
+
+    #[test]
+    fn html_rules_apply_like_styles() {
+        let page = parse::parse(r#"{(loud (size "20")) (text (size "10"))} ({loud} "x")"#).unwrap();
+        let html = to_html(&page);
+
+        // the last rule for the item's classes that sets a size is the one that wins
+        let start = html.find("<style>").unwrap();
+        let end = html.find("</style>").unwrap();
+        let size = html[start..end]
+            .split("}\n")
+            .filter(|rule| rule.starts_with(".fml-text {") || rule.starts_with(".loud {"))
+            .filter_map(|rule| rule.lines().find(|line| line.contains("font-size")))
+            .last()
+            .unwrap();
+
+        let computed = style::compute(&page)[0].1;
+        assert_eq!(computed.size, 20);
+        assert_eq!(size.trim(), format!("font-size: {}px;", computed.size));
+    }
+}
@@ -0,0 +1,303 @@
+//! Pages that own their markup, so they can be kept around, sent between threads and
+//! changed after they're parsed.
+//!
+//! Each owned type mirrors a borrowed one, and can be borrowed back as one, to be used
+//! with everything that takes a [`Page`].
+
+use super::scan::{OwnedToken, Token};
+use super::{InlineStyle, ItemPayload, Page, PageItem, Selector, Span};
+
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedPage {
+    pub styles: OwnedPageStyles,
+    pub items: Vec<OwnedPageItem>,
+}
+
+pub type OwnedPageStyles = HashMap<OwnedSelector, Vec<OwnedInlineStyle>>;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct OwnedSelector {
+    pub tokens: Vec<OwnedToken>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedPageItem {
+    pub span: Span,
+    pub builtin: OwnedToken,
+    pub styles: Vec<OwnedInlineStyle>,
+    pub payload: OwnedItemPayload,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedItemPayload {
+    Text {
+        text: Vec<OwnedToken>,
+    },
+    Children {
+        children: Vec<OwnedPageItem>,
+        line: usize,
+    },
+    Link {
+        link: OwnedToken,
+        text: Vec<OwnedToken>,
+    },
+    Blob {
+        name: OwnedToken,
+        alt: Vec<OwnedToken>,
+    },
+    Anchor {
+        anchor: OwnedToken,
+    },
+    Error,
+}
+
+#[rustfmt::skip]
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedInlineStyle {
+    Mono { token: OwnedToken },
+    Serif { token: OwnedToken },
+    Sans { token: OwnedToken },
+    Bold { token: OwnedToken },
+    Italic { token: OwnedToken },
+    Underline { token: OwnedToken },
+    Strike { token: OwnedToken },
+    Fg { token: OwnedToken, arg: (u8, u8, u8) },
+    Bg { token: OwnedToken, arg: (u8, u8, u8) },
+    Fill { token: OwnedToken, arg: u8 },
+    Size { token: OwnedToken, arg: usize },
+    UserDefined { token: OwnedToken },
+}
+
+impl<'a> Page<'a> {
+    pub fn into_owned(self) -> OwnedPage {
+        OwnedPage::from(&self)
+    }
+}
+
+impl OwnedPage {
+    /// Borrow the page, to render it or turn it back into markup.
+    pub fn as_page(&self) -> Page<'_> {
+        Page {
+            styles: self
+                .styles
+                .iter()
+                .map(|(selector, styles)| {
+                    let styles = styles.iter().map(OwnedInlineStyle::as_style).collect();
+                    (selector.as_selector(), styles)
+                })
+                .collect(),
+            items: self.items.iter().map(OwnedPageItem::as_item).collect(),
+        }
+    }
+}
+
+impl OwnedSelector {
+    pub fn as_selector(&self) -> Selector<'_> {
+        Selector {
+            tokens: self.tokens.iter().map(OwnedToken::as_token).collect(),
+        }
+    }
+}
+
+impl OwnedPageItem {
+    pub fn as_item<'a>(&'a self) -> PageItem<'a> {
+        let tokens = |tokens: &'a [OwnedToken]| tokens.iter().map(OwnedToken::as_token).collect();
+        let payload = match &self.payload {
+            OwnedItemPayload::Text { text } => ItemPayload::Text { text: tokens(text) },
+            OwnedItemPayload::Children { children, line } => ItemPayload::Children {
+                children: children.iter().map(OwnedPageItem::as_item).collect(),
+                line: *line,
+            },
+            OwnedItemPayload::Link { link, text } => ItemPayload::Link {
+                link: link.as_token(),
+                text: tokens(text),
+            },
+            OwnedItemPayload::Blob { name, alt } => ItemPayload::Blob {
+                name: name.as_token(),
+                alt: tokens(alt),
+            },
+            OwnedItemPayload::Anchor { anchor } => ItemPayload::Anchor {
+                anchor: anchor.as_token(),
+            },
+            OwnedItemPayload::Error => ItemPayload::Error,
+        };
+
+        PageItem {
+            span: self.span,
+            builtin: self.builtin.as_token(),
+            styles: self.styles.iter().map(OwnedInlineStyle::as_style).collect(),
+            payload,
+        }
+    }
+}
+
+impl OwnedInlineStyle {
+    #[rustfmt::skip]
+    pub fn as_style(&self) -> InlineStyle<'_> {
+        match self {
+            OwnedInlineStyle::Mono { token }
+                => InlineStyle::Mono { token: token.as_token() },
+            OwnedInlineStyle::Serif { token }
+                => InlineStyle::Serif { token: token.as_token() },
+            OwnedInlineStyle::Sans { token }
+                => InlineStyle::Sans { token: token.as_token() },
+            OwnedInlineStyle::Bold { token }
+                => InlineStyle::Bold { token: token.as_token() },
+            OwnedInlineStyle::Italic { token }
+                => InlineStyle::Italic { token: token.as_token() },
+            OwnedInlineStyle::Underline { token }
+                => InlineStyle::Underline { token: token.as_token() },
+            OwnedInlineStyle::Strike { token }
+                => InlineStyle::Strike { token: token.as_token() },
+            OwnedInlineStyle::Fg { token, arg }
+                => InlineStyle::Fg { token: token.as_token(), arg: *arg },
+            OwnedInlineStyle::Bg { token, arg }
+                => InlineStyle::Bg { token: token.as_token(), arg: *arg },
+            OwnedInlineStyle::Fill { token, arg }
+                => InlineStyle::Fill { token: token.as_token(), arg: *arg },
+            OwnedInlineStyle::Size { token, arg }
+                => InlineStyle::Size { token: token.as_token(), arg: *arg },
+            OwnedInlineStyle::UserDefined { token }
+                => InlineStyle::UserDefined { token: token.as_token() },
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl From<&InlineStyle<'_>> for OwnedInlineStyle {
+    fn from(inline_style: &InlineStyle<'_>) -> Self {
+        match inline_style {
+            InlineStyle::Mono { token }
+                => OwnedInlineStyle::Mono { token: token.into() },
+            InlineStyle::Serif { token }
+                => OwnedInlineStyle::Serif { token: token.into() },
+            InlineStyle::Sans { token }
+                => OwnedInlineStyle::Sans { token: token.into() },
+            InlineStyle::Bold { token }
+                => OwnedInlineStyle::Bold { token: token.into() },
+            InlineStyle::Italic { token }
+                => OwnedInlineStyle::Italic { token: token.into() },
+            InlineStyle::Underline { token }
+                => OwnedInlineStyle::Underline { token: token.into() },
+            InlineStyle::Strike { token }
+                => OwnedInlineStyle::Strike { token: token.into() },
+            InlineStyle::Fg { token, arg }
+                => OwnedInlineStyle::Fg { token: token.into(), arg: *arg },
+            InlineStyle::Bg { token, arg }
+                => OwnedInlineStyle::Bg { token: token.into(), arg: *arg },
+            InlineStyle::Fill { token, arg }
+                => OwnedInlineStyle::Fill { token: token.into(), arg: *arg },
+            InlineStyle::Size { token, arg }
+                => OwnedInlineStyle::Size { token: token.into(), arg: *arg },
+            InlineStyle::UserDefined { token }
+                => OwnedInlineStyle::UserDefined { token: token.into() },
+        }
+    }
+}
+
+impl From<&PageItem<'_>> for OwnedPageItem {
+    fn from(item: &PageItem) -> Self {
+        let tokens = |tokens: &[Token]| tokens.iter().map(OwnedToken::from).collect();
+        let payload = match &item.payload {
+            ItemPayload::Text { text } => OwnedItemPayload::Text { text: tokens(text) },
+            ItemPayload::Children { children, line } => OwnedItemPayload::Children {
+                children: children.iter().map(From::from).collect(),
+                line: *line,
+            },
+            ItemPayload::Link { link, text } => OwnedItemPayload::Link {
+                link: link.into(),
+                text: tokens(text),
+            },
+            ItemPayload::Blob { name, alt } => OwnedItemPayload::Blob {
+                name: name.into(),
+                alt: tokens(alt),
+            },
+            ItemPayload::Anchor { anchor } => OwnedItemPayload::Anchor {
+                anchor: anchor.into(),
+            },
+            ItemPayload::Error => OwnedItemPayload::Error,
+        };
+
+        OwnedPageItem {
+            span: item.span,
+            builtin: (&item.builtin).into(),
+            styles: item.styles.iter().map(From::from).collect(),
+            payload,
+        }
+    }
+}
+
+impl From<&Page<'_>> for OwnedPage {
+    fn from(page: &Page<'_>) -> Self {
+        let styles = page
+            .styles
+            .iter()
+            .map(|(selector, styles)| {
+                let selector = OwnedSelector {
+                    tokens: selector.tokens.iter().map(OwnedToken::from).collect(),
+                };
+                (selector, styles.iter().map(From::from).collect())
+            })
+            .collect();
+
+        OwnedPage {
+            styles,
+            items: page.items.iter().map(From::from).collect(),
+        }
+    }
+}
+
+impl From<Page<'_>> for OwnedPage {
+    fn from(page: Page<'_>) -> Self {
+        page.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markup::parse::parse;
+
+    #[test]
+    fn round_trip() {
+        let source = include_str!("../../../server/pages/test_markup.fml");
+        let page = parse(source).unwrap();
+        let owned = OwnedPage::from(&page);
+        assert_eq!(owned.as_page(), page);
+        assert_eq!(
+            crate::markup::format::to_source(&owned.as_page(), 80),
+            crate::markup::format::to_source(&page, 80)
+        );
+
+        // owned pages outlive their markup, and can go to other threads
+        let owned = {
+            let source = String::from("{(a bold)}({a} \"frog\\n\")");
+            parse(&source).unwrap().into_owned()
+        };
+        let moved = std::thread::spawn(move || owned).join().unwrap();
+
+        let item = moved.as_page().items.remove(0);
+        match item.payload {
+            ItemPayload::Text { text } => assert_eq!(text[0].decoded(), "frog\n"),
+            _ => panic!("not text"),
+        }
+    }
+
+    #[test]
+    fn mutation() {
+        let mut page = parse("(box (\"a\") (\"b\"))").unwrap().into_owned();
+        let copy = page.clone();
+
+        if let OwnedItemPayload::Children { children, .. } = &mut page.items[0].payload {
+            children.reverse();
+        }
+
+        assert_ne!(page, copy);
+        assert_eq!(
+            crate::markup::format::to_source(&page.as_page(), 80),
+            "(box (\"b\") (\"a\"))\n"
+        );
+    }
+}
@@ -1,136 +1,261 @@
 use crate::{AddMsg, FroggiError, ParseError};
 
-use super::scan::{Scanner, Token, TokenKind};
-use super::{InlineStyle, ItemPayload, Page, PageItem, PageStyles};
+use super::scan::{Scanner, Span, Token, TokenKind};
+use super::{InlineStyle, ItemPayload, Page, PageItem, PageStyles, Selector};
 
 use std::collections::HashMap;
 
 /// Parse some data into a Page.
 pub fn parse(data: &str) -> Result<Page<'_>, Vec<FroggiError>> {
+    let (page, errors) = parse_partial(data);
+    if errors.is_empty() {
+        Ok(page)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse as much of some data as possible, along with every error in it. Items with
+/// errors are kept in the page as [`ItemPayload::Error`] items, and parsing carries on
+/// after them.
+pub fn parse_partial(data: &str) -> (Page<'_>, Vec<FroggiError>) {
     let mut errors = Vec::new();
     let mut items = Vec::new();
     let mut page_styles = HashMap::new();
 
     let mut first_item = true;
     let mut scanner = Scanner::new(data);
-    while scanner.peek_token()?.kind() != TokenKind::End {
-        match scanner.peek_token()?.kind() {
+    loop {
+        let token = match scanner.peek_token() {
+            Ok(token) => token,
+            Err(error) => {
+                // the scanner has moved past whatever was wrong
+                errors.push(error);
+                continue;
+            }
+        };
+
+        match token.kind() {
+            TokenKind::End => break,
+
             // there should only be a single page-level style element,
             // and it should be the first item
             TokenKind::LeftBrace if first_item => {
                 first_item = false;
-                match parse_page_styles(&mut scanner) {
-                    Ok(styles) => page_styles = styles,
-                    Err(error) => errors.push(error),
-                }
+                page_styles = parse_page_styles(&mut scanner, &mut errors);
             }
 
             TokenKind::LeftBrace if !first_item => {
                 errors.push(
-                    FroggiError::parse(
-                        ParseError::ExpectedItem { got: "{".into() },
-                        scanner.peek_token()?.line(),
-                    )
-                    .msg_str("page items must be the first item in the page"),
+                    FroggiError::parse(ParseError::ExpectedItem { got: "{".into() }, token.span())
+                        .msg_str("page items must be the first item in the page"),
                 );
-                while scanner.peek_token()?.kind() != TokenKind::RightBrace {
-                    scanner.next_token()?;
-                }
-                scanner.next_token()?;
+                skip_past(&mut scanner, TokenKind::RightBrace, &mut errors);
             }
 
             TokenKind::LeftParen => {
                 first_item = false;
-                match parse_item(&mut scanner, &page_styles) {
-                    Ok(item) => {
-                        items.push(item);
-                    }
-                    Err(error) => {
-                        errors.push(error);
-                    }
-                }
+                items.push(parse_item(&mut scanner, &page_styles, &mut errors));
             }
 
             _ => {
                 errors.push(FroggiError::parse(
                     ParseError::ExpectedItem {
-                        got: scanner.peek_token()?.clone_lexeme(),
+                        got: token.clone_lexeme(),
                     },
-                    scanner.peek_token()?.line(),
+                    token.span(),
                 ));
-                scanner.next_token()?;
-                while !scanner.at_top_level() {
-                    scanner.next_token()?;
-                }
+                let _ = scanner.next_token();
             }
         }
     }
 
-    if errors.is_empty() {
-        Ok(Page {
-            styles: page_styles,
-            items,
-        })
-    } else {
-        Err(errors)
+    let page = Page {
+        styles: page_styles,
+        items,
+    };
+
+    (page, errors)
+}
+
+/// Skip tokens until whatever was open at `depth` is closed, or the page ends. Returns
+/// the span of the last token skipped.
+fn recover(scanner: &mut Scanner, depth: usize, errors: &mut Vec<FroggiError>) -> Option<Span> {
+    let mut last = None;
+    while scanner.depth() >= depth {
+        match scanner.next_token() {
+            Ok(token) if token.kind() == TokenKind::End => break,
+            Ok(token) => last = Some(token.span()),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    last
+}
+
+/// Skip tokens until one of `kind` has been skipped, or the page ends.
+fn skip_past(scanner: &mut Scanner, kind: TokenKind, errors: &mut Vec<FroggiError>) {
+    loop {
+        match scanner.next_token() {
+            Ok(token) if token.kind() == kind || token.kind() == TokenKind::End => break,
+            Ok(_) => {}
+            Err(error) => errors.push(error),
+        }
     }
 }
 
 // consume top-level page style
-fn parse_page_styles<'a>(scanner: &mut Scanner<'a>) -> Result<PageStyles<'a>, FroggiError> {
+fn parse_page_styles<'a>(
+    scanner: &mut Scanner<'a>,
+    errors: &mut Vec<FroggiError>,
+) -> PageStyles<'a> {
+    let mut page_styles = HashMap::new();
+
     // parse outer list of rules
-    let left_brace = consume(scanner, TokenKind::LeftBrace)?;
+    let left_brace = match consume(scanner, TokenKind::LeftBrace) {
+        Ok(left_brace) => left_brace,
+        Err(error) => {
+            errors.push(error);
+            return page_styles;
+        }
+    };
 
-    let in_page_style_item = true;
-    let mut page_styles = HashMap::new();
+    loop {
+        match scanner.peek_token() {
+            Ok(token)
+                if token.kind() == TokenKind::RightBrace || token.kind() == TokenKind::End =>
+            {
+                if let Err(error) = consume(scanner, TokenKind::RightBrace).msg(format!(
+                    "unbalanced braces starting on line {}",
+                    left_brace.line()
+                )) {
+                    errors.push(error);
+                }
+                break;
+            }
+            Ok(_) => {}
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        }
 
-    while scanner.peek_token()?.kind() != TokenKind::RightBrace {
-        // parse one single rule
-        consume(scanner, TokenKind::LeftParen)
-            .msg_str("expected style rules inside page style item")?;
+        // a rule with an error in it is skipped, and the next one is tried
+        let depth = scanner.depth() + 1;
+        match parse_style_rule(scanner) {
+            Ok((selector, styles)) => {
+                page_styles.insert(selector, styles);
+            }
+            Err(error) => {
+                errors.push(error);
+                recover(scanner, depth, errors);
+            }
+        }
+    }
 
-        // name of the rule
-        let selector = consume_selector(scanner)?;
+    page_styles
+}
 
-        // styles that belong to the rule
-        let styles = parse_style_list(scanner, &HashMap::with_capacity(0), in_page_style_item)?;
+// parse one single rule
+fn parse_style_rule<'a>(
+    scanner: &mut Scanner<'a>,
+) -> Result<(Selector<'a>, Vec<InlineStyle<'a>>), FroggiError> {
+    let in_page_style_item = true;
 
-        page_styles.insert(selector, styles);
-        consume(scanner, TokenKind::RightParen).msg_str("end of the style rule")?;
-    }
+    consume(scanner, TokenKind::LeftParen)
+        .msg_str("expected style rules inside page style item")?;
 
-    consume(scanner, TokenKind::RightBrace).msg(format!(
-        "unbalanced braces starting on line {}",
-        left_brace.line()
-    ))?;
+    // name of the rule
+    let selector = consume_selector(scanner)?;
+
+    // styles that belong to the rule
+    let styles = parse_style_list(scanner, &HashMap::with_capacity(0), in_page_style_item)?;
+
+    consume(scanner, TokenKind::RightParen).msg_str("end of the style rule")?;
 
-    Ok(page_styles)
+    Ok((selector, styles))
 }
 
-// parse some normal page item
+// parse some normal page item, or an error item standing in for it
 fn parse_item<'a>(
     scanner: &mut Scanner<'a>,
     page_styles: &PageStyles<'a>,
-) -> Result<PageItem<'a>, FroggiError> {
-    let left_paren = consume(scanner, TokenKind::LeftParen)?;
+    errors: &mut Vec<FroggiError>,
+) -> PageItem<'a> {
+    let left_paren = match consume(scanner, TokenKind::LeftParen) {
+        Ok(left_paren) => left_paren,
+        Err(error) => {
+            let span = error.span().unwrap_or_default();
+            errors.push(error);
+            return error_item(Token::spanned(TokenKind::LeftParen, span, ""), span);
+        }
+    };
 
+    let depth = scanner.depth();
+    match parse_item_contents(scanner, page_styles, errors, left_paren) {
+        Ok(item) => item,
+        Err(error) => {
+            errors.push(error);
+            let end = recover(scanner, depth, errors).unwrap_or_else(|| left_paren.span());
+            error_item(left_paren, left_paren.span().to(end))
+        }
+    }
+}
+
+fn error_item(builtin: Token, span: Span) -> PageItem {
+    PageItem {
+        span,
+        builtin,
+        styles: Vec::new(),
+        payload: ItemPayload::Error,
+    }
+}
+
+fn parse_item_contents<'a>(
+    scanner: &mut Scanner<'a>,
+    page_styles: &PageStyles<'a>,
+    errors: &mut Vec<FroggiError>,
+    left_paren: Token<'a>,
+) -> Result<PageItem<'a>, FroggiError> {
     let result = match scanner.peek_token()?.kind() {
         TokenKind::Blob => parse_blob(scanner, page_styles)?,
         TokenKind::Link => parse_link(scanner, page_styles)?,
         TokenKind::Anchor => parse_anchor(scanner)?,
         TokenKind::Text => parse_text(scanner, page_styles)?,
-        TokenKind::VBox => parse_vbox(scanner, page_styles)?,
-        TokenKind::Box => parse_box(scanner, page_styles)?,
-        TokenKind::Inline => parse_inline(scanner, page_styles)?,
+        TokenKind::VBox => parse_vbox(scanner, page_styles, errors)?,
+        TokenKind::Box => parse_box(scanner, page_styles, errors)?,
+        TokenKind::Inline => parse_inline(scanner, page_styles, errors)?,
         _ => parse_implicit_text(scanner, page_styles)?,
     };
 
-    consume(scanner, TokenKind::RightParen).msg(format!(
+    let right_paren = consume(scanner, TokenKind::RightParen).msg(format!(
         "unbalanced parens starting on line {}",
         left_paren.line()
     ))?;
 
-    Ok(result)
+    // the item starts and ends with its parentheses, not its builtin
+    Ok(PageItem {
+        span: left_paren.span().to(right_paren.span()),
+        ..result
+    })
+}
+
+/// Parse the children of a box, each of which is its own item even if it has errors.
+fn parse_children<'a>(
+    scanner: &mut Scanner<'a>,
+    page_styles: &PageStyles<'a>,
+    errors: &mut Vec<FroggiError>,
+) -> Result<Vec<PageItem<'a>>, FroggiError> {
+    let mut children = Vec::new();
+
+    while !matches!(
+        scanner.peek_token()?.kind(),
+        TokenKind::RightParen | TokenKind::End
+    ) {
+        children.push(parse_item(scanner, page_styles, errors));
+    }
+
+    Ok(children)
 }
 
 fn parse_blob<'a>(
@@ -147,6 +272,7 @@ fn parse_blob<'a>(
     };
 
     Ok(PageItem {
+        span: builtin.span(),
         builtin,
         styles,
         payload,
@@ -167,6 +293,7 @@ fn parse_link<'a>(
     };
 
     Ok(PageItem {
+        span: builtin.span(),
         builtin,
         styles,
         payload,
@@ -178,6 +305,7 @@ fn parse_anchor<'a>(scanner: &mut Scanner<'a>) -> Result<PageItem<'a>, FroggiErr
     let anchor = consume(scanner, TokenKind::String)?;
     let payload = ItemPayload::Anchor { anchor };
     Ok(PageItem {
+        span: builtin.span(),
         builtin,
         styles: Vec::new(),
         payload,
@@ -193,6 +321,7 @@ fn parse_text<'a>(
     let text = collect_text(scanner)?;
 
     Ok(PageItem {
+        span: builtin.span(),
         builtin,
         styles,
         payload: ItemPayload::Text { text },
@@ -202,16 +331,14 @@ fn parse_text<'a>(
 fn parse_vbox<'a>(
     scanner: &mut Scanner<'a>,
     page_styles: &PageStyles<'a>,
+    errors: &mut Vec<FroggiError>,
 ) -> Result<PageItem<'a>, FroggiError> {
     let builtin = consume(scanner, TokenKind::VBox)?;
     let styles = parse_inline_styles(scanner, page_styles)?;
-    let mut children = Vec::new();
-
-    while scanner.peek_token()?.kind() != TokenKind::RightParen {
-        children.push(parse_item(scanner, page_styles)?);
-    }
+    let children = parse_children(scanner, page_styles, errors)?;
 
     Ok(PageItem {
+        span: builtin.span(),
         builtin,
         styles,
         payload: ItemPayload::Children {
@@ -224,16 +351,14 @@ fn parse_vbox<'a>(
 fn parse_box<'a>(
     scanner: &mut Scanner<'a>,
     page_styles: &PageStyles<'a>,
+    errors: &mut Vec<FroggiError>,
 ) -> Result<PageItem<'a>, FroggiError> {
     let builtin = consume(scanner, TokenKind::Box)?;
     let styles = parse_inline_styles(scanner, page_styles)?;
-    let mut children = Vec::new();
-
-    while scanner.peek_token()?.kind() != TokenKind::RightParen {
-        children.push(parse_item(scanner, page_styles)?);
-    }
+    let children = parse_children(scanner, page_styles, errors)?;
 
     Ok(PageItem {
+        span: builtin.span(),
         builtin,
         styles,
         payload: ItemPayload::Children {
@@ -246,16 +371,14 @@ fn parse_box<'a>(
 fn parse_inline<'a>(
     scanner: &mut Scanner<'a>,
     page_styles: &PageStyles<'a>,
+    errors: &mut Vec<FroggiError>,
 ) -> Result<PageItem<'a>, FroggiError> {
     let builtin = consume(scanner, TokenKind::Inline)?;
     let styles = parse_inline_styles(scanner, page_styles)?;
-    let mut children = Vec::new();
-
-    while scanner.peek_token()?.kind() != TokenKind::RightParen {
-        children.push(parse_item(scanner, page_styles)?);
-    }
+    let children = parse_children(scanner, page_styles, errors)?;
 
     Ok(PageItem {
+        span: builtin.span(),
         builtin,
         styles,
         payload: ItemPayload::Children {
@@ -269,11 +392,13 @@ fn parse_implicit_text<'a>(
     scanner: &mut Scanner<'a>,
     page_styles: &PageStyles<'a>,
 ) -> Result<PageItem<'a>, FroggiError> {
-    let implicit = Token::new(TokenKind::ImplicitText, scanner.peek_token()?.line(), "");
+    let start = scanner.peek_token()?.span().start;
+    let implicit = Token::spanned(TokenKind::ImplicitText, Span { start, end: start }, "");
     let styles = parse_inline_styles(scanner, page_styles)?;
     let text = collect_text(scanner)?;
 
     Ok(PageItem {
+        span: implicit.span(),
         builtin: implicit,
         styles,
         payload: ItemPayload::Text { text },
@@ -310,14 +435,18 @@ fn parse_style_list<'a>(
         match token.kind() {
             TokenKind::Identifier => {
                 if !in_page_style_item {
-                    if page_styles.contains_key(&token) {
+                    // a name only used to select what's inside it is still a style
+                    if page_styles
+                        .keys()
+                        .any(|selector| selector.tokens.contains(&token))
+                    {
                         styles.push(InlineStyle::UserDefined { token });
                     } else {
                         return Err(FroggiError::parse(
                             ParseError::UnknownStyle {
                                 style: token.clone_lexeme(),
                             },
-                            token.line(),
+                            token.span(),
                         ));
                     }
                 } else {
@@ -326,7 +455,7 @@ fn parse_style_list<'a>(
                         ParseError::RecursiveStyle {
                             style: token.clone_lexeme(),
                         },
-                        token.line(),
+                        token.span(),
                     ))
                     .msg_str("styles may not reference user-defined styles.");
                 }
@@ -379,13 +508,13 @@ fn parse_style_list<'a>(
                     }
 
                     TokenKind::Fill => {
-                        let arg = arg.lexeme().parse::<u8>().map_err(|_| {
+                        let arg = arg.decoded().parse::<u8>().map_err(|_| {
                             FroggiError::parse(
                                 ParseError::IncorrectNumberFormat {
                                     num: arg.clone_lexeme(),
                                     wanted: String::from("1 or more"),
                                 },
-                                arg.line(),
+                                arg.span(),
                             )
                         })?;
 
@@ -393,13 +522,13 @@ fn parse_style_list<'a>(
                     }
 
                     TokenKind::Size => {
-                        let arg = arg.lexeme().parse::<usize>().map_err(|_| {
+                        let arg = arg.decoded().parse::<usize>().map_err(|_| {
                             FroggiError::parse(
                                 ParseError::IncorrectNumberFormat {
                                     num: arg.clone_lexeme(),
                                     wanted: String::from("valid size"),
                                 },
-                                arg.line(),
+                                arg.span(),
                             )
                         })?;
 
@@ -411,7 +540,7 @@ fn parse_style_list<'a>(
                             ParseError::ExpectedStyle {
                                 got: token.clone_lexeme(),
                             },
-                            token.line(),
+                            token.span(),
                         ))
                         .msg(format!("{} does not take an argument", token.lexeme()))
                     }
@@ -425,7 +554,7 @@ fn parse_style_list<'a>(
                     ParseError::ExpectedStyle {
                         got: token.clone_lexeme(),
                     },
-                    token.line(),
+                    token.span(),
                 ))
                 .msg_str("expected a style rule in the list of inline style rules")
             }
@@ -437,13 +566,13 @@ fn parse_style_list<'a>(
 
 fn parse_hex_color(arg: Token) -> Result<(u8, u8, u8), FroggiError> {
     // TODO: support hsv/rgb/3-digit hex values?
-    let bytes = hex::decode(arg.lexeme()).map_err(|_| {
+    let bytes = hex::decode(arg.decoded().as_bytes()).map_err(|_| {
         FroggiError::parse(
             ParseError::IncorrectNumberFormat {
                 num: arg.clone_lexeme(),
                 wanted: String::from("valid hex number"),
             },
-            arg.line(),
+            arg.span(),
         )
     })?;
 
@@ -453,7 +582,7 @@ fn parse_hex_color(arg: Token) -> Result<(u8, u8, u8), FroggiError> {
                 num: arg.clone_lexeme(),
                 wanted: String::from("6-digit hex number"),
             },
-            arg.line(),
+            arg.span(),
         ));
     }
 
@@ -470,23 +599,39 @@ fn collect_text<'a>(scanner: &mut Scanner<'a>) -> Result<Vec<Token<'a>>, FroggiE
     Ok(text)
 }
 
-fn consume_selector<'a>(scanner: &mut Scanner<'a>) -> Result<Token<'a>, FroggiError> {
-    let token = scanner.next_token()?;
-    match token.kind() {
+pub(crate) fn is_selector(kind: TokenKind) -> bool {
+    matches!(
+        kind,
         TokenKind::Identifier
-        | TokenKind::Link
-        | TokenKind::Box
-        | TokenKind::VBox
-        | TokenKind::Text => Ok(token),
-        _ => Err(FroggiError::parse(
+            | TokenKind::Link
+            | TokenKind::Blob
+            | TokenKind::Box
+            | TokenKind::VBox
+            | TokenKind::Inline
+            | TokenKind::Text
+    )
+}
+
+// the selector of a rule, which is every selector token before the styles
+fn consume_selector<'a>(scanner: &mut Scanner<'a>) -> Result<Selector<'a>, FroggiError> {
+    let token = scanner.next_token()?;
+    if !is_selector(token.kind()) {
+        return Err(FroggiError::parse(
             ParseError::UnexpectedToken {
                 expected: TokenKind::Identifier,
                 got: token.clone_lexeme(),
             },
-            token.line(),
+            token.span(),
         ))
-        .msg_str("selectors must be either built-in items or links, or user-defined selectors"),
+        .msg_str("selectors must be built-in items, links, blobs, or user-defined selectors");
     }
+
+    let mut tokens = vec![token];
+    while is_selector(scanner.peek_token()?.kind()) {
+        tokens.push(scanner.next_token()?);
+    }
+
+    Ok(Selector { tokens })
 }
 
 fn consume<'a>(scanner: &mut Scanner<'a>, kind: TokenKind) -> Result<Token<'a>, FroggiError> {
@@ -500,7 +645,7 @@ fn consume<'a>(scanner: &mut Scanner<'a>, kind: TokenKind) -> Result<Token<'a>,
                 expected: kind,
                 got: token.clone_lexeme(),
             },
-            token.line(),
+            token.span(),
         ))
     }
 }
@@ -511,7 +656,7 @@ mod test {
 
     #[test]
     fn recursive_user_style() {
-        let page = "{(a b) (b a)}";
+        let page = "{(a bold) (b italic a)}";
 
         match parse(page).unwrap_err()[0].kind() {
             crate::ErrorKind::ParseError { error, .. } => match error {
@@ -608,6 +753,12 @@ mod test {
         parse(item).unwrap();
     }
 
+    fn page_style_errors(scanner: &mut Scanner) -> bool {
+        let mut errors = Vec::new();
+        parse_page_styles(scanner, &mut errors);
+        !errors.is_empty()
+    }
+
     #[test]
     fn ill_formed_page_styles() {
         use crate::markup::scan::Scanner;
@@ -616,47 +767,53 @@ mod test {
         // can never fail for a reason that we don't expect
         let style = "{";
         let mut scanner = Scanner::new(style);
-        assert!(parse_page_styles(&mut scanner).is_err());
+        assert!(page_style_errors(&mut scanner));
 
         let style = "{text) serif}";
         let mut scanner = Scanner::new(style);
-        assert!(parse_page_styles(&mut scanner).is_err());
+        assert!(page_style_errors(&mut scanner));
 
         let style = "{(text) serif}";
         let mut scanner = Scanner::new(style);
-        assert!(parse_page_styles(&mut scanner).is_err());
+        assert!(page_style_errors(&mut scanner));
 
         let style = "{() (style)}";
         let mut scanner = Scanner::new(style);
-        assert!(parse_page_styles(&mut scanner).is_err());
+        assert!(page_style_errors(&mut scanner));
 
         let style = "{( (style)}";
         let mut scanner = Scanner::new(style);
-        assert!(parse_page_styles(&mut scanner).is_err());
+        assert!(page_style_errors(&mut scanner));
 
         let style = "{ (style))}";
         let mut scanner = Scanner::new(style);
-        assert!(parse_page_styles(&mut scanner).is_err());
+        assert!(page_style_errors(&mut scanner));
 
         let style = "";
         let mut scanner = Scanner::new(style);
-        assert!(parse_page_styles(&mut scanner).is_err());
+        assert!(page_style_errors(&mut scanner));
     }
 
     #[test]
     fn well_formed_page_style() {
         let style = r#"{(text serif)(footnote underline (fg "902100"))}"#;
         let mut scanner = crate::markup::scan::Scanner::new(style);
-        let style = parse_page_styles(&mut scanner).unwrap();
+        let mut errors = Vec::new();
+        let style = parse_page_styles(&mut scanner, &mut errors);
+        assert!(errors.is_empty());
         let mut styles = HashMap::new();
         styles.insert(
-            Token::new(TokenKind::Text, 1, "text"),
+            Selector {
+                tokens: vec![Token::new(TokenKind::Text, 1, "text")],
+            },
             vec![InlineStyle::Serif {
                 token: Token::new(TokenKind::Serif, 1, "serif"),
             }],
         );
         styles.insert(
-            Token::new(TokenKind::Identifier, 1, "footnote"),
+            Selector {
+                tokens: vec![Token::new(TokenKind::Identifier, 1, "footnote")],
+            },
             vec![
                 InlineStyle::Underline {
                     token: Token::new(TokenKind::Underline, 1, "underline"),
@@ -670,9 +827,137 @@ mod test {
         assert_eq!(style, styles);
     }
 
+    #[test]
+    fn selectors() {
+        let sample = r#"{(inline bold) (& italic) (^ underline) (quote-box text mono)
+ (quote-box vbox loud (size "20")) (loud (fg "ff0000"))}
+(vbox {quote-box} ({loud} "a"))"#;
+        let page = parse(sample).unwrap();
+
+        let mut selectors = page
+            .styles
+            .keys()
+            .map(|selector| {
+                selector
+                    .tokens
+                    .iter()
+                    .map(|token| token.lexeme())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>();
+        selectors.sort();
+        assert_eq!(
+            selectors,
+            vec![
+                "&",
+                "^",
+                "inline",
+                "loud",
+                "quote-box text",
+                "quote-box vbox loud"
+            ]
+        );
+
+        let rule = page
+            .styles
+            .keys()
+            .find(|selector| selector.tokens.len() == 3)
+            .unwrap();
+        assert_eq!(rule.subject().lexeme(), "loud");
+        assert_eq!(rule.context().len(), 2);
+        assert_eq!(&sample[rule.span().range()], "quote-box vbox loud");
+
+        // a style can be used if it's in the selector of any rule
+        parse(r#"{(a b bold)} ({b} "c")"#).unwrap();
+        parse(r#"{(a b bold)} ({a} "c")"#).unwrap();
+        assert!(parse(r#"{(a b bold)} ({c} "c")"#).is_err());
+
+        // anchors have no styles
+        assert!(parse(r#"{(# bold)}"#).is_err());
+    }
+
     #[test]
     fn test_markup() {
         let sample = include_str!("../../../server/pages/test_markup.fml");
         parse(sample).unwrap();
     }
+
+    #[test]
+    fn spans() {
+        let sample = "(\"a\")\n  (box {bold} (\"b\"))";
+        let page = parse(sample).unwrap();
+        assert_eq!(&sample[page.items[0].span.range()], "(\"a\")");
+        assert_eq!(&sample[page.items[1].span.range()], "(box {bold} (\"b\"))");
+        assert_eq!(page.items[1].span.start.line, 2);
+        assert_eq!(page.items[1].span.start.column, 3);
+        assert_eq!(&sample[page.items[1].styles[0].span().range()], "bold");
+
+        let sample = "(\"a\")\n(box {(fg \"zz\")})";
+        let errors = parse(sample).unwrap_err();
+        let span = errors[0].span().unwrap();
+        assert_eq!(&sample[span.range()], "\"zz\"");
+        assert_eq!((span.start.line, span.start.column), (2, 11));
+    }
+
+    #[test]
+    fn recovery() {
+        let sample = r#"{(quote (fg "nothex")) (aside italic)}
+(box ("fine") ({bad} "typo") (& ) ("also fine"))
+(vbox ("one" "\q") (^ "frgi://x/" {aside} "link"))
+stray
+("last")"#;
+        let (page, errors) = parse_partial(sample);
+        let messages = errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 5, "{:#?}", messages);
+        let lines = errors
+            .iter()
+            .map(|error| error.line().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 2, 2, 3, 4]);
+
+        // the rule with an error is skipped, and the one after it kept
+        assert_eq!(page.styles.len(), 1);
+
+        let kinds = |items: &[PageItem]| {
+            items
+                .iter()
+                .map(|item| matches!(item.payload, ItemPayload::Error))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(page.items.len(), 3);
+        assert_eq!(kinds(&page.items), vec![false, false, false]);
+        match (&page.items[0].payload, &page.items[1].payload) {
+            (
+                ItemPayload::Children {
+                    children: first, ..
+                },
+                ItemPayload::Children {
+                    children: second, ..
+                },
+            ) => {
+                assert_eq!(kinds(first), vec![false, true, true, false]);
+                assert_eq!(&sample[first[1].span.range()], r#"({bad} "typo")"#);
+                assert_eq!(kinds(second), vec![true, false]);
+            }
+            _ => panic!(),
+        }
+
+        assert!(parse(sample).is_err());
+    }
+
+    #[test]
+    fn unclosed() {
+        let (page, errors) = parse_partial("(box (\"a\")");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(page.items.len(), 1);
+
+        let (page, errors) = parse_partial("(\"a\") {(style)} (\"b\"");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(page.items.len(), 2);
+    }
 }
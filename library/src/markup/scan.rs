@@ -1,5 +1,8 @@
 use crate::{FroggiError, ScanError};
 
+use std::borrow::Cow;
+use std::ops::Range;
+
 fn is_control_character(c: u8) -> bool {
     c == b'{'
         || c == b'}'
@@ -48,10 +51,54 @@ pub enum TokenKind {
     End,
 }
 
+/// A place in some markup. Lines and columns start at 1, and columns count characters.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The part of some markup that a token, item, or error covers, from the start of its
+/// first character to the end of its last.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// A span that only knows which line it's on.
+    pub fn line(line: usize) -> Span {
+        let position = Position {
+            line,
+            ..Position::default()
+        };
+
+        Span {
+            start: position,
+            end: position,
+        }
+    }
+
+    /// The byte range of the span in the markup it came from.
+    pub fn range(&self) -> Range<usize> {
+        self.start.offset..self.end.offset
+    }
+
+    /// The span from the start of this one to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Token<'a> {
     kind: TokenKind,
-    line: usize,
+    span: Span,
     lexeme: &'a str,
 }
 
@@ -71,8 +118,14 @@ impl std::hash::Hash for Token<'_> {
 }
 
 impl Token<'_> {
+    /// A token that was never scanned, so only its line is known.
+    #[cfg(test)]
     pub(crate) fn new(kind: TokenKind, line: usize, lexeme: &str) -> Token<'_> {
-        Token { kind, line, lexeme }
+        Token::spanned(kind, Span::line(line), lexeme)
+    }
+
+    pub(crate) fn spanned(kind: TokenKind, span: Span, lexeme: &str) -> Token<'_> {
+        Token { kind, span, lexeme }
     }
 
     pub fn kind(&self) -> TokenKind {
@@ -80,19 +133,173 @@ impl Token<'_> {
     }
 
     pub fn line(&self) -> usize {
-        self.line
+        self.span.start.line
+    }
+
+    /// Where the token is in the markup, including the quotes of strings.
+    pub fn span(&self) -> Span {
+        self.span
     }
 
     pub fn clone_lexeme(&self) -> String {
         self.lexeme().into()
     }
 
+    /// The token as it was written. Strings don't include their quotes, and their
+    /// escape sequences are left alone.
     pub fn lexeme(&self) -> &str {
         match self.kind {
             TokenKind::String => &self.lexeme[1..self.lexeme.len() - 1],
             _ => self.lexeme,
         }
     }
+
+    /// The text the token stands for, with the escape sequences in strings replaced by
+    /// the characters they stand for.
+    pub fn decoded(&self) -> Cow<'_, str> {
+        match self.kind {
+            TokenKind::String => unescape(self.lexeme()),
+            _ => Cow::Borrowed(self.lexeme),
+        }
+    }
+}
+
+/// A token that owns its lexeme, for pages that outlive their markup.
+#[derive(Clone, Debug)]
+pub struct OwnedToken {
+    kind: TokenKind,
+    span: Span,
+    lexeme: String,
+}
+
+impl PartialEq for OwnedToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_token() == other.as_token()
+    }
+}
+
+impl Eq for OwnedToken {}
+
+impl std::hash::Hash for OwnedToken {
+    fn hash<H: std::hash::Hasher>(&self, hasher: &mut H) {
+        self.as_token().hash(hasher);
+    }
+}
+
+impl OwnedToken {
+    /// Borrow the token back.
+    pub fn as_token(&self) -> Token<'_> {
+        Token::spanned(self.kind, self.span, &self.lexeme)
+    }
+
+    pub fn kind(&self) -> TokenKind {
+        self.kind
+    }
+
+    pub fn line(&self) -> usize {
+        self.span.start.line
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The token as it was written, like [`Token::lexeme`].
+    pub fn lexeme(&self) -> &str {
+        match self.kind {
+            TokenKind::String => &self.lexeme[1..self.lexeme.len() - 1],
+            _ => &self.lexeme,
+        }
+    }
+
+    /// The text the token stands for, like [`Token::decoded`].
+    pub fn decoded(&self) -> Cow<'_, str> {
+        match self.kind {
+            TokenKind::String => unescape(self.lexeme()),
+            _ => Cow::Borrowed(&self.lexeme),
+        }
+    }
+}
+
+impl From<&Token<'_>> for OwnedToken {
+    fn from(token: &Token<'_>) -> Self {
+        OwnedToken {
+            kind: token.kind,
+            span: token.span,
+            lexeme: token.lexeme.into(),
+        }
+    }
+}
+
+impl From<Token<'_>> for OwnedToken {
+    fn from(token: Token<'_>) -> Self {
+        OwnedToken::from(&token)
+    }
+}
+
+/// Replace escape sequences with the characters they stand for. Sequences the scanner
+/// wouldn't accept are left as they are.
+pub fn unescape(text: &str) -> Cow<'_, str> {
+    if !text.contains('\\') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('\\') {
+        decoded.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        match escape_sequence(rest) {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                decoded.push('\\');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+/// Escape text so that it can be put inside a string.
+pub fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The character an escape sequence at the start of `text` stands for, and how many
+/// bytes the sequence is.
+fn escape_sequence(text: &str) -> Option<(char, usize)> {
+    let mut chars = text.strip_prefix('\\')?.chars();
+    let c = match chars.next()? {
+        '"' => '"',
+        '\\' => '\\',
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        'u' => {
+            // \u{1f438}
+            let digits = text[2..].strip_prefix('{')?;
+            let end = digits.find('}')?;
+            let code = &digits[..end];
+            if code.is_empty() || code.len() > 6 {
+                return None;
+            }
+
+            let c = u32::from_str_radix(code, 16)
+                .ok()
+                .and_then(char::from_u32)?;
+            return Some((c, 4 + end));
+        }
+        _ => return None,
+    };
+
+    Some((c, 2))
 }
 
 #[derive(Debug)]
@@ -100,6 +307,7 @@ pub struct Scanner<'a> {
     start: usize,
     current: usize,
     line: usize,
+    line_start: usize,
     paren_level: usize,
     token: Option<Token<'a>>,
     source: &'a [u8],
@@ -111,6 +319,7 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
             paren_level: 0,
             token: None,
             source: s.as_bytes(),
@@ -121,6 +330,15 @@ impl<'a> Scanner<'a> {
         self.paren_level == 0
     }
 
+    /// How many parentheses are open, not counting a token that's only been peeked at.
+    pub fn depth(&self) -> usize {
+        match self.token.map(|token| token.kind()) {
+            Some(TokenKind::LeftParen) => self.paren_level - 1,
+            Some(TokenKind::RightParen) => self.paren_level + 1,
+            _ => self.paren_level,
+        }
+    }
+
     pub fn peek_token(&mut self) -> Result<Token<'a>, FroggiError> {
         if self.token.is_none() {
             self.next()?;
@@ -139,47 +357,47 @@ impl<'a> Scanner<'a> {
 
     fn next(&mut self) -> Result<Token<'a>, FroggiError> {
         let token = if self.at_end() {
-            Token::new(TokenKind::End, self.line, "")
+            Token::spanned(TokenKind::End, self.span_here(), "")
         } else {
             self.slurp_whitespace();
             self.start = self.current;
-            Token::new(
-                match self.advance() {
-                    b'\0' => {
-                        self.token = Some(Token::new(TokenKind::End, self.line, ""));
-                        return Ok(Token::new(TokenKind::End, self.line, ""));
-                    }
+            let start = self.position();
+            let kind = match self.advance() {
+                b'\0' => {
+                    let end = Token::spanned(TokenKind::End, self.span_here(), "");
+                    self.token = Some(end);
+                    return Ok(end);
+                }
 
-                    b'"' => self.text(),
-                    b'{' => Ok(TokenKind::LeftBrace),
-                    b'}' => Ok(TokenKind::RightBrace),
+                b'"' => self.text(start),
+                b'{' => Ok(TokenKind::LeftBrace),
+                b'}' => Ok(TokenKind::RightBrace),
 
-                    b'(' => {
-                        self.paren_level += 1;
-                        Ok(TokenKind::LeftParen)
-                    }
+                b'(' => {
+                    self.paren_level += 1;
+                    Ok(TokenKind::LeftParen)
+                }
 
-                    b')' => {
-                        if self.paren_level != 0 {
-                            self.paren_level -= 1;
-                            Ok(TokenKind::RightParen)
-                        } else {
-                            Err(FroggiError::parse(
-                                crate::ParseError::UnbalancedParentheses,
-                                self.line,
-                            ))
-                        }
+                b')' => {
+                    if self.paren_level != 0 {
+                        self.paren_level -= 1;
+                        Ok(TokenKind::RightParen)
+                    } else {
+                        Err(FroggiError::parse(
+                            crate::ParseError::UnbalancedParentheses,
+                            self.span_from(start),
+                        ))
                     }
+                }
+
+                b'&' => Ok(TokenKind::Blob),
+                b'^' => Ok(TokenKind::Link),
+                b'#' => Ok(TokenKind::Anchor),
 
-                    b'&' => Ok(TokenKind::Blob),
-                    b'^' => Ok(TokenKind::Link),
-                    b'#' => Ok(TokenKind::Anchor),
+                _ => self.identifier(),
+            }?;
 
-                    _ => self.identifier(),
-                }?,
-                self.line,
-                self.lexeme()?,
-            )
+            Token::spanned(kind, self.span_from(start), self.lexeme()?)
         };
 
         self.token = Some(token);
@@ -211,32 +429,86 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn text(&mut self) -> Result<TokenKind, FroggiError> {
-        let start_line = self.line;
-
+    fn text(&mut self, start: Position) -> Result<TokenKind, FroggiError> {
         while !self.at_end() && self.peek() != b'"' {
-            if self.peek() == b'\n' {
-                self.line += 1;
-            }
             if self.peek() == b'\\' {
-                self.advance();
-                if self.peek() != b'\"' {
-                    return self.error(ScanError::UnknownEscapeCode {
-                        code: self.peek() as char,
-                    });
-                }
+                self.escape()?;
+                continue;
+            }
+            if self.advance() == b'\n' {
+                self.newline();
             }
-            self.advance();
         }
 
         if self.at_end() {
-            self.error(ScanError::UnterminatedString { start_line })
+            Err(FroggiError::scan(
+                ScanError::UnterminatedString {
+                    start_line: start.line,
+                },
+                self.span_from(start),
+            ))
         } else {
             self.advance();
             Ok(TokenKind::String)
         }
     }
 
+    fn escape(&mut self) -> Result<(), FroggiError> {
+        let start = self.position();
+        // long enough for the longest sequence, \u{10ffff}
+        let end = self.source.len().min(self.current + 10);
+        let rest = match std::str::from_utf8(&self.source[self.current..end]) {
+            Ok(rest) => rest,
+            Err(error) => std::str::from_utf8(&self.source[self.current..][..error.valid_up_to()])?,
+        };
+
+        if let Some((_, len)) = escape_sequence(rest) {
+            self.current += len;
+            return Ok(());
+        }
+
+        let (error, len) = match rest.chars().nth(1) {
+            Some('u') => {
+                let escape = match rest.find('}') {
+                    Some(i) => &rest[..=i],
+                    None => "\\u",
+                };
+                let len = escape.len();
+                let escape = escape.into();
+                (ScanError::InvalidCodepoint { escape }, len)
+            }
+            // a backslash at the end of a line is left for skip_string() to count
+            Some('\n') => (ScanError::UnknownEscapeCode { code: '\n' }, 1),
+            Some(code) => (ScanError::UnknownEscapeCode { code }, 1 + code.len_utf8()),
+            // the string is unterminated, which text() reports
+            None => {
+                self.advance();
+                return Ok(());
+            }
+        };
+
+        self.current += len;
+        let span = self.span_from(start);
+        self.skip_string();
+        Err(FroggiError::scan(error, span))
+    }
+
+    /// Skip the rest of a string with a bad escape in it, so that scanning carries on
+    /// after the string instead of in the middle of it.
+    fn skip_string(&mut self) {
+        while !self.at_end() && self.peek() != b'"' {
+            match self.advance() {
+                b'\\' if self.peek() == b'"' || self.peek() == b'\\' => {
+                    self.advance();
+                }
+                b'\n' => self.newline(),
+                _ => {}
+            }
+        }
+
+        self.advance();
+    }
+
     fn slurp_whitespace(&mut self) {
         while self.peek() == b';' || self.peek().is_ascii_whitespace() {
             if self.peek() == b';' {
@@ -247,7 +519,7 @@ impl<'a> Scanner<'a> {
 
             while !self.at_end() && self.peek().is_ascii_whitespace() {
                 if self.advance() == b'\n' {
-                    self.line += 1;
+                    self.newline();
                 }
             }
         }
@@ -274,8 +546,37 @@ impl<'a> Scanner<'a> {
         Ok(std::str::from_utf8(&self.source[self.start..self.current])?)
     }
 
-    fn error(&self, error: ScanError) -> Result<TokenKind, FroggiError> {
-        Err(FroggiError::scan(error, self.line))
+    /// Start a new line after advancing past a newline.
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    fn position(&self) -> Position {
+        let offset = self.current.min(self.source.len());
+        // count the characters on the line, skipping utf8 continuation bytes
+        let column = self.source[self.line_start.min(offset)..offset]
+            .iter()
+            .filter(|byte| *byte & 0xc0 != 0x80)
+            .count()
+            + 1;
+
+        Position {
+            offset,
+            line: self.line,
+            column,
+        }
+    }
+
+    fn span_from(&self, start: Position) -> Span {
+        Span {
+            start,
+            end: self.position(),
+        }
+    }
+
+    fn span_here(&self) -> Span {
+        self.span_from(self.position())
     }
 }
 
@@ -367,4 +668,98 @@ mod test {
         map.insert(Token::new(TokenKind::Identifier, 1, "i"), ());
         assert!(map.contains_key(&Token::new(TokenKind::Identifier, 3889583, "i")));
     }
+
+    #[test]
+    fn escapes() {
+        let mut scanner = Scanner::new(r#""say \"hi\" \\ \n\t\u{1f438}""#);
+        let token = scanner.next_token().unwrap();
+        assert_eq!(token.lexeme(), r#"say \"hi\" \\ \n\t\u{1f438}"#);
+        assert_eq!(token.decoded(), "say \"hi\" \\ \n\t\u{1f438}");
+
+        let plain = Token::new(TokenKind::String, 1, "\"plain\"");
+        assert!(matches!(plain.decoded(), Cow::Borrowed("plain")));
+
+        for bad in &[r#""\q""#, r#""\u{110000}""#, r#""\u{}""#, r#""\u1f438""#] {
+            assert!(Scanner::new(bad).next_token().is_err(), "{}", bad);
+        }
+
+        let error = Scanner::new("\"oops\\").next_token().unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            crate::ErrorKind::ScanError {
+                error: ScanError::UnterminatedString { .. },
+                ..
+            }
+        ));
+
+        let text = "a \\ path\\with \"quotes\"";
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(unescape(r#"\q stays"#), r#"\q stays"#);
+    }
+
+    #[test]
+    fn spans() {
+        let source = "(text\n  \"h\u{e9}llo\" \u{fc}n\u{ef})";
+        let mut scanner = Scanner::new(source);
+        let mut spans = Vec::new();
+        loop {
+            let token = scanner.next_token().unwrap();
+            if token.kind() == TokenKind::End {
+                break;
+            }
+            spans.push(token.span());
+        }
+
+        let at = |offset, line, column| Position {
+            offset,
+            line,
+            column,
+        };
+        assert_eq!(
+            spans[0],
+            Span {
+                start: at(0, 1, 1),
+                end: at(1, 1, 2)
+            }
+        );
+        assert_eq!(
+            spans[1],
+            Span {
+                start: at(1, 1, 2),
+                end: at(5, 1, 6)
+            }
+        );
+        assert_eq!(
+            spans[2],
+            Span {
+                start: at(8, 2, 3),
+                end: at(16, 2, 10)
+            }
+        );
+        assert_eq!(&source[spans[3].range()], "\u{fc}n\u{ef}");
+        assert_eq!(
+            spans[3],
+            Span {
+                start: at(17, 2, 11),
+                end: at(22, 2, 14)
+            }
+        );
+
+        let source = "(\"ok\" \"a\\q\")";
+        let mut scanner = Scanner::new(source);
+        let error = loop {
+            if let Err(error) = scanner.next_token() {
+                break error;
+            }
+        };
+        let span = error.span().unwrap();
+        assert_eq!(&source[span.range()], "\\q");
+
+        let source = "(\"a\nb";
+        let mut scanner = Scanner::new(source);
+        scanner.next_token().unwrap();
+        let span = scanner.next_token().unwrap_err().span().unwrap();
+        assert_eq!(&source[span.range()], "\"a\nb");
+        assert_eq!((span.start.line, span.end.line), (1, 2));
+    }
 }
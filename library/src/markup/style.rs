@@ -0,0 +1,429 @@
+//! Working out how each item of a page looks.
+//!
+//! An item's style starts as the style of the item it's inside, or the default style
+//! for items at the top of the page. Then, each later one winning over the earlier ones:
+//!
+//! 1. the page style rules for the item's builtin, like `(text serif)`. Text without a
+//!    builtin counts as `text`.
+//! 2. the item's user-defined and inline styles, in the order they're written. A
+//!    user-defined style stands for the styles in its page style rules.
+//!
+//! A rule like `(quote-box text serif)` only applies to items inside a `quote-box`, and
+//! is applied after the rules for the same thing with fewer items in their selectors.
+//! Rules with selectors of the same length are applied in the order they're written.
+//!
+//! Everything but `fill` is inherited, since `fill` is about how much of its parent an
+//! item takes up.
+
+use super::scan::{Token, TokenKind};
+use super::visit::Entry;
+use super::{InlineStyle, Page, PageItem, PageStyles};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FontType {
+    Mono,
+    Serif,
+    Sans,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct FontStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strike: bool,
+}
+
+/// The fully resolved style of an item.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ComputedStyle {
+    pub font_type: FontType,
+    pub font_style: FontStyle,
+    pub background: (u8, u8, u8),
+    pub foreground: (u8, u8, u8),
+    pub fill: u8,
+    pub size: usize,
+}
+
+impl Default for ComputedStyle {
+    fn default() -> ComputedStyle {
+        ComputedStyle {
+            font_type: FontType::Serif,
+            font_style: FontStyle::default(),
+            background: (0xff, 0xff, 0xff),
+            foreground: (0x00, 0x00, 0x00),
+            fill: 1,
+            size: 12,
+        }
+    }
+}
+
+impl ComputedStyle {
+    /// The style the children of an item with this style start with.
+    pub fn inherited(&self) -> ComputedStyle {
+        ComputedStyle {
+            fill: ComputedStyle::default().fill,
+            ..*self
+        }
+    }
+
+    /// Apply some styles in order, to an item inside `ancestors`, outermost first.
+    /// User-defined styles apply the rules for them whose selectors match.
+    pub fn apply(
+        &mut self,
+        ancestors: &[&PageItem],
+        styles: &[InlineStyle],
+        page_styles: &PageStyles,
+    ) {
+        for inline_style in styles {
+            match inline_style {
+                InlineStyle::Mono { .. } => self.font_type = FontType::Mono,
+                InlineStyle::Serif { .. } => self.font_type = FontType::Serif,
+                InlineStyle::Sans { .. } => self.font_type = FontType::Sans,
+                InlineStyle::Bold { .. } => self.font_style.bold = true,
+                InlineStyle::Italic { .. } => self.font_style.italic = true,
+                InlineStyle::Underline { .. } => self.font_style.underline = true,
+                InlineStyle::Strike { .. } => self.font_style.strike = true,
+                InlineStyle::Fg { arg, .. } => self.foreground = *arg,
+                InlineStyle::Bg { arg, .. } => self.background = *arg,
+                InlineStyle::Fill { arg, .. } => self.fill = *arg,
+                InlineStyle::Size { arg, .. } => self.size = *arg,
+                InlineStyle::UserDefined { token } => {
+                    for rule in rules(page_styles, ancestors, |subject| subject == token) {
+                        self.apply(ancestors, rule, page_styles);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The rules whose subjects pass `subject` and whose contexts match `ancestors`, in
+/// the order they apply: shorter selectors first, then in the order they're written.
+pub fn rules<'p, 'a>(
+    page_styles: &'p PageStyles<'a>,
+    ancestors: &[&PageItem],
+    subject: impl Fn(&Token) -> bool,
+) -> Vec<&'p [InlineStyle<'a>]> {
+    let mut rules = page_styles
+        .iter()
+        .filter(|(selector, _)| subject(&selector.subject()))
+        .filter(|(selector, _)| within(selector.context(), ancestors))
+        .collect::<Vec<_>>();
+
+    rules.sort_by_key(|(selector, _)| (selector.tokens.len(), selector.span().start.offset));
+    rules
+        .into_iter()
+        .map(|(_, styles)| styles.as_slice())
+        .collect()
+}
+
+/// Whether each token of a selector's context matches an ancestor, with each one
+/// inside the one before it.
+fn within(context: &[Token], ancestors: &[&PageItem]) -> bool {
+    let mut ancestors = ancestors.iter().rev();
+    context
+        .iter()
+        .rev()
+        .all(|token| ancestors.any(|ancestor| selects(token, ancestor)))
+}
+
+/// Whether an item matches one token of a selector.
+pub fn selects(token: &Token, item: &PageItem) -> bool {
+    match token.kind() {
+        TokenKind::Identifier => item.styles.iter().any(|style| match style {
+            InlineStyle::UserDefined { token: class } => class == token,
+            _ => false,
+        }),
+        TokenKind::Text => {
+            matches!(
+                item.builtin.kind(),
+                TokenKind::Text | TokenKind::ImplicitText
+            )
+        }
+        kind => item.builtin.kind() == kind,
+    }
+}
+
+/// The style of an item inside `ancestors`, outermost first, the innermost of which
+/// has the style `parent`.
+pub fn item_style(
+    ancestors: &[&PageItem],
+    parent: &ComputedStyle,
+    item: &PageItem,
+    page_styles: &PageStyles,
+) -> ComputedStyle {
+    let mut style = parent.inherited();
+
+    let builtin =
+        |subject: &Token| subject.kind() != TokenKind::Identifier && selects(subject, item);
+    for rule in rules(page_styles, ancestors, builtin) {
+        style.apply(ancestors, rule, page_styles);
+    }
+
+    style.apply(ancestors, &item.styles, page_styles);
+    style
+}
+
+/// The style of every item of a page, in the order of [`Page::iter_items`].
+pub fn compute<'p, 'a>(page: &'p Page<'a>) -> Vec<(Entry<'p, 'a>, ComputedStyle)> {
+    let mut ancestors = Vec::new();
+    let mut parents = Vec::new();
+    let mut styles = Vec::new();
+
+    for entry in page.iter_items() {
+        ancestors.truncate(entry.depth());
+        parents.truncate(entry.depth());
+
+        let parent = parents.last().copied().unwrap_or_default();
+        let style = item_style(&ancestors, &parent, entry.item, &page.styles);
+
+        ancestors.push(entry.item);
+        parents.push(style);
+        styles.push((entry, style));
+    }
+
+    styles
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markup::parse::parse;
+
+    #[test]
+    fn font_style() {
+        let page = r#"{(a italic) (b bold) (c mono)} ({a b c underline} "")"#;
+        let page = parse(page).unwrap();
+        let mut style = ComputedStyle::default();
+        style.apply(&[], &page.items[0].styles, &page.styles);
+
+        assert_eq!(
+            style,
+            ComputedStyle {
+                font_type: FontType::Mono,
+                font_style: FontStyle {
+                    italic: true,
+                    bold: true,
+                    underline: true,
+                    ..FontStyle::default()
+                },
+                background: (0xff, 0xff, 0xff),
+                foreground: (0x00, 0x00, 0x00),
+                fill: 1,
+                size: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn bg_fg_application_order() {
+        let page = r#"
+        {(a (bg "b11111"))
+         (b (fg "f22222") (bg "baaaad"))
+         (c (bg "b33333"))}
+
+        ({(fg "f11111") a (bg "b22222") b (fg "f33333") c} "")
+        "#;
+
+        let page = parse(page).unwrap();
+        let mut style = ComputedStyle::default();
+        style.apply(&[], &page.items[0].styles, &page.styles);
+
+        assert_eq!(
+            style,
+            ComputedStyle {
+                font_type: FontType::Serif,
+                font_style: FontStyle::default(),
+                background: (0xb3, 0x33, 0x33),
+                foreground: (0xf3, 0x33, 0x33),
+                fill: 1,
+                size: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn font_type_application_order() {
+        let page = r#"{(a sans) (b serif) (c mono)} ({a b c} "")"#;
+        let page = parse(page).unwrap();
+        let mut style = ComputedStyle::default();
+        style.apply(&[], &page.items[0].styles, &page.styles);
+
+        assert_eq!(
+            style,
+            ComputedStyle {
+                font_type: FontType::Mono,
+                ..ComputedStyle::default()
+            }
+        );
+    }
+
+    #[test]
+    fn cascade() {
+        let page = r#"
+        {(text sans (size "14"))
+         (box (fg "101010") (fill "3"))
+         (^ underline)
+         (loud bold (size "20"))}
+
+        (box {(bg "202020")}
+         ("a")
+         (text {mono} "b")
+         (vbox {loud (fill "2")}
+          ({(size "16")} "c")
+          (^ "/d" "d")))
+        (& "e.png")
+        "#;
+
+        let page = parse(page).unwrap();
+        let styles = compute(&page)
+            .into_iter()
+            .map(|(entry, style)| (entry.path, style))
+            .collect::<Vec<_>>();
+
+        let default = ComputedStyle::default();
+        let outer = ComputedStyle {
+            foreground: (0x10, 0x10, 0x10),
+            background: (0x20, 0x20, 0x20),
+            fill: 3,
+            ..default
+        };
+        let text = ComputedStyle {
+            font_type: FontType::Sans,
+            size: 14,
+            fill: 1,
+            ..outer
+        };
+        let vbox = ComputedStyle {
+            font_style: FontStyle {
+                bold: true,
+                ..FontStyle::default()
+            },
+            size: 20,
+            fill: 2,
+            ..outer
+        };
+
+        assert_eq!(
+            styles,
+            vec![
+                (vec![0], outer),
+                (vec![0, 0], text),
+                (
+                    vec![0, 1],
+                    ComputedStyle {
+                        font_type: FontType::Mono,
+                        ..text
+                    }
+                ),
+                (vec![0, 2], vbox),
+                (
+                    vec![0, 2, 0],
+                    ComputedStyle {
+                        font_type: FontType::Sans,
+                        size: 16,
+                        fill: 1,
+                        ..vbox
+                    }
+                ),
+                (
+                    vec![0, 2, 1],
+                    ComputedStyle {
+                        font_style: FontStyle {
+                            bold: true,
+                            underline: true,
+                            ..FontStyle::default()
+                        },
+                        fill: 1,
+                        ..vbox
+                    }
+                ),
+                (vec![1], default),
+            ]
+        );
+    }
+
+    #[test]
+    fn descendants() {
+        let page = r#"
+        {(quote-box text italic)
+         (quote-box vbox text (fg "ff0000"))
+         (text (fg "00ff00") (size "10"))
+         (& (size "30"))
+         (inline bold)
+         (quote-box loud (size "40"))
+         (loud (size "20"))}
+
+        (box {quote-box}
+         ("a")
+         (vbox (text {loud} "b"))
+         (inline (& "c.png")))
+        ("d")
+        (vbox ("e"))
+        "#;
+
+        let page = parse(page).unwrap();
+        let styles = compute(&page)
+            .into_iter()
+            .map(|(entry, style)| (entry.path, style))
+            .collect::<Vec<_>>();
+
+        let default = ComputedStyle::default();
+        let text = ComputedStyle {
+            foreground: (0x00, 0xff, 0x00),
+            size: 10,
+            ..default
+        };
+        let italic = FontStyle {
+            italic: true,
+            ..FontStyle::default()
+        };
+        let bold = FontStyle {
+            bold: true,
+            ..FontStyle::default()
+        };
+
+        assert_eq!(
+            styles,
+            vec![
+                (vec![0], default),
+                (
+                    vec![0, 0],
+                    ComputedStyle {
+                        font_style: italic,
+                        ..text
+                    }
+                ),
+                (vec![0, 1], default),
+                (
+                    vec![0, 1, 0],
+                    ComputedStyle {
+                        font_style: italic,
+                        foreground: (0xff, 0x00, 0x00),
+                        size: 40,
+                        ..default
+                    }
+                ),
+                (
+                    vec![0, 2],
+                    ComputedStyle {
+                        font_style: bold,
+                        ..default
+                    }
+                ),
+                (
+                    vec![0, 2, 0],
+                    ComputedStyle {
+                        font_style: bold,
+                        size: 30,
+                        ..default
+                    }
+                ),
+                (vec![1], text),
+                (vec![2], default),
+                (vec![2, 0], text),
+            ]
+        );
+    }
+}
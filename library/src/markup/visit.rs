@@ -0,0 +1,411 @@
+//! Walking the items of a page, without writing the recursion into children every time.
+//!
+//! A [`Visitor`] has a method for each part of a page, each of which walks into the
+//! parts under it unless it's overridden. An overriding method can call the matching
+//! `walk_` function to keep going. [`VisitorMut`] is the same, for changing a page.
+//!
+//! ```
+//! use froggi::markup::visit::{walk_item, Visitor};
+//! use froggi::markup::PageItem;
+//!
+//! struct Deepest(usize, usize);
+//!
+//! impl<'a> Visitor<'a> for Deepest {
+//!     fn visit_item(&mut self, item: &PageItem<'a>) {
+//!         self.0 += 1;
+//!         self.1 = self.1.max(self.0);
+//!         walk_item(self, item);
+//!         self.0 -= 1;
+//!     }
+//! }
+//!
+//! let page = froggi::markup::parse::parse("(box (\"a\") (vbox (\"b\")))").unwrap();
+//! let mut deepest = Deepest(0, 0);
+//! deepest.visit_page(&page);
+//! assert_eq!(deepest.1, 3);
+//! ```
+
+use super::scan::Token;
+use super::{InlineStyle, ItemPayload, Page, PageItem, Selector};
+
+pub trait Visitor<'a> {
+    fn visit_page(&mut self, page: &Page<'a>) {
+        walk_page(self, page);
+    }
+
+    /// A rule in the page style.
+    fn visit_page_style(&mut self, selector: &Selector<'a>, styles: &[InlineStyle<'a>]) {
+        walk_page_style(self, selector, styles);
+    }
+
+    fn visit_item(&mut self, item: &PageItem<'a>) {
+        walk_item(self, item);
+    }
+
+    fn visit_payload(&mut self, payload: &ItemPayload<'a>) {
+        walk_payload(self, payload);
+    }
+
+    fn visit_style(&mut self, _style: &InlineStyle<'a>) {}
+
+    /// A string of text, link text or alt text.
+    fn visit_text(&mut self, _text: &Token<'a>) {}
+}
+
+pub fn walk_page<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, page: &Page<'a>) {
+    for (selector, styles) in &page.styles {
+        visitor.visit_page_style(selector, styles);
+    }
+
+    for item in &page.items {
+        visitor.visit_item(item);
+    }
+}
+
+pub fn walk_page_style<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    _selector: &Selector<'a>,
+    styles: &[InlineStyle<'a>],
+) {
+    for style in styles {
+        visitor.visit_style(style);
+    }
+}
+
+pub fn walk_item<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, item: &PageItem<'a>) {
+    for style in &item.styles {
+        visitor.visit_style(style);
+    }
+
+    visitor.visit_payload(&item.payload);
+}
+
+pub fn walk_payload<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, payload: &ItemPayload<'a>) {
+    match payload {
+        ItemPayload::Text { text }
+        | ItemPayload::Link { text, .. }
+        | ItemPayload::Blob { alt: text, .. } => {
+            for text in text {
+                visitor.visit_text(text);
+            }
+        }
+
+        ItemPayload::Children { children, .. } => {
+            for child in children {
+                visitor.visit_item(child);
+            }
+        }
+
+        ItemPayload::Anchor { .. } | ItemPayload::Error => {}
+    }
+}
+
+pub trait VisitorMut<'a> {
+    fn visit_page_mut(&mut self, page: &mut Page<'a>) {
+        walk_page_mut(self, page);
+    }
+
+    /// A rule in the page style. Selectors are keys, so they can't be changed here.
+    fn visit_page_style_mut(&mut self, selector: &Selector<'a>, styles: &mut Vec<InlineStyle<'a>>) {
+        walk_page_style_mut(self, selector, styles);
+    }
+
+    fn visit_item_mut(&mut self, item: &mut PageItem<'a>) {
+        walk_item_mut(self, item);
+    }
+
+    fn visit_payload_mut(&mut self, payload: &mut ItemPayload<'a>) {
+        walk_payload_mut(self, payload);
+    }
+
+    fn visit_style_mut(&mut self, _style: &mut InlineStyle<'a>) {}
+
+    fn visit_text_mut(&mut self, _text: &mut Token<'a>) {}
+}
+
+pub fn walk_page_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, page: &mut Page<'a>) {
+    for (selector, styles) in page.styles.iter_mut() {
+        visitor.visit_page_style_mut(selector, styles);
+    }
+
+    for item in &mut page.items {
+        visitor.visit_item_mut(item);
+    }
+}
+
+pub fn walk_page_style_mut<'a, V: VisitorMut<'a> + ?Sized>(
+    visitor: &mut V,
+    _selector: &Selector<'a>,
+    styles: &mut Vec<InlineStyle<'a>>,
+) {
+    for style in styles {
+        visitor.visit_style_mut(style);
+    }
+}
+
+pub fn walk_item_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, item: &mut PageItem<'a>) {
+    for style in &mut item.styles {
+        visitor.visit_style_mut(style);
+    }
+
+    visitor.visit_payload_mut(&mut item.payload);
+}
+
+pub fn walk_payload_mut<'a, V: VisitorMut<'a> + ?Sized>(
+    visitor: &mut V,
+    payload: &mut ItemPayload<'a>,
+) {
+    match payload {
+        ItemPayload::Text { text }
+        | ItemPayload::Link { text, .. }
+        | ItemPayload::Blob { alt: text, .. } => {
+            for text in text {
+                visitor.visit_text_mut(text);
+            }
+        }
+
+        ItemPayload::Children { children, .. } => {
+            for child in children {
+                visitor.visit_item_mut(child);
+            }
+        }
+
+        ItemPayload::Anchor { .. } | ItemPayload::Error => {}
+    }
+}
+
+/// An item of a page, and where it is in the page.
+#[derive(Debug)]
+pub struct Entry<'p, 'a> {
+    pub item: &'p PageItem<'a>,
+    /// The index of the item in the page, then in each of the children it's inside.
+    pub path: Vec<usize>,
+}
+
+impl Entry<'_, '_> {
+    /// How many items the item is inside. Items at the top of the page are at depth 0.
+    pub fn depth(&self) -> usize {
+        self.path.len() - 1
+    }
+}
+
+/// The items of a page, depth first. From [`Page::iter_items`].
+pub struct Items<'p, 'a> {
+    stack: Vec<std::iter::Enumerate<std::slice::Iter<'p, PageItem<'a>>>>,
+    path: Vec<usize>,
+}
+
+impl<'p, 'a> Iterator for Items<'p, 'a> {
+    type Item = Entry<'p, 'a>;
+
+    fn next(&mut self) -> Option<Entry<'p, 'a>> {
+        loop {
+            let depth = self.stack.len().checked_sub(1)?;
+            match self.stack.last_mut()?.next() {
+                Some((i, item)) => {
+                    self.path.truncate(depth);
+                    self.path.push(i);
+
+                    if let ItemPayload::Children { children, .. } = &item.payload {
+                        self.stack.push(children.iter().enumerate());
+                    }
+
+                    return Some(Entry {
+                        item,
+                        path: self.path.clone(),
+                    });
+                }
+
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Page<'a> {
+    /// Every item in the page, each before its children.
+    pub fn iter_items(&self) -> Items<'_, 'a> {
+        Items {
+            stack: vec![self.items.iter().enumerate()],
+            path: Vec::new(),
+        }
+    }
+
+    /// The item at a path from [`Entry::path`].
+    pub fn item_at(&self, path: &[usize]) -> Option<&PageItem<'a>> {
+        let (first, rest) = path.split_first()?;
+        let mut item = self.items.get(*first)?;
+        for i in rest {
+            item = match &item.payload {
+                ItemPayload::Children { children, .. } => children.get(*i)?,
+                _ => return None,
+            };
+        }
+        Some(item)
+    }
+
+    /// Where each link in the page goes, in order.
+    pub fn links(&self) -> impl Iterator<Item = Token<'a>> + '_ {
+        self.iter_items()
+            .filter_map(|entry| match &entry.item.payload {
+                ItemPayload::Link { link, .. } => Some(*link),
+                _ => None,
+            })
+    }
+
+    /// The name of each blob in the page, in order. Names that are used more than once
+    /// come up more than once.
+    pub fn blobs(&self) -> impl Iterator<Item = Token<'a>> + '_ {
+        self.iter_items()
+            .filter_map(|entry| match &entry.item.payload {
+                ItemPayload::Blob { name, .. } => Some(*name),
+                _ => None,
+            })
+    }
+
+    /// The name of each anchor in the page, in order.
+    pub fn anchors(&self) -> impl Iterator<Item = Token<'a>> + '_ {
+        self.iter_items()
+            .filter_map(|entry| match &entry.item.payload {
+                ItemPayload::Anchor { anchor } => Some(*anchor),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markup::parse::parse;
+    use crate::markup::scan::TokenKind;
+
+    const SOURCE: &str = r##"{(a bold)}
+(box ("one") (vbox {a} (^ "/two" "two") (& "three.png")))
+(# "four")
+(inline (& "three.png" "again") (^ "#four"))"##;
+
+    #[test]
+    fn iter_items() {
+        let page = parse(SOURCE).unwrap();
+        let entries = page
+            .iter_items()
+            .map(|entry| (entry.item.builtin.kind(), entry.depth(), entry.path))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            entries,
+            vec![
+                (TokenKind::Box, 0, vec![0]),
+                (TokenKind::ImplicitText, 1, vec![0, 0]),
+                (TokenKind::VBox, 1, vec![0, 1]),
+                (TokenKind::Link, 2, vec![0, 1, 0]),
+                (TokenKind::Blob, 2, vec![0, 1, 1]),
+                (TokenKind::Anchor, 0, vec![1]),
+                (TokenKind::Inline, 0, vec![2]),
+                (TokenKind::Blob, 1, vec![2, 0]),
+                (TokenKind::Link, 1, vec![2, 1]),
+            ]
+        );
+
+        for entry in page.iter_items() {
+            assert_eq!(page.item_at(&entry.path), Some(entry.item));
+        }
+        assert_eq!(page.item_at(&[0, 0, 0]), None);
+        assert_eq!(page.item_at(&[]), None);
+        assert_eq!(parse("").unwrap().iter_items().count(), 0);
+    }
+
+    #[test]
+    fn helpers() {
+        let page = parse(SOURCE).unwrap();
+        let lexemes = |tokens: Vec<Token>| {
+            tokens
+                .iter()
+                .map(|token| token.clone_lexeme())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(lexemes(page.links().collect()), vec!["/two", "#four"]);
+        assert_eq!(
+            lexemes(page.blobs().collect()),
+            vec!["three.png", "three.png"]
+        );
+        assert_eq!(lexemes(page.anchors().collect()), vec!["four"]);
+    }
+
+    #[test]
+    fn visitor() {
+        struct Count {
+            items: usize,
+            styles: usize,
+            text: Vec<String>,
+        }
+
+        impl<'a> Visitor<'a> for Count {
+            fn visit_item(&mut self, item: &PageItem<'a>) {
+                self.items += 1;
+                walk_item(self, item);
+            }
+
+            fn visit_style(&mut self, _: &InlineStyle<'a>) {
+                self.styles += 1;
+            }
+
+            fn visit_text(&mut self, text: &Token<'a>) {
+                self.text.push(text.clone_lexeme());
+            }
+        }
+
+        let page = parse(SOURCE).unwrap();
+        let mut count = Count {
+            items: 0,
+            styles: 0,
+            text: Vec::new(),
+        };
+        count.visit_page(&page);
+
+        assert_eq!(count.items, 9);
+        // the page style's bold, and the vbox's a
+        assert_eq!(count.styles, 2);
+        assert_eq!(count.text, vec!["one", "two", "again"]);
+    }
+
+    #[test]
+    fn visitor_mut() {
+        // drop every style and put children in reverse
+        struct Plain;
+
+        impl<'a> VisitorMut<'a> for Plain {
+            fn visit_item_mut(&mut self, item: &mut PageItem<'a>) {
+                item.styles.clear();
+                if let ItemPayload::Children { children, .. } = &mut item.payload {
+                    children.reverse();
+                }
+                walk_item_mut(self, item);
+            }
+
+            fn visit_page_style_mut(
+                &mut self,
+                _: &Selector<'a>,
+                styles: &mut Vec<InlineStyle<'a>>,
+            ) {
+                styles.clear();
+            }
+        }
+
+        let mut page = parse(SOURCE).unwrap();
+        Plain.visit_page_mut(&mut page);
+
+        assert_eq!(
+            crate::markup::format::to_source(&page, 200),
+            concat!(
+                "{(a)}\n",
+                "(box (vbox (& \"three.png\") (^ \"/two\" \"two\")) (\"one\"))\n",
+                "(# \"four\")\n",
+                "(inline (^ \"#four\") (& \"three.png\" \"again\"))\n",
+            )
+        );
+    }
+}
@@ -1,13 +1,50 @@
+use crate::response::Item;
 use crate::{serialize_to_bytes, AddMsg, ErrorKind, FroggiError};
 
 use std::io::Read;
+use std::net::SocketAddr;
 
 /// Represents a froggi request to a server.
 pub struct Request {
     version: u8,
     path: String,
+    metadata: Vec<(String, String)>,
+    upload: Option<Upload>,
+    peer: Option<SocketAddr>,
+    client_fingerprint: Option<String>,
 }
 
+/// A page and its items, sent to a server to be published at the request's path.
+///
+/// Sent in version 3 and later.
+#[derive(Debug, Clone)]
+pub struct Upload {
+    page: String,
+    items: Vec<Item>,
+}
+
+impl Upload {
+    pub fn new(page: String, items: Vec<Item>) -> Upload {
+        Upload { page, items }
+    }
+
+    pub fn page(&self) -> &str {
+        &self.page
+    }
+
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    pub fn parse(&self) -> Result<crate::markup::Page<'_>, Vec<FroggiError>> {
+        crate::markup::parse::parse(&self.page)
+    }
+}
+
+// what a request asks of the server, sent in version 3 and later
+const KIND_READ: u8 = 0;
+const KIND_PUBLISH: u8 = 1;
+
 impl Request {
     /// Create a new request.
     pub fn new(path: impl ToString) -> Result<Self, FroggiError> {
@@ -17,8 +54,77 @@ impl Request {
         if path.len() > u16::MAX as usize {
             Err(FroggiError::new(ErrorKind::RequestFormatError).msg_str("The path is too large."))
         } else {
-            Ok(Request { version, path })
+            Ok(Request {
+                version,
+                path,
+                metadata: Vec::new(),
+                upload: None,
+                peer: None,
+                client_fingerprint: None,
+            })
+        }
+    }
+
+    /// Create a new request for a path on a particular host.
+    pub fn with_host(path: impl ToString, host: impl ToString) -> Result<Self, FroggiError> {
+        let mut request = Request::new(path)?;
+        request.set_metadata("host", host)?;
+        Ok(request)
+    }
+
+    /// Create a request to publish a page and its items at a path.
+    pub fn publish(path: impl ToString, upload: Upload) -> Result<Self, FroggiError> {
+        if upload.page.len() > u32::MAX as usize {
+            return Err(
+                FroggiError::new(ErrorKind::RequestFormatError).msg_str("The page is too large.")
+            );
         }
+
+        if upload.items.len() > u16::MAX as usize {
+            return Err(FroggiError::new(ErrorKind::RequestFormatError)
+                .msg_str("There are too many items."));
+        }
+
+        if upload.items.iter().any(|item| {
+            item.name().len() > u16::MAX as usize || item.data().len() > u32::MAX as usize
+        }) {
+            return Err(
+                FroggiError::new(ErrorKind::RequestFormatError).msg_str("An item is too large.")
+            );
+        }
+
+        let mut request = Request::new(path)?;
+        request.upload = Some(upload);
+        Ok(request)
+    }
+
+    /// Add a metadata entry, replacing any existing entry with the same key.
+    ///
+    /// Metadata is only sent in version 1 and later.
+    pub fn set_metadata(
+        &mut self,
+        key: impl ToString,
+        value: impl ToString,
+    ) -> Result<(), FroggiError> {
+        let key = key.to_string();
+        let value = value.to_string();
+
+        if key.len() > u16::MAX as usize || value.len() > u16::MAX as usize {
+            return Err(FroggiError::new(ErrorKind::RequestFormatError)
+                .msg_str("The metadata entry is too large."));
+        }
+
+        if self.metadata.len() >= u16::MAX as usize {
+            return Err(FroggiError::new(ErrorKind::RequestFormatError)
+                .msg_str("There are too many metadata entries."));
+        }
+
+        match self.metadata.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.metadata.push((key, value)),
+        }
+
+        Ok(())
     }
 
     /// Read a requets from a source of bytes.
@@ -37,17 +143,131 @@ impl Request {
 
         let path = String::from_utf8(path_buf)?;
 
-        Ok(Request { version, path })
+        // version 1 added metadata after the path
+        let mut metadata = Vec::new();
+        if version >= 1 {
+            let mut num_entries = [0u8; 2];
+            bytes.read_exact(&mut num_entries)?;
+            let num_entries = crate::deserialize_bytes(&num_entries);
+
+            for _ in 0..num_entries {
+                let key = read_string(bytes)?;
+                let value = read_string(bytes)?;
+                metadata.push((key, value));
+            }
+        }
+
+        // version 3 added publishing
+        let mut upload = None;
+        if version >= 3 {
+            let mut kind = [0u8; 1];
+            bytes.read_exact(&mut kind)?;
+            match kind[0] {
+                KIND_READ => {}
+                KIND_PUBLISH => {
+                    let (page, items) = crate::response::read_body(bytes)?;
+                    upload = Some(Upload { page, items });
+                }
+                kind => {
+                    return Err(FroggiError::new(ErrorKind::RequestFormatError)
+                        .msg(format!("unknown request kind {}", kind)));
+                }
+            }
+        }
+
+        Ok(Request {
+            version,
+            path,
+            metadata,
+            upload,
+            peer: None,
+            client_fingerprint: None,
+        })
     }
 
     pub fn version(&self) -> u8 {
         self.version
     }
 
+    /// The requested path, without any query.
     pub fn path(&self) -> &str {
+        match self.path.find('?') {
+            Some(i) => &self.path[..i],
+            None => &self.path,
+        }
+    }
+
+    /// The part of the requested path after a `?`, if any.
+    pub fn query(&self) -> Option<&str> {
+        self.path.find('?').map(|i| &self.path[i + 1..])
+    }
+
+    /// The requested path as it was sent, including any query.
+    pub fn raw_path(&self) -> &str {
         &self.path
     }
 
+    /// The value of a metadata entry.
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every metadata entry, in the order they were sent.
+    pub fn metadata_entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.metadata
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The hostname the client wants a page from, without any port.
+    ///
+    /// Version 0 requests never have a host.
+    pub fn host(&self) -> Option<&str> {
+        let host = self.metadata("host")?;
+
+        // [::1]:11121, example.com:11121
+        if host.starts_with('[') {
+            host.find(']').map(|i| &host[..=i])
+        } else {
+            match host.rfind(':') {
+                Some(i) if !host[..i].contains(':') => Some(&host[..i]),
+                _ => Some(host),
+            }
+        }
+    }
+
+    /// The page the client wants to publish, if this is a publish request.
+    pub fn upload(&self) -> Option<&Upload> {
+        self.upload.as_ref()
+    }
+
+    /// The token the client sent to prove who it is, if any.
+    pub fn token(&self) -> Option<&str> {
+        self.metadata("token")
+    }
+
+    /// The address of the client that sent the request, if it came from the network.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    pub(crate) fn set_peer(&mut self, peer: Option<SocketAddr>) {
+        self.peer = peer;
+    }
+
+    /// The fingerprint of the certificate the client identified itself with, if it
+    /// connected over TLS and presented one.
+    pub fn client_fingerprint(&self) -> Option<&str> {
+        self.client_fingerprint.as_deref()
+    }
+
+    pub(crate) fn set_client_fingerprint(&mut self, fingerprint: Option<String>) {
+        self.client_fingerprint = fingerprint;
+    }
+
     pub fn into_bytes(self) -> Vec<u8> {
         self.into()
     }
@@ -64,39 +284,153 @@ impl Into<Vec<u8>> for Request {
         data.push(low);
         data.push(high);
 
-        // remainder of request is the path
+        // next is the path
         data.extend(self.path.bytes());
 
+        // version 1 and later: number of metadata entries, then each key and value
+        if self.version >= 1 {
+            let (low, high) = serialize_to_bytes(self.metadata.len());
+            data.push(low);
+            data.push(high);
+
+            for (key, value) in self.metadata.iter() {
+                for string in &[key, value] {
+                    let (low, high) = serialize_to_bytes(string.len());
+                    data.push(low);
+                    data.push(high);
+                    data.extend(string.bytes());
+                }
+            }
+        }
+
+        // version 3 and later: what kind of request it is, then the page to publish
+        if self.version >= 3 {
+            match &self.upload {
+                Some(upload) => {
+                    data.push(KIND_PUBLISH);
+                    crate::response::write_body(&upload.page, &upload.items, &mut data);
+                }
+                None => data.push(KIND_READ),
+            }
+        }
+
         data
     }
 }
 
+fn read_string(bytes: &mut impl Read) -> Result<String, FroggiError> {
+    let mut len = [0u8; 2];
+    bytes.read_exact(&mut len)?;
+    let len = crate::deserialize_bytes(&len);
+
+    let mut buf = vec![0; len];
+    bytes.read_exact(&mut buf)?;
+
+    Ok(String::from_utf8(buf)?)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[rustfmt::skip]
-    const REQUEST_BYTES: &[u8] = &[
+    const REQUEST_BYTES_V0: &[u8] = &[
         0x00,                                                       // version
         0x09, 0x00,                                                 // path length
         0x69, 0x6e, 0x64, 0x65, 0x78, 0x2e, 0x66, 0x6d, 0x6c,       // request path
     ];
 
+    #[rustfmt::skip]
+    const REQUEST_BYTES: &[u8] = &[
+        0x03,                                                       // version
+        0x09, 0x00,                                                 // path length
+        0x69, 0x6e, 0x64, 0x65, 0x78, 0x2e, 0x66, 0x6d, 0x6c,       // request path
+        0x01, 0x00,                                                 // number of metadata entries
+        0x04, 0x00,                                                 // key length
+        0x68, 0x6f, 0x73, 0x74,                                     // key
+        0x09, 0x00,                                                 // value length
+        0x66, 0x72, 0x6f, 0x67, 0x67, 0x69, 0x2e, 0x69, 0x6f,       // value
+        0x00,                                                       // kind
+    ];
+
+    #[test]
+    fn from_bytes_v0() {
+        let mut bytes = REQUEST_BYTES_V0;
+        let request = Request::from_bytes(&mut bytes).unwrap();
+        assert_eq!(request.version, 0);
+        assert_eq!(&request.path, "index.fml");
+        assert_eq!(request.host(), None);
+    }
+
     #[test]
     fn from_bytes() {
-        let mut bytes = REQUEST_BYTES.clone();
+        let mut bytes = REQUEST_BYTES;
         let request = Request::from_bytes(&mut bytes).unwrap();
         assert_eq!(request.version, crate::FROGGI_VERSION);
         assert_eq!(&request.path, "index.fml");
+        assert_eq!(request.host(), Some("froggi.io"));
     }
 
     #[test]
     fn to_bytes() {
-        let request = Request::new("index.fml").unwrap();
+        let request = Request::with_host("index.fml", "froggi.io").unwrap();
         let data_test = request.into_bytes();
 
         assert_eq!(data_test.len(), REQUEST_BYTES.len());
 
         crate::test::test_bytes(REQUEST_BYTES, &data_test).unwrap();
     }
+
+    #[test]
+    fn host_without_port() {
+        let mut request = Request::with_host("", "frog.com:11121").unwrap();
+        assert_eq!(request.host(), Some("frog.com"));
+
+        request.set_metadata("host", "[::1]:11121").unwrap();
+        assert_eq!(request.host(), Some("[::1]"));
+
+        request.set_metadata("host", "[::1]").unwrap();
+        assert_eq!(request.host(), Some("[::1]"));
+
+        request.set_metadata("host", "::1").unwrap();
+        assert_eq!(request.host(), Some("::1"));
+        assert_eq!(request.metadata.len(), 1);
+    }
+
+    #[test]
+    fn query() {
+        let request = Request::new("search.fml?q=frog?s").unwrap();
+        assert_eq!(request.path(), "search.fml");
+        assert_eq!(request.query(), Some("q=frog?s"));
+        assert_eq!(request.raw_path(), "search.fml?q=frog?s");
+
+        let request = Request::new("index.fml").unwrap();
+        assert_eq!(request.path(), "index.fml");
+        assert_eq!(request.query(), None);
+    }
+
+    #[test]
+    fn publish() {
+        let upload = Upload::new(
+            String::from("(& \"frog.png\" \"a frog\")"),
+            vec![Item::new(String::from("frog.png"), vec![1, 2, 3])],
+        );
+        let mut request = Request::publish("/frogs.fml", upload).unwrap();
+        request.set_metadata("token", "secret").unwrap();
+
+        let bytes = request.into_bytes();
+        let request = Request::from_bytes(&mut bytes.as_slice()).unwrap();
+        assert_eq!(request.path(), "/frogs.fml");
+        assert_eq!(request.token(), Some("secret"));
+
+        let upload = request.upload().unwrap();
+        upload.parse().unwrap();
+        assert_eq!(upload.items()[0].name(), "frog.png");
+        assert_eq!(upload.items()[0].data(), &[1, 2, 3]);
+
+        // a request cut short
+        let mut bytes = bytes;
+        bytes.truncate(bytes.len() - 1);
+        assert!(Request::from_bytes(&mut bytes.as_slice()).is_err());
+    }
 }
@@ -1,7 +1,8 @@
-use crate::FroggiError;
+use crate::{AddMsg, ErrorKind, FroggiError};
 
 use std::io::Read;
 
+#[derive(Clone)]
 pub struct Item {
     name: String,
     data: Vec<u8>,
@@ -27,12 +28,74 @@ impl Item {
     }
 }
 
+/// Whether the server could produce the requested page.
+///
+/// Sent in version 2 and later. Responses to older clients are always `Ok`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Status {
+    Ok = 0,
+    BadRequest = 1,
+    Forbidden = 2,
+    NotFound = 3,
+    ServerError = 4,
+    RateLimited = 5,
+    Unauthorized = 6,
+}
+
+impl Status {
+    pub fn from_u8(status: u8) -> Option<Status> {
+        match status {
+            0 => Some(Status::Ok),
+            1 => Some(Status::BadRequest),
+            2 => Some(Status::Forbidden),
+            3 => Some(Status::NotFound),
+            4 => Some(Status::ServerError),
+            5 => Some(Status::RateLimited),
+            6 => Some(Status::Unauthorized),
+            _ => None,
+        }
+    }
+
+    pub fn is_ok(self) -> bool {
+        self == Status::Ok
+    }
+
+    /// The status to send to a client that speaks `version`, which might not know
+    /// about this one.
+    fn for_version(self, version: u8) -> Status {
+        match self {
+            // version 3 added rate limiting and authentication
+            Status::RateLimited if version < 3 => Status::ServerError,
+            Status::Unauthorized if version < 3 => Status::Forbidden,
+            status => status,
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Status::Ok => "ok",
+            Status::BadRequest => "bad request",
+            Status::Forbidden => "forbidden",
+            Status::NotFound => "not found",
+            Status::ServerError => "server error",
+            Status::RateLimited => "rate limited",
+            Status::Unauthorized => "authentication required",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
 /// Represents a response from a froggi server.
 #[derive(Debug)]
 pub struct Response {
     version: u8,
+    status: Status,
     page: String,
     items: Vec<Item>,
+    message: Option<String>,
 }
 
 impl Response {
@@ -40,65 +103,67 @@ impl Response {
     pub fn new(page: String, items: Vec<Item>) -> Self {
         Self {
             version: crate::FROGGI_VERSION,
+            status: Status::Ok,
             page,
             items,
+            message: None,
         }
     }
 
+    /// Create a response explaining why a request failed, with a plain built-in page.
+    ///
+    /// The message is kept so that middleware can build a nicer page from it.
+    pub fn error(status: Status, message: impl ToString) -> Self {
+        let message = message.to_string();
+        let page = format!(
+            "(\"{}\")\n(\"{}\")",
+            status,
+            crate::markup::escape(&message)
+        );
+
+        Self {
+            status,
+            message: Some(message),
+            ..Response::new(page, Vec::new())
+        }
+    }
+
+    /// Set the status of the response.
+    pub fn with_status(mut self, status: Status) -> Self {
+        self.status = status;
+        self
+    }
+
     pub fn parse(&self) -> Result<crate::markup::Page<'_>, Vec<FroggiError>> {
         crate::markup::parse::parse(&self.page)
     }
 
     /// Read a response from a source of bytes.
     pub fn from_bytes(bytes: &mut impl Read) -> Result<Self, FroggiError> {
-        // response header, 5 bytes long
-        let mut header = [0u8; 5];
-        bytes.read_exact(&mut header)?;
-
-        // consists of version (1 byte) and page length (4 bytes)
-        let version = header[0];
-        let page_len = crate::deserialize_four_bytes(&header[1..]);
-
-        // read page
-        // Vec::with_capacity doesn't work here for some reason
-        let mut page_buf = vec![0; page_len];
-        bytes.read_exact(&mut page_buf)?;
-        let page = String::from_utf8(page_buf)?;
-
-        // number of items, two bytes
-        let mut num_items = [0u8; 2];
-        bytes.read_exact(&mut num_items)?;
-        let num_items = crate::deserialize_bytes(&num_items);
-
-        // read items
-        let mut items = Vec::with_capacity(num_items);
-        for _ in 0..num_items {
-            // length of the item's name
-            let mut item_name_len = [0u8; 2];
-            bytes.read_exact(&mut item_name_len)?;
-            let item_name_len = crate::deserialize_bytes(&item_name_len);
-
-            // item name
-            let mut name_buf = vec![0; item_name_len];
-            bytes.read_exact(&mut name_buf)?;
-            let name = String::from_utf8(name_buf)?;
-
-            // item length
-            let mut item_len = [0u8; 4];
-            bytes.read_exact(&mut item_len)?;
-            let item_len = crate::deserialize_four_bytes(&item_len);
-
-            // item
-            let mut data = vec![0; item_len];
-            bytes.read_exact(&mut data)?;
-
-            items.push(Item { name, data });
-        }
+        // response header, version (1 byte) and status (1 byte, version 2 and later)
+        let mut version = [0u8; 1];
+        bytes.read_exact(&mut version)?;
+        let version = version[0];
+
+        let status = if version >= 2 {
+            let mut status = [0u8; 1];
+            bytes.read_exact(&mut status)?;
+            Status::from_u8(status[0]).ok_or_else(|| {
+                FroggiError::new(ErrorKind::ResponseFormatError)
+                    .msg(format!("unknown status {}", status[0]))
+            })?
+        } else {
+            Status::Ok
+        };
+
+        let (page, items) = read_body(bytes)?;
 
         Ok(Self {
             version,
+            status,
             page,
             items,
+            message: None,
         })
     }
 
@@ -106,6 +171,22 @@ impl Response {
         self.version
     }
 
+    /// Send the response in an older version of the protocol, for older clients.
+    pub(crate) fn set_version(&mut self, version: u8) {
+        self.version = version.min(crate::FROGGI_VERSION);
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Why the request failed, if the response was created with [`Response::error`].
+    ///
+    /// This is never sent to the client except as part of the page.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
     pub fn page(&self) -> &str {
         &self.page
     }
@@ -126,41 +207,13 @@ impl Into<Vec<u8>> for Response {
         // first byte: version number
         data.push(self.version);
 
-        // next four bytes: page length
-        let page_len = crate::serialize_to_four_bytes(self.page.len());
-        data.push(page_len[0]);
-        data.push(page_len[1]);
-        data.push(page_len[2]);
-        data.push(page_len[3]);
-
-        // next string: page
-        data.extend_from_slice(self.page.as_bytes());
-
-        // next two bytes: number of items
-        let (num_items_low, num_items_high) = crate::serialize_to_bytes(self.items.len());
-        data.push(num_items_low);
-        data.push(num_items_high);
-
-        for item in self.items.iter() {
-            // next two bytes: item name length
-            let (item_name_low, item_name_high) = crate::serialize_to_bytes(item.name.len());
-            data.push(item_name_low);
-            data.push(item_name_high);
-
-            // next string: item name
-            data.extend_from_slice(item.name.as_bytes());
-
-            // next four bytes: item length
-            let item_len = crate::serialize_to_four_bytes(item.data.len());
-            data.push(item_len[0]);
-            data.push(item_len[1]);
-            data.push(item_len[2]);
-            data.push(item_len[3]);
-
-            // next string: item data
-            data.extend_from_slice(&item.data);
+        // version 2 added the status
+        if self.version >= 2 {
+            data.push(self.status.for_version(self.version) as u8);
         }
 
+        write_body(&self.page, &self.items, &mut data);
+
         assert!(data.len() <= u32::MAX as usize);
 
         data
@@ -169,7 +222,8 @@ impl Into<Vec<u8>> for Response {
 
 #[rustfmt::skip]
 pub const DATA_REAL: &[u8] = &[
-    0x00,                                                                                   // version
+    0x03,                                                                                   // version
+    0x00,                                                                                   // status
     0x3c, 0x00, 0x00, 0x00,                                                                 // page len
     0x28, 0x69, 0x6d, 0x67, 0x20, 0x22, 0x77, 0x68, 0x69, 0x74, 0x65, 0x2e, 0x70, 0x6e,
     0x67, 0x22, 0x29, 0x0a, 0x28, 0x74, 0x78, 0x74, 0x20, 0x22, 0x66, 0x75, 0x67, 0x68,
@@ -203,6 +257,96 @@ pub const DATA_REAL: &[u8] = &[
     0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82u8,
 ];
 
+/// Read a page and its items, which follow the header of responses and publish requests.
+pub(crate) fn read_body(bytes: &mut impl Read) -> Result<(String, Vec<Item>), FroggiError> {
+    // page length, 4 bytes
+    let mut page_len = [0u8; 4];
+    bytes.read_exact(&mut page_len)?;
+    let page_len = crate::deserialize_four_bytes(&page_len);
+
+    // read page
+    let page = String::from_utf8(read_exactly(bytes, page_len)?)?;
+
+    // number of items, two bytes
+    let mut num_items = [0u8; 2];
+    bytes.read_exact(&mut num_items)?;
+    let num_items = crate::deserialize_bytes(&num_items);
+
+    // read items
+    let mut items = Vec::with_capacity(num_items);
+    for _ in 0..num_items {
+        // length of the item's name
+        let mut item_name_len = [0u8; 2];
+        bytes.read_exact(&mut item_name_len)?;
+        let item_name_len = crate::deserialize_bytes(&item_name_len);
+
+        // item name
+        let name = String::from_utf8(read_exactly(bytes, item_name_len)?)?;
+
+        // item length
+        let mut item_len = [0u8; 4];
+        bytes.read_exact(&mut item_len)?;
+        let item_len = crate::deserialize_four_bytes(&item_len);
+
+        // item
+        let data = read_exactly(bytes, item_len)?;
+
+        items.push(Item { name, data });
+    }
+
+    Ok((page, items))
+}
+
+/// Read `len` bytes, only allocating as they arrive so that a peer can't make us
+/// allocate more than it sends.
+fn read_exactly(bytes: &mut impl Read, len: usize) -> Result<Vec<u8>, FroggiError> {
+    let mut data = Vec::new();
+    bytes.take(len as u64).read_to_end(&mut data)?;
+    if data.len() < len {
+        return Err(FroggiError::io(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    Ok(data)
+}
+
+/// Write a page and its items in the layout [`read_body`] reads.
+pub(crate) fn write_body(page: &str, items: &[Item], data: &mut Vec<u8>) {
+    // next four bytes: page length
+    let page_len = crate::serialize_to_four_bytes(page.len());
+    data.push(page_len[0]);
+    data.push(page_len[1]);
+    data.push(page_len[2]);
+    data.push(page_len[3]);
+
+    // next string: page
+    data.extend_from_slice(page.as_bytes());
+
+    // next two bytes: number of items
+    let (num_items_low, num_items_high) = crate::serialize_to_bytes(items.len());
+    data.push(num_items_low);
+    data.push(num_items_high);
+
+    for item in items.iter() {
+        // next two bytes: item name length
+        let (item_name_low, item_name_high) = crate::serialize_to_bytes(item.name.len());
+        data.push(item_name_low);
+        data.push(item_name_high);
+
+        // next string: item name
+        data.extend_from_slice(item.name.as_bytes());
+
+        // next four bytes: item length
+        let item_len = crate::serialize_to_four_bytes(item.data.len());
+        data.push(item_len[0]);
+        data.push(item_len[1]);
+        data.push(item_len[2]);
+        data.push(item_len[3]);
+
+        // next string: item data
+        data.extend_from_slice(&item.data);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -247,4 +391,40 @@ mod test {
 
         crate::test::test_bytes(DATA_REAL, &data_test).unwrap();
     }
+
+    #[test]
+    fn status() {
+        let response = Response::error(Status::NotFound, "no page at \"a\\b\"");
+        response.parse().unwrap();
+        assert_eq!(response.message(), Some("no page at \"a\\b\""));
+
+        let bytes = Response::error(Status::NotFound, "").into_bytes();
+        let response = Response::from_bytes(&mut bytes.as_slice()).unwrap();
+        assert_eq!(response.status(), Status::NotFound);
+
+        // older clients don't know about the status
+        let mut old = Response::error(Status::NotFound, "");
+        old.set_version(1);
+        let bytes = old.into_bytes();
+        let response = Response::from_bytes(&mut bytes.as_slice()).unwrap();
+        assert_eq!(response.version(), 1);
+        assert_eq!(response.status(), Status::Ok);
+
+        // or the statuses added after theirs
+        let mut old = Response::error(Status::RateLimited, "");
+        old.set_version(2);
+        let bytes = old.into_bytes();
+        let response = Response::from_bytes(&mut bytes.as_slice()).unwrap();
+        assert_eq!(response.status(), Status::ServerError);
+
+        let mut old = Response::error(Status::Unauthorized, "");
+        old.set_version(2);
+        let bytes = old.into_bytes();
+        let response = Response::from_bytes(&mut bytes.as_slice()).unwrap();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let mut bytes = Response::new(String::new(), Vec::new()).into_bytes();
+        bytes[1] = 200;
+        assert!(Response::from_bytes(&mut bytes.as_slice()).is_err());
+    }
 }
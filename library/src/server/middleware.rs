@@ -0,0 +1,178 @@
+use crate::request::Request;
+use crate::response::{Response, Status};
+use crate::FroggiError;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What was sent in reply to a request.
+#[derive(Debug, Clone, Copy)]
+pub struct Sent {
+    pub status: Status,
+    /// Size of the whole response, including items.
+    pub bytes: usize,
+    /// Time from accepting the connection to writing the last byte.
+    pub duration: Duration,
+}
+
+/// Hooks that run around every request handled by a [`Server`](super::Server).
+pub trait Middleware: Send + Sync + 'static {
+    /// Called when a client connects, before its request is read. Returning false
+    /// closes the connection without a response.
+    fn connect(&self, _peer: SocketAddr) -> bool {
+        true
+    }
+
+    /// Called when a connection that was allowed by `connect` closes.
+    fn disconnect(&self, _peer: SocketAddr) {}
+
+    /// Called before the handler. Returning a response skips the handler and any
+    /// remaining middleware's `before`.
+    fn before(&self, _request: &Request) -> Option<Response> {
+        None
+    }
+
+    /// Called after a response has been produced, before it is sent.
+    fn after(&self, _request: &Request, _response: &mut Response) {}
+
+    /// Called once the response has been sent.
+    fn complete(&self, _request: &Request, _sent: &Sent) {}
+
+    /// Called when a connection fails before a response could be sent.
+    fn error(&self, _peer: Option<SocketAddr>, _error: &FroggiError) {}
+}
+
+/// Middleware that's shared with handlers that need to ask it things.
+impl<M: Middleware> Middleware for Arc<M> {
+    fn connect(&self, peer: SocketAddr) -> bool {
+        (**self).connect(peer)
+    }
+
+    fn disconnect(&self, peer: SocketAddr) {
+        (**self).disconnect(peer)
+    }
+
+    fn before(&self, request: &Request) -> Option<Response> {
+        (**self).before(request)
+    }
+
+    fn after(&self, request: &Request, response: &mut Response) {
+        (**self).after(request, response)
+    }
+
+    fn complete(&self, request: &Request, sent: &Sent) {
+        (**self).complete(request, sent)
+    }
+
+    fn error(&self, peer: Option<SocketAddr>, error: &FroggiError) {
+        (**self).error(peer, error)
+    }
+}
+
+/// Logs each request and any connection errors.
+pub struct Logger {
+    sink: Box<dyn Fn(&str) + Send + Sync>,
+}
+
+impl Logger {
+    /// Log to stdout.
+    pub fn stdout() -> Logger {
+        Logger::new(|line| println!("{}", line))
+    }
+
+    /// Log to an arbitrary sink, one line at a time.
+    pub fn new(sink: impl Fn(&str) + Send + Sync + 'static) -> Logger {
+        Logger {
+            sink: Box::new(sink),
+        }
+    }
+}
+
+impl Middleware for Logger {
+    fn complete(&self, request: &Request, sent: &Sent) {
+        (self.sink)(&format!(
+            "{} request (version {}, length {}): {} - {}, {} bytes in {:.1}ms",
+            peer_name(request.peer()),
+            request.version(),
+            request.raw_path().len(),
+            request.raw_path(),
+            sent.status,
+            sent.bytes,
+            sent.duration.as_secs_f64() * 1000.,
+        ));
+    }
+
+    fn error(&self, peer: Option<SocketAddr>, error: &FroggiError) {
+        (self.sink)(&format!("{} error: {}", peer_name(peer), error));
+    }
+}
+
+fn peer_name(peer: Option<SocketAddr>) -> String {
+    peer.map(|peer| peer.to_string())
+        .unwrap_or_else(|| String::from("unknown peer"))
+}
+
+/// Rejects requests and responses that are too large.
+pub struct Limits {
+    pub max_path_length: usize,
+    pub max_response_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_path_length: u16::MAX as usize,
+            max_response_size: u32::MAX as usize,
+        }
+    }
+}
+
+impl Middleware for Limits {
+    fn before(&self, request: &Request) -> Option<Response> {
+        if request.raw_path().len() > self.max_path_length {
+            Some(Response::error(Status::BadRequest, "request path too long"))
+        } else {
+            None
+        }
+    }
+
+    fn after(&self, _: &Request, response: &mut Response) {
+        let size = response.page().len()
+            + response
+                .items()
+                .iter()
+                .map(|item| item.name().len() + item.data().len())
+                .sum::<usize>();
+
+        if size > self.max_response_size {
+            *response = Response::error(Status::ServerError, "response too large");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let limits = Limits {
+            max_path_length: 3,
+            max_response_size: 10,
+        };
+
+        assert!(limits.before(&Request::new("abc").unwrap()).is_none());
+        assert!(limits.before(&Request::new("abcd").unwrap()).is_some());
+
+        let request = Request::new("abc").unwrap();
+        let mut response = Response::new(String::from("(\"short\")"), Vec::new());
+        limits.after(&request, &mut response);
+        assert_eq!(response.page(), "(\"short\")");
+
+        let mut response = Response::new(String::from("(\"too long\")"), Vec::new());
+        limits.after(&request, &mut response);
+        assert_eq!(response.status(), Status::ServerError);
+        assert_eq!(response.message(), Some("response too large"));
+    }
+}
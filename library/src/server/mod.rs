@@ -0,0 +1,487 @@
+//! A small framework for writing froggi servers.
+//!
+//! ```no_run
+//! use froggi::response::Response;
+//! use froggi::server::{Router, Server};
+//!
+//! let router = Router::new().route("/hello/:name", |_request, params| {
+//!     let page = format!("(\"hello, {}\")", params.get("name").unwrap());
+//!     Response::new(page, Vec::new())
+//! });
+//!
+//! Server::bind("0.0.0.0:11121").unwrap().serve(router).unwrap();
+//! ```
+
+pub mod middleware;
+pub mod rate_limit;
+pub mod router;
+
+pub use middleware::{Limits, Logger, Middleware, Sent};
+pub use rate_limit::{Network, RateLimit};
+pub use router::{Params, Router, VirtualHosts};
+
+use crate::request::Request;
+use crate::response::{Response, Status};
+use crate::{AddMsg, ErrorKind, FroggiError};
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Something that can produce a response for a request.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: &Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
+
+/// Listens for froggi requests and hands them to a [`Handler`].
+pub struct Server {
+    listeners: Vec<TcpListener>,
+    middleware: Vec<Box<dyn Middleware>>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    request_deadline: Option<Duration>,
+    max_connections: usize,
+    max_request_size: u64,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Server {
+    /// Listen on every address that `addrs` resolves to.
+    pub fn bind(addrs: impl ToSocketAddrs) -> Result<Server, FroggiError> {
+        let mut listeners = Vec::new();
+        for addr in addrs.to_socket_addrs()? {
+            listeners.push(TcpListener::bind(addr)?);
+        }
+
+        Ok(Server {
+            listeners,
+            middleware: Vec::new(),
+            read_timeout: None,
+            write_timeout: None,
+            request_deadline: None,
+            max_connections: usize::MAX,
+            max_request_size: u64::MAX,
+            #[cfg(feature = "tls")]
+            tls: None,
+        })
+    }
+
+    /// Add a middleware. Middleware runs in the order it was added.
+    pub fn middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// How long to wait for a client to send its request.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a client has to send its whole request, however slowly it trickles in.
+    pub fn request_deadline(mut self, deadline: Duration) -> Self {
+        self.request_deadline = Some(deadline);
+        self
+    }
+
+    /// How long to wait for a client to accept the response.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// How many clients may be served at once. Further clients are disconnected.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Only accept TLS connections, presenting `identity` to clients. Clients may
+    /// present an identity of their own, which handlers see as
+    /// [`Request::client_fingerprint`].
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, identity: &crate::tls::Identity) -> Result<Self, FroggiError> {
+        self.tls = Some(crate::tls::server_config(identity)?);
+        Ok(self)
+    }
+
+    /// How many bytes a request may be, including any page it publishes. Clients that
+    /// send more are disconnected.
+    pub fn max_request_size(mut self, max_request_size: u64) -> Self {
+        self.max_request_size = max_request_size;
+        self
+    }
+
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
+
+    /// Serve requests forever, one thread per client.
+    pub fn serve(self, handler: impl Handler) -> Result<(), FroggiError> {
+        let shared = Arc::new(Shared {
+            handler: Box::new(handler),
+            middleware: self.middleware,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            request_deadline: self.request_deadline,
+            max_connections: self.max_connections,
+            max_request_size: self.max_request_size,
+            connections: AtomicUsize::new(0),
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+        });
+
+        let threads = self
+            .listeners
+            .into_iter()
+            .map(|listener| {
+                let shared = Arc::clone(&shared);
+                std::thread::spawn(move || accept(listener, shared))
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().expect("listener thread panicked")?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Shared {
+    handler: Box<dyn Handler>,
+    middleware: Vec<Box<dyn Middleware>>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    request_deadline: Option<Duration>,
+    max_connections: usize,
+    max_request_size: u64,
+    connections: AtomicUsize,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Shared {
+    fn connect(&self, peer: SocketAddr) -> bool {
+        for (i, middleware) in self.middleware.iter().enumerate() {
+            if !middleware.connect(peer) {
+                for earlier in &self.middleware[..i] {
+                    earlier.disconnect(peer);
+                }
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn disconnect(&self, peer: SocketAddr) {
+        for middleware in &self.middleware {
+            middleware.disconnect(peer);
+        }
+    }
+
+    fn error(&self, peer: Option<SocketAddr>, error: &FroggiError) {
+        for middleware in &self.middleware {
+            middleware.error(peer, error);
+        }
+    }
+}
+
+fn accept(listener: TcpListener, shared: Arc<Shared>) -> Result<(), FroggiError> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                shared.error(None, &error.into());
+                continue;
+            }
+        };
+
+        let peer = stream.peer_addr().ok();
+        if shared.connections.fetch_add(1, Ordering::SeqCst) >= shared.max_connections {
+            shared.connections.fetch_sub(1, Ordering::SeqCst);
+            shared.error(
+                peer,
+                &FroggiError::io(std::io::ErrorKind::ConnectionRefused.into())
+                    .msg_str("too many connections"),
+            );
+            continue;
+        }
+
+        if let Some(peer) = peer {
+            if !shared.connect(peer) {
+                shared.connections.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+        }
+
+        let shared = Arc::clone(&shared);
+        std::thread::spawn(move || {
+            if let Err(error) = serve_connection(stream, &shared) {
+                shared.error(peer, &error);
+            }
+            if let Some(peer) = peer {
+                shared.disconnect(peer);
+            }
+            shared.connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    Ok(())
+}
+
+fn serve_connection(stream: TcpStream, shared: &Shared) -> Result<(), FroggiError> {
+    let start = Instant::now();
+    let peer = stream.peer_addr().ok();
+    let span = tracing::info_span!("connection", peer = ?peer);
+    let _entered = span.enter();
+
+    stream.set_read_timeout(shared.read_timeout)?;
+    stream.set_write_timeout(shared.write_timeout)?;
+
+    // the deadline covers the TLS handshake too
+    let mut socket = Deadline {
+        stream: &stream,
+        timeout: shared.read_timeout,
+        deadline: shared.request_deadline.map(|deadline| start + deadline),
+    };
+
+    #[cfg(feature = "tls")]
+    if let Some(config) = &shared.tls {
+        let mut connection =
+            rustls::ServerConnection::new(Arc::clone(config)).map_err(crate::tls::error)?;
+        tracing::debug_span!("handshake").in_scope(|| -> Result<(), FroggiError> {
+            while connection.is_handshaking() {
+                connection.complete_io(&mut socket)?;
+            }
+            Ok(())
+        })?;
+
+        let fingerprint = connection
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .map(|certificate| crate::tls::fingerprint(&certificate.0));
+
+        let mut tls = rustls::Stream::new(&mut connection, &mut socket);
+        exchange(&mut tls, start, peer, fingerprint, shared)?;
+
+        connection.send_close_notify();
+        connection.complete_io(&mut socket)?;
+        return Ok(());
+    }
+
+    exchange(&mut socket, start, peer, None, shared)
+}
+
+/// Read a request from the client and send it a response.
+fn exchange(
+    stream: &mut (impl Read + Write),
+    start: Instant,
+    peer: Option<SocketAddr>,
+    fingerprint: Option<String>,
+    shared: &Shared,
+) -> Result<(), FroggiError> {
+    let mut request = tracing::debug_span!("decode")
+        .in_scope(|| Request::from_bytes(&mut Read::take(&mut *stream, shared.max_request_size)))?;
+    request.set_peer(peer);
+    request.set_client_fingerprint(fingerprint);
+
+    let mut response = tracing::debug_span!("handle", path = request.path()).in_scope(|| {
+        let mut response = shared
+            .middleware
+            .iter()
+            .find_map(|middleware| middleware.before(&request))
+            .unwrap_or_else(|| handle(shared, &request));
+
+        for middleware in &shared.middleware {
+            middleware.after(&request, &mut response);
+        }
+
+        response
+    });
+
+    // answer in the client's version so it can understand us
+    response.set_version(request.version());
+    let status = response.status();
+
+    let bytes = tracing::debug_span!("encode").in_scope(|| {
+        let bytes = response.into_bytes();
+        stream.write_all(&bytes)?;
+        stream.flush().map(|_| bytes.len())
+    })?;
+
+    let sent = Sent {
+        status,
+        bytes,
+        duration: start.elapsed(),
+    };
+
+    for middleware in &shared.middleware {
+        middleware.complete(&request, &sent);
+    }
+
+    Ok(())
+}
+
+/// Reads from a stream, failing once a deadline has passed no matter how slowly the
+/// client is sending.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|remaining| *remaining > Duration::from_secs(0))
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "the client took too long to send its request",
+                    )
+                })?;
+
+            let timeout = self
+                .timeout
+                .map_or(remaining, |timeout| timeout.min(remaining));
+            self.stream.set_read_timeout(Some(timeout))?;
+        }
+
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Deadline<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Run the handler, turning a panic into an error response so the client isn't left
+/// with a dropped connection.
+fn handle(shared: &Shared, request: &Request) -> Response {
+    match std::panic::catch_unwind(AssertUnwindSafe(|| shared.handler.handle(request))) {
+        Ok(response) => response,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("unknown cause"));
+
+            shared.error(
+                request.peer(),
+                &FroggiError::new(ErrorKind::HandlerPanic).msg(message),
+            );
+            Response::error(
+                Status::ServerError,
+                "the server failed to handle the request",
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let router = Router::new().route("/echo/*rest", |_: &Request, params: &Params| {
+            Response::new(format!("(\"{}\")", params.get("rest").unwrap()), Vec::new())
+        });
+
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addrs()[0];
+        std::thread::spawn(move || server.serve(router));
+
+        let response = crate::send_request(addr, "echo/a/b").unwrap();
+        assert_eq!(response.page(), "(\"a/b\")");
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = crate::send_request(addr, "nothing").unwrap();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn slow_clients_are_dropped() {
+        let server = Server::bind("127.0.0.1:0")
+            .unwrap()
+            .request_deadline(Duration::from_millis(300));
+        let addr = server.local_addrs()[0];
+        std::thread::spawn(move || {
+            server.serve(|_: &Request| Response::new(String::new(), Vec::new()))
+        });
+
+        // one byte at a time, each well within any per-read timeout
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let request = Request::new("index.fml").unwrap().into_bytes();
+        for byte in &request {
+            if stream.write_all(&[*byte]).is_err() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        // the server gave up before the request was finished
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn large_requests_are_dropped() {
+        let server = Server::bind("127.0.0.1:0").unwrap().max_request_size(64);
+        let addr = server.local_addrs()[0];
+        std::thread::spawn(move || {
+            server.serve(|_: &Request| Response::new(String::new(), Vec::new()))
+        });
+
+        let upload = crate::request::Upload::new(String::from("(\"frog\")"), Vec::new());
+        let request = Request::publish("frog.fml", upload.clone()).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&request.into_bytes()).unwrap();
+        assert!(Response::from_bytes(&mut stream).is_ok());
+
+        let upload = crate::request::Upload::new(String::from("(\"frog\")").repeat(10), Vec::new());
+        let request = Request::publish("frog.fml", upload).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let _ = stream.write_all(&request.into_bytes());
+        assert!(Response::from_bytes(&mut stream).is_err());
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addrs()[0];
+        std::thread::spawn(move || server.serve(|_: &Request| -> Response { panic!("oops") }));
+
+        let response = crate::send_request(addr, "").unwrap();
+        assert_eq!(response.status(), Status::ServerError);
+        response.parse().unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+use super::Middleware;
+use crate::request::Request;
+use crate::response::{Response, Status};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// A range of addresses, like `203.0.113.0/24` or `2001:db8::/32`. A bare address is
+/// a network of just that address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Network> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        if prefix > max {
+            None
+        } else {
+            Some(Network {
+                addr: mask(addr, prefix),
+                prefix,
+            })
+        }
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, canonical(addr)) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                mask(canonical(addr), self.prefix) == self.addr
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Network, ()> {
+        match s.find('/') {
+            Some(i) => {
+                let addr = s[..i].parse::<IpAddr>().map_err(|_| ())?;
+                let prefix = s[i + 1..].parse::<u8>().map_err(|_| ())?;
+                Network::new(canonical(addr), prefix).ok_or(())
+            }
+            None => {
+                let addr = canonical(s.parse::<IpAddr>().map_err(|_| ())?);
+                let prefix = if addr.is_ipv4() { 32 } else { 128 };
+                Network::new(addr, prefix).ok_or(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+// ::ffff:1.2.3.4 is the same client as 1.2.3.4
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4() {
+            Some(v4) if v6.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => IpAddr::V4(v4),
+            _ => addr,
+        },
+        _ => addr,
+    }
+}
+
+fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(v4);
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            IpAddr::V4((bits & mask).into())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(v6);
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(bits & mask))
+        }
+    }
+}
+
+/// The address that a client is limited by. IPv6 clients usually get a whole /64, so
+/// they're limited together.
+fn client_key(addr: IpAddr) -> IpAddr {
+    match canonical(addr) {
+        IpAddr::V6(v6) => mask(IpAddr::V6(v6), 64),
+        v4 => v4,
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Default)]
+struct Client {
+    bucket: Option<Bucket>,
+    connections: usize,
+}
+
+/// Limits how often and how many times at once each client may connect, and turns
+/// away banned clients.
+///
+/// Each client has a bucket of `burst` tokens which refills at `per_second` tokens a
+/// second. A request takes a token, and clients with an empty bucket get a
+/// [`Status::RateLimited`] response. Clients with too many open connections, and
+/// banned clients, are disconnected without a response.
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+    max_connections: usize,
+    banned: Vec<Network>,
+    clients: Mutex<HashMap<IpAddr, Client>>,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u32) -> RateLimit {
+        RateLimit {
+            per_second,
+            burst: burst as f64,
+            max_connections: usize::MAX,
+            banned: Vec::new(),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Don't limit requests, only connections and bans.
+    pub fn unlimited() -> RateLimit {
+        RateLimit {
+            burst: f64::INFINITY,
+            ..RateLimit::new(0., 0)
+        }
+    }
+
+    /// How many connections each client may have open at once.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Refuse every connection from a network.
+    pub fn ban(mut self, network: Network) -> Self {
+        self.banned.push(network);
+        self
+    }
+
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.banned.iter().any(|network| network.contains(addr))
+    }
+
+    fn take(&self, addr: IpAddr, now: Instant) -> bool {
+        if self.burst.is_infinite() {
+            return true;
+        }
+
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients.entry(client_key(addr)).or_default();
+        let bucket = client.bucket.get_or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+
+    // forget clients that have gone quiet so the map doesn't grow forever
+    fn prune(&self, clients: &mut HashMap<IpAddr, Client>, now: Instant) {
+        let (per_second, burst) = (self.per_second, self.burst);
+        clients.retain(|_, client| match &client.bucket {
+            _ if client.connections > 0 => true,
+            Some(bucket) => {
+                let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * per_second < burst
+            }
+            None => false,
+        });
+    }
+}
+
+const PRUNE_THRESHOLD: usize = 4096;
+
+impl Middleware for RateLimit {
+    fn connect(&self, peer: SocketAddr) -> bool {
+        if self.is_banned(peer.ip()) {
+            return false;
+        }
+
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= PRUNE_THRESHOLD {
+            self.prune(&mut clients, Instant::now());
+        }
+
+        let client = clients.entry(client_key(peer.ip())).or_default();
+        if client.connections >= self.max_connections {
+            return false;
+        }
+
+        client.connections += 1;
+        true
+    }
+
+    fn disconnect(&self, peer: SocketAddr) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&client_key(peer.ip())) {
+            client.connections = client.connections.saturating_sub(1);
+        }
+    }
+
+    fn before(&self, request: &Request) -> Option<Response> {
+        let peer = request.peer()?;
+        if self.take(peer.ip(), Instant::now()) {
+            None
+        } else {
+            Some(Response::error(
+                Status::RateLimited,
+                "too many requests, try again later",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn networks() {
+        let network = "203.0.113.7/24".parse::<Network>().unwrap();
+        assert_eq!(network.to_string(), "203.0.113.0/24");
+        assert!(network.contains("203.0.113.200".parse().unwrap()));
+        assert!(network.contains("::ffff:203.0.113.1".parse().unwrap()));
+        assert!(!network.contains("203.0.114.1".parse().unwrap()));
+        assert!(!network.contains("2001:db8::1".parse().unwrap()));
+
+        let network = "2001:db8::/32".parse::<Network>().unwrap();
+        assert!(network.contains("2001:db8:1:2::3".parse().unwrap()));
+        assert!(!network.contains("2001:db9::".parse().unwrap()));
+
+        let single = "192.0.2.1".parse::<Network>().unwrap();
+        assert!(single.contains("192.0.2.1".parse().unwrap()));
+        assert!(!single.contains("192.0.2.2".parse().unwrap()));
+
+        assert!("0.0.0.0/0"
+            .parse::<Network>()
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!("192.0.2.1/33".parse::<Network>().is_err());
+        assert!("localhost".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn buckets() {
+        let limit = RateLimit::new(2., 3);
+        let now = Instant::now();
+        let addr = "192.0.2.1".parse().unwrap();
+
+        assert!(limit.take(addr, now));
+        assert!(limit.take(addr, now));
+        assert!(limit.take(addr, now));
+        assert!(!limit.take(addr, now));
+        assert!(limit.take("192.0.2.2".parse().unwrap(), now));
+
+        // refills at two tokens a second
+        let later = now + Duration::from_millis(500);
+        assert!(limit.take(addr, later));
+        assert!(!limit.take(addr, later));
+    }
+
+    #[test]
+    fn ipv6_clients_share_a_bucket() {
+        let limit = RateLimit::new(0., 1);
+        let now = Instant::now();
+
+        assert!(limit.take("2001:db8:0:1::1".parse().unwrap(), now));
+        assert!(!limit.take("2001:db8:0:1::2".parse().unwrap(), now));
+        assert!(limit.take("2001:db8:0:2::1".parse().unwrap(), now));
+    }
+
+    #[test]
+    fn connections() {
+        let limit = RateLimit::new(1., 1)
+            .max_connections(1)
+            .ban("198.51.100.0/24".parse().unwrap());
+
+        let peer = "192.0.2.1:1000".parse().unwrap();
+        assert!(limit.connect(peer));
+        assert!(!limit.connect("192.0.2.1:1001".parse().unwrap()));
+        limit.disconnect(peer);
+        assert!(limit.connect(peer));
+
+        assert!(!limit.connect("198.51.100.9:1000".parse().unwrap()));
+    }
+}
@@ -0,0 +1,253 @@
+use super::Handler;
+use crate::request::Request;
+use crate::response::{Response, Status};
+
+/// Path segments captured by a route pattern.
+#[derive(Debug, Default, PartialEq)]
+pub struct Params {
+    params: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+type RouteFn = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync>;
+
+/// Dispatches requests to handlers by path.
+///
+/// Patterns are split on `/`. A segment starting with `:` captures one path segment,
+/// and a final segment starting with `*` captures the rest of the path, which may be
+/// empty. Routes are tried in the order they were added.
+pub struct Router {
+    routes: Vec<(Vec<Segment>, RouteFn)>,
+    fallback: Box<dyn Handler>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            fallback: Box::new(|request: &Request| {
+                Response::error(Status::NotFound, format!("no page at {:?}", request.path()))
+            }),
+        }
+    }
+
+    /// Add a route.
+    ///
+    /// # Panics
+    /// If a `*` segment is not the last segment of the pattern.
+    pub fn route(
+        mut self,
+        pattern: &str,
+        handler: impl Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let segments = parse_pattern(pattern);
+        if let Some(i) = segments
+            .iter()
+            .position(|segment| matches!(segment, Segment::Rest(_)))
+        {
+            assert_eq!(
+                i + 1,
+                segments.len(),
+                "* must be the last segment of {}",
+                pattern
+            );
+        }
+
+        self.routes.push((segments, Box::new(handler)));
+        self
+    }
+
+    /// Add a route that ignores its parameters.
+    pub fn mount(self, pattern: &str, handler: impl Handler) -> Self {
+        self.route(pattern, move |request, _| handler.handle(request))
+    }
+
+    /// Set the handler for requests that match no route.
+    pub fn fallback(mut self, handler: impl Handler) -> Self {
+        self.fallback = Box::new(handler);
+        self
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &Request) -> Response {
+        for (pattern, handler) in &self.routes {
+            if let Some(params) = match_path(pattern, request.path()) {
+                return handler(request, &params);
+            }
+        }
+
+        self.fallback.handle(request)
+    }
+}
+
+/// Dispatches requests to handlers by the host they were sent to.
+///
+/// Requests without a host, such as those from version 0 clients, and requests for
+/// hosts that were not added go to the default handler. Host names are compared
+/// case-insensitively.
+pub struct VirtualHosts {
+    hosts: Vec<(String, Box<dyn Handler>)>,
+    default: Box<dyn Handler>,
+}
+
+impl VirtualHosts {
+    pub fn new(default: impl Handler) -> VirtualHosts {
+        VirtualHosts {
+            hosts: Vec::new(),
+            default: Box::new(default),
+        }
+    }
+
+    pub fn host(mut self, name: &str, handler: impl Handler) -> Self {
+        self.hosts.push((name.to_lowercase(), Box::new(handler)));
+        self
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: &Request) -> Response {
+        let handler = request.host().and_then(|host| {
+            self.hosts
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(host))
+                .map(|(_, handler)| handler)
+        });
+
+        handler.unwrap_or(&self.default).handle(request)
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.into())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Rest(name.into())
+            } else {
+                Segment::Literal(segment.into())
+            }
+        })
+        .collect()
+}
+
+fn match_path(pattern: &[Segment], path: &str) -> Option<Params> {
+    let mut params = Params::default();
+    let mut path = path.split('/').filter(|segment| !segment.is_empty());
+
+    for segment in pattern {
+        match segment {
+            Segment::Literal(literal) => {
+                if path.next()? != literal {
+                    return None;
+                }
+            }
+
+            Segment::Param(name) => {
+                params.params.push((name.clone(), path.next()?.into()));
+            }
+
+            Segment::Rest(name) => {
+                let rest = path.by_ref().collect::<Vec<_>>().join("/");
+                params.params.push((name.clone(), rest));
+            }
+        }
+    }
+
+    if path.next().is_none() {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn literal() {
+        let pattern = parse_pattern("/a/b");
+        assert!(match_path(&pattern, "a/b").is_some());
+        assert!(match_path(&pattern, "/a/b/").is_some());
+        assert!(match_path(&pattern, "a").is_none());
+        assert!(match_path(&pattern, "a/b/c").is_none());
+    }
+
+    #[test]
+    fn captures() {
+        let pattern = parse_pattern("/users/:name/*page");
+        let params = match_path(&pattern, "/users/zack/posts/1.fml").unwrap();
+        assert_eq!(params.get("name"), Some("zack"));
+        assert_eq!(params.get("page"), Some("posts/1.fml"));
+
+        let params = match_path(&pattern, "/users/zack").unwrap();
+        assert_eq!(params.get("page"), Some(""));
+
+        assert!(match_path(&pattern, "/users").is_none());
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let router = Router::new()
+            .route("/a", |_, _| Response::new("(\"a\")".into(), Vec::new()))
+            .route("/*rest", |_, _| {
+                Response::new("(\"rest\")".into(), Vec::new())
+            });
+
+        let request = Request::new("a").unwrap();
+        assert_eq!(router.handle(&request).page(), "(\"a\")");
+
+        let request = Request::new("b").unwrap();
+        assert_eq!(router.handle(&request).page(), "(\"rest\")");
+    }
+
+    #[test]
+    fn virtual_hosts() {
+        let page =
+            |page: &'static str| move |_: &Request| Response::new(String::from(page), Vec::new());
+
+        let hosts = VirtualHosts::new(page("default"))
+            .host("frog.example", page("frog"))
+            .host("toad.example", page("toad"));
+
+        let request = Request::with_host("", "TOAD.example:11121").unwrap();
+        assert_eq!(hosts.handle(&request).page(), "toad");
+
+        let request = Request::with_host("", "newt.example").unwrap();
+        assert_eq!(hosts.handle(&request).page(), "default");
+
+        let request = Request::new("").unwrap();
+        assert_eq!(hosts.handle(&request).page(), "default");
+    }
+}
@@ -0,0 +1,217 @@
+//! TLS for froggi connections, built with the `tls` feature.
+//!
+//! froggi doesn't use certificate authorities. Servers and clients both present
+//! self-signed certificates: a client remembers the certificate a host showed it the
+//! first time, and a client's certificate is an identity that it controls, which
+//! servers know it by. Identities are told apart by their [`fingerprint`].
+
+use crate::{AddMsg, ErrorKind, FroggiError};
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::server::{ClientCertVerified, ClientCertVerifier};
+use rustls::{Certificate, ClientConfig, DistinguishedName, PrivateKey, ServerConfig, ServerName};
+use sha2::{Digest, Sha256};
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// The fingerprint of a DER-encoded certificate, like `sha256:9f86d0...`.
+pub fn fingerprint(certificate: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(certificate)))
+}
+
+pub(crate) fn error(error: impl ToString) -> FroggiError {
+    FroggiError::new(ErrorKind::TlsError).msg(error.to_string())
+}
+
+/// A certificate and its private key.
+#[derive(Clone)]
+pub struct Identity {
+    name: String,
+    certificates: Vec<Vec<u8>>,
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Identity {{ name: {}, fingerprint: {} }}",
+            self.name,
+            self.fingerprint()
+        )
+    }
+}
+
+impl Identity {
+    /// Make a new self-signed identity.
+    pub fn generate(name: &str) -> Result<Identity, FroggiError> {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+
+        let certificate = rcgen::Certificate::from_params(params).map_err(error)?;
+        Ok(Identity {
+            name: name.into(),
+            certificates: vec![certificate.serialize_der().map_err(error)?],
+            key: certificate.serialize_private_key_der(),
+        })
+    }
+
+    /// Read an identity from PEM, with its certificates followed by its key.
+    pub fn from_pem(name: &str, pem: &str) -> Result<Identity, FroggiError> {
+        let mut certificates = Vec::new();
+        let mut key = None;
+        for item in rustls_pemfile::read_all(&mut pem.as_bytes())? {
+            match item {
+                rustls_pemfile::Item::X509Certificate(der) => certificates.push(der),
+                rustls_pemfile::Item::PKCS8Key(der)
+                | rustls_pemfile::Item::RSAKey(der)
+                | rustls_pemfile::Item::ECKey(der) => key = Some(der),
+                _ => {}
+            }
+        }
+
+        match (certificates.is_empty(), key) {
+            (false, Some(key)) => Ok(Identity {
+                name: name.into(),
+                certificates,
+                key,
+            }),
+            (true, _) => Err(error("no certificate in identity")),
+            (_, None) => Err(error("no private key in identity")),
+        }
+    }
+
+    /// Read an identity from a certificate file and a key file, both PEM.
+    pub fn from_pem_files(
+        name: &str,
+        certificate: &Path,
+        key: &Path,
+    ) -> Result<Identity, FroggiError> {
+        let mut pem = std::fs::read_to_string(certificate)?;
+        pem.push('\n');
+        pem.push_str(&std::fs::read_to_string(key)?);
+        Identity::from_pem(name, &pem)
+    }
+
+    /// Write the identity as PEM, so it can be read back by [`Identity::from_pem`].
+    ///
+    /// This includes the private key, so keep it secret.
+    pub fn to_pem(&self) -> String {
+        let mut items = self
+            .certificates
+            .iter()
+            .map(|der| pem::Pem::new("CERTIFICATE", der.clone()))
+            .collect::<Vec<_>>();
+        items.push(pem::Pem::new("PRIVATE KEY", self.key.clone()));
+        pem::encode_many(&items)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.certificates[0])
+    }
+
+    fn chain(&self) -> Vec<Certificate> {
+        self.certificates.iter().cloned().map(Certificate).collect()
+    }
+}
+
+/// Configuration for a server presenting `identity`, which asks clients for their
+/// identity but doesn't require one.
+pub fn server_config(identity: &Identity) -> Result<Arc<ServerConfig>, FroggiError> {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(Arc::new(AnyClient))
+        .with_single_cert(identity.chain(), PrivateKey(identity.key.clone()))
+        .map_err(error)?;
+    Ok(Arc::new(config))
+}
+
+/// Configuration for a client presenting `identity`, if any. Server certificates
+/// aren't checked here, the client compares them against the ones it's seen before.
+pub fn client_config(identity: Option<&Identity>) -> Result<Arc<ClientConfig>, FroggiError> {
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AnyServer));
+
+    let config = match identity {
+        Some(identity) => builder
+            .with_client_auth_cert(identity.chain(), PrivateKey(identity.key.clone()))
+            .map_err(error)?,
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Arc::new(config))
+}
+
+/// Accepts any client certificate. The handshake still proves the client has the
+/// certificate's private key.
+struct AnyClient;
+
+impl ClientCertVerifier for AnyClient {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+/// Accepts any server certificate. The handshake still proves the server has the
+/// certificate's private key.
+struct AnyServer;
+
+impl ServerCertVerifier for AnyServer {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identities() {
+        let identity = Identity::generate("frog").unwrap();
+        assert!(identity.fingerprint().starts_with("sha256:"));
+        assert_eq!(identity.fingerprint().len(), "sha256:".len() + 64);
+
+        let read = Identity::from_pem("frog", &identity.to_pem()).unwrap();
+        assert_eq!(read.fingerprint(), identity.fingerprint());
+        assert_ne!(
+            Identity::generate("frog").unwrap().fingerprint(),
+            identity.fingerprint()
+        );
+
+        assert!(Identity::from_pem("frog", "").is_err());
+        server_config(&identity).unwrap();
+        client_config(Some(&identity)).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+use froggi::request::Request;
+use froggi::server::{Middleware, Sent};
+use froggi::FroggiError;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::{AccessLog, LogFormat};
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Writes a line to the access log for every request. Connections that fail before a
+/// response is sent aren't requests, so they go to the server log instead.
+pub struct AccessLogger {
+    format: LogFormat,
+    file: Mutex<RotatingFile>,
+}
+
+impl AccessLogger {
+    pub fn new(config: &AccessLog) -> io::Result<AccessLogger> {
+        Ok(AccessLogger {
+            format: config.format,
+            file: Mutex::new(RotatingFile::open(
+                &config.path,
+                config.max_size,
+                config.keep,
+            )?),
+        })
+    }
+}
+
+impl Middleware for AccessLogger {
+    fn complete(&self, request: &Request, sent: &Sent) {
+        let line = Entry::new(SystemTime::now(), request, sent).format(self.format);
+        if let Err(error) = self.file.lock().unwrap().write_line(&line) {
+            warn!("could not write to the access log - {}", error);
+        }
+    }
+
+    fn error(&self, peer: Option<SocketAddr>, error: &FroggiError) {
+        match peer {
+            Some(peer) => warn!("{} error: {}", peer, error),
+            None => warn!("unknown peer error: {}", error),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: String,
+    peer: Option<String>,
+    host: Option<&'a str>,
+    path: &'a str,
+    version: u8,
+    status: String,
+    bytes: usize,
+    duration_ms: f64,
+}
+
+impl Entry<'_> {
+    fn new<'a>(time: SystemTime, request: &'a Request, sent: &Sent) -> Entry<'a> {
+        Entry {
+            timestamp: crate::time::format_rfc3339(time),
+            peer: request.peer().map(|peer| peer.to_string()),
+            host: request.host(),
+            path: request.raw_path(),
+            version: request.version(),
+            status: sent.status.to_string(),
+            bytes: sent.bytes,
+            duration_ms: sent.duration.as_secs_f64() * 1000.,
+        }
+    }
+
+    fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Json => serde_json::to_string(self).expect("entries are valid json"),
+            LogFormat::Human => format!(
+                "{} {} {} {:?} v{} {} {}b {:.1}ms",
+                self.timestamp,
+                self.peer.as_deref().unwrap_or("-"),
+                self.host.unwrap_or("-"),
+                self.path,
+                self.version,
+                self.status,
+                self.bytes,
+                self.duration_ms,
+            ),
+        }
+    }
+}
+
+/// A file that is moved to `name.1`, `name.2`, ... when it grows too large.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path: path.into(),
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..self.keep).rev() {
+                let from = self.rotated(i);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(i + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, i: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", i));
+        name.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::test::TempDir;
+    use froggi::response::Status;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn formats() {
+        let request = Request::with_host("/a \"b\"", "froggi.io").unwrap();
+        let sent = Sent {
+            status: Status::NotFound,
+            bytes: 40,
+            duration: Duration::from_micros(1500),
+        };
+
+        let entry = Entry::new(UNIX_EPOCH, &request, &sent);
+        assert_eq!(
+            entry.format(LogFormat::Human),
+            "1970-01-01T00:00:00.000Z - froggi.io \"/a \\\"b\\\"\" v3 not found 40b 1.5ms"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&entry.format(LogFormat::Json)).unwrap();
+        assert_eq!(json["path"], "/a \"b\"");
+        assert_eq!(json["peer"], serde_json::Value::Null);
+        assert_eq!(json["status"], "not found");
+        assert_eq!(json["bytes"], 40);
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = TempDir::new("froggi-access-log-test");
+
+        let path = dir.join("access.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in &["first", "second", "third", "fourth"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("access.log"), "fourth\n");
+        assert_eq!(read("access.log.1"), "third\n");
+        assert_eq!(read("access.log.2"), "second\n");
+        assert!(!dir.join("access.log.3").exists());
+    }
+}
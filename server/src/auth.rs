@@ -0,0 +1,273 @@
+use froggi::request::Request;
+use froggi::response::{Response, Status};
+use froggi::server::{Middleware, Network};
+use tracing::debug;
+
+use crate::config::AccessRule;
+
+use std::net::IpAddr;
+
+/// Turns away clients that may not see the pages they asked for.
+///
+/// Virtual hosts use their own rules if any of them match, otherwise the top-level
+/// ones. Of those, only the rule with the most specific path applies.
+pub struct AccessControl {
+    default: Vec<Rule>,
+    hosts: Vec<(String, Vec<Rule>)>,
+    default_host: Option<String>,
+}
+
+struct Rule {
+    path: String,
+    allow: Vec<Network>,
+    deny: Vec<Network>,
+    tokens: Vec<String>,
+}
+
+impl Rule {
+    fn new(rule: &AccessRule) -> Rule {
+        let networks = |entries: &[String]| {
+            entries
+                .iter()
+                .map(|entry| entry.parse().expect("validated network"))
+                .collect()
+        };
+
+        Rule {
+            path: rule.path.clone(),
+            allow: networks(&rule.allow),
+            deny: networks(&rule.deny),
+            tokens: rule.tokens.clone(),
+        }
+    }
+
+    fn admits(&self, addr: IpAddr) -> bool {
+        let allowed =
+            self.allow.is_empty() || self.allow.iter().any(|network| network.contains(addr));
+        allowed && !self.deny.iter().any(|network| network.contains(addr))
+    }
+
+    /// Whether the client that sent `request` may see the page at `path`.
+    fn check(&self, request: &Request, path: &str) -> Result<(), Response> {
+        match request.peer() {
+            Some(peer) if !self.admits(peer.ip()) => {
+                return Err(Response::error(
+                    Status::Forbidden,
+                    format!("{} may not be viewed from {}", path, peer.ip()),
+                ));
+            }
+            _ => {}
+        }
+
+        if self.tokens.is_empty() {
+            return Ok(());
+        }
+
+        match request.token() {
+            Some(sent) if self.tokens.iter().any(|token| same(token, sent)) => Ok(()),
+            Some(_) => Err(Response::error(
+                Status::Unauthorized,
+                format!("the token for {} was not accepted", path),
+            )),
+            None => Err(Response::error(
+                Status::Unauthorized,
+                format!("{} needs a token", path),
+            )),
+        }
+    }
+}
+
+impl AccessControl {
+    pub fn new(default: &[AccessRule]) -> AccessControl {
+        AccessControl {
+            default: default.iter().map(Rule::new).collect(),
+            hosts: Vec::new(),
+            default_host: None,
+        }
+    }
+
+    /// The host whose rules are used for requests without a configured hostname.
+    pub fn default_host(mut self, name: &str) -> Self {
+        self.default_host = Some(name.into());
+        self
+    }
+
+    pub fn host(mut self, name: &str, rules: &[AccessRule]) -> Self {
+        self.hosts
+            .push((name.into(), rules.iter().map(Rule::new).collect()));
+        self
+    }
+
+    /// Whether the client that sent `request` may see another page on the same host,
+    /// for pages that list other pages, like search results and feeds.
+    pub fn allows(&self, request: &Request, path: &str) -> bool {
+        match self.find(request.host(), path) {
+            Some(rule) => rule.check(request, path).is_ok(),
+            None => true,
+        }
+    }
+
+    fn find(&self, host: Option<&str>, request_path: &str) -> Option<&Rule> {
+        let find_host = |host: &str| {
+            self.hosts
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        };
+
+        let host_rules = host
+            .and_then(find_host)
+            .or_else(|| self.default_host.as_deref().and_then(find_host));
+
+        host_rules
+            .and_then(|(_, rules)| best_match(rules, request_path))
+            .or_else(|| best_match(&self.default, request_path))
+    }
+}
+
+fn best_match<'a>(rules: &'a [Rule], request_path: &str) -> Option<&'a Rule> {
+    let path = crate::segments(request_path);
+    rules
+        .iter()
+        .filter(|rule| within(&path, &crate::segments(&rule.path)))
+        .max_by_key(|rule| crate::segments(&rule.path).len())
+}
+
+/// Whether `path` is `rule_path` or inside it. Segments are compared without case, since
+/// on some filesystems `/TEAM/notes.fml` opens the same file as `/team/notes.fml`.
+fn within(path: &[&str], rule_path: &[&str]) -> bool {
+    path.len() >= rule_path.len()
+        && path
+            .iter()
+            .zip(rule_path)
+            .all(|(segment, rule_segment)| segment.to_lowercase() == rule_segment.to_lowercase())
+}
+
+/// Compare tokens in the same time wherever they differ, so that they can't be
+/// guessed a character at a time.
+pub fn same(token: &str, sent: &str) -> bool {
+    token.len() == sent.len()
+        && token
+            .bytes()
+            .zip(sent.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+impl Middleware for AccessControl {
+    fn before(&self, request: &Request) -> Option<Response> {
+        let rule = self.find(request.host(), request.path())?;
+        match rule.check(request, request.path()) {
+            Ok(()) => None,
+            Err(response) => {
+                debug!("{}", response.message().unwrap_or_default());
+                Some(response)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(path: &str, allow: &[&str], deny: &[&str], tokens: &[&str]) -> AccessRule {
+        let strings = |list: &[&str]| list.iter().map(|s| s.to_string()).collect();
+        AccessRule {
+            path: path.into(),
+            allow: strings(allow),
+            deny: strings(deny),
+            tokens: strings(tokens),
+        }
+    }
+
+    fn status(access: &AccessControl, request: &Request) -> Status {
+        match access.before(request) {
+            Some(response) => response.status(),
+            None => Status::Ok,
+        }
+    }
+
+    #[test]
+    fn most_specific_rule_applies() {
+        let access = AccessControl::new(&[
+            rule("/", &[], &["203.0.113.0/24"], &[]),
+            rule("/team", &[], &[], &["frog", "toad"]),
+            rule("/team/public", &[], &[], &[]),
+        ]);
+
+        let mut request = Request::new("/team/notes.fml").unwrap();
+        assert_eq!(status(&access, &request), Status::Unauthorized);
+
+        request.set_metadata("token", "newt").unwrap();
+        assert_eq!(status(&access, &request), Status::Unauthorized);
+
+        request.set_metadata("token", "toad").unwrap();
+        assert_eq!(status(&access, &request), Status::Ok);
+
+        let request = Request::new("/team/public/index.fml").unwrap();
+        assert_eq!(status(&access, &request), Status::Ok);
+
+        let request = Request::new("/teams.fml").unwrap();
+        assert_eq!(status(&access, &request), Status::Ok);
+    }
+
+    #[test]
+    fn paths_ignore_case() {
+        let access =
+            AccessControl::new(&[rule("/", &[], &[], &[]), rule("/team", &[], &[], &["frog"])]);
+
+        for path in &["/TEAM/notes.fml", "/Team/notes.fml", "/team/NOTES.FML"] {
+            let request = Request::new(path).unwrap();
+            assert_eq!(status(&access, &request), Status::Unauthorized);
+        }
+
+        let request = Request::new("/").unwrap();
+        assert!(!access.allows(&request, "/TEAM/notes.fml"));
+    }
+
+    #[test]
+    fn hosts_have_their_own_rules() {
+        let access = AccessControl::new(&[rule("/", &[], &[], &["everyone"])])
+            .host("internal", &[rule("/", &[], &[], &["team"])]);
+
+        let mut request = Request::with_host("/", "internal").unwrap();
+        request.set_metadata("token", "team").unwrap();
+        assert_eq!(status(&access, &request), Status::Ok);
+
+        request.set_metadata("token", "everyone").unwrap();
+        assert_eq!(status(&access, &request), Status::Unauthorized);
+
+        let mut request = Request::with_host("/", "froggi.io").unwrap();
+        request.set_metadata("token", "everyone").unwrap();
+        assert_eq!(status(&access, &request), Status::Ok);
+    }
+
+    #[test]
+    fn other_pages() {
+        let access = AccessControl::new(&[rule("/team", &[], &[], &["frog"])]);
+
+        let mut request = Request::new("/search?notes").unwrap();
+        assert!(access.allows(&request, "/index.fml"));
+        assert!(!access.allows(&request, "/team/notes.fml"));
+
+        request.set_metadata("token", "frog").unwrap();
+        assert!(access.allows(&request, "/team/notes.fml"));
+    }
+
+    #[test]
+    fn networks() {
+        let rule = Rule::new(&rule("/", &["10.0.0.0/8"], &["10.0.0.13"], &[]));
+        assert!(rule.admits("10.1.2.3".parse().unwrap()));
+        assert!(!rule.admits("10.0.0.13".parse().unwrap()));
+        assert!(!rule.admits("192.0.2.1".parse().unwrap()));
+        assert!(!rule.admits("::ffff:10.0.0.13".parse().unwrap()));
+    }
+
+    #[test]
+    fn tokens_compare() {
+        assert!(same("frog", "frog"));
+        assert!(!same("frog", "frig"));
+        assert!(!same("frog", "frogs"));
+        assert!(!same("frog", ""));
+    }
+}
@@ -0,0 +1,417 @@
+use froggi::request::Request;
+use froggi::response::{Response, Status};
+use froggi::FroggiError;
+use tracing::warn;
+
+use std::fmt;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Error produced while running a CGI script.
+#[derive(Debug)]
+pub enum CgiError {
+    NotFound {
+        script: String,
+    },
+    Spawn {
+        script: PathBuf,
+        error: std::io::Error,
+    },
+    Timeout {
+        script: PathBuf,
+        timeout: Duration,
+    },
+    TooLarge {
+        script: PathBuf,
+        max_output: usize,
+    },
+    Failed {
+        script: PathBuf,
+        status: ExitStatus,
+    },
+    Encoding {
+        script: PathBuf,
+    },
+    Markup {
+        script: PathBuf,
+        errors: Vec<FroggiError>,
+    },
+}
+
+impl CgiError {
+    pub fn status(&self) -> Status {
+        match self {
+            CgiError::NotFound { .. } => Status::NotFound,
+            _ => Status::ServerError,
+        }
+    }
+
+    /// What to tell the client, which leaves out the server's own paths.
+    pub fn message(&self) -> String {
+        match self {
+            CgiError::NotFound { .. } => self.to_string(),
+            _ => String::from("the script could not make a page"),
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl fmt::Display for CgiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CgiError::NotFound { script }
+                => write!(f, "no script named {:?}", script),
+            CgiError::Spawn { script, error }
+                => write!(f, "could not run {} - {}", script.display(), error),
+            CgiError::Timeout { script, timeout }
+                => write!(f, "{} did not finish within {} seconds", script.display(), timeout.as_secs()),
+            CgiError::TooLarge { script, max_output }
+                => write!(f, "{} wrote more than {} bytes", script.display(), max_output),
+            CgiError::Failed { script, status }
+                => write!(f, "{} failed - {}", script.display(), status),
+            CgiError::Encoding { script }
+                => write!(f, "{} did not write utf8", script.display()),
+            CgiError::Markup { script, errors }
+                => write!(f, "{} wrote {} markup error(s), the first is: {}", script.display(), errors.len(), errors[0]),
+        }
+    }
+}
+
+/// Runs executables from a directory to produce pages.
+///
+/// The script gets the request in its environment:
+///
+/// * `FROGGI_VERSION` - protocol version of the request
+/// * `FROGGI_PATH` - the full request path, without the query
+/// * `FROGGI_SCRIPT` - the name of the script
+/// * `FROGGI_PATH_INFO` - the part of the path after the script name
+/// * `FROGGI_QUERY` - the part of the path after `?`, if any
+/// * `FROGGI_HOST` - the hostname the client asked for, without any port, if it
+///   sent one
+/// * `FROGGI_META_<KEY>` - each metadata entry of the request, like `FROGGI_META_TOKEN`,
+///   with the key in upper case and anything but letters and digits replaced by `_`
+/// * `FROGGI_PEER` - the client's address
+/// * `FROGGI_CLIENT_FINGERPRINT` - the fingerprint of the client's certificate, if
+///   it presented one
+/// * `FROGGI_ITEMS` - an empty directory the script may write items into
+///
+/// It writes FML to stdout, which is verified before being sent. Items the page
+/// references are read from `FROGGI_ITEMS`, then from the script's directory.
+pub struct Cgi {
+    dir: PathBuf,
+    timeout: Duration,
+    max_output: usize,
+}
+
+impl Cgi {
+    pub fn new(dir: PathBuf, timeout: Duration, max_output: usize) -> Cgi {
+        Cgi {
+            dir,
+            timeout,
+            max_output,
+        }
+    }
+
+    pub fn handle(&self, request: &Request, script: &str, path_info: &str) -> Response {
+        match self.run(request, script, path_info) {
+            Ok(response) => response,
+            Err(error) => {
+                warn!("{}", error);
+                Response::error(error.status(), error.message())
+            }
+        }
+    }
+
+    pub fn run(
+        &self,
+        request: &Request,
+        script: &str,
+        path_info: &str,
+    ) -> Result<Response, CgiError> {
+        let path = self.dir.join(script);
+        if script.starts_with('.') || script.contains(&['/', '\\'][..]) || !path.is_file() {
+            return Err(CgiError::NotFound {
+                script: script.into(),
+            });
+        }
+
+        let items_dir = ItemsDir::new().map_err(|error| CgiError::Spawn {
+            script: path.clone(),
+            error,
+        })?;
+
+        let mut command = Command::new(&path);
+        command
+            .current_dir(&self.dir)
+            .env_clear()
+            .env("FROGGI_VERSION", request.version().to_string())
+            .env("FROGGI_PATH", request.path())
+            .env("FROGGI_SCRIPT", script)
+            .env("FROGGI_PATH_INFO", path_info)
+            .env("FROGGI_QUERY", request.query().unwrap_or(""))
+            .env("FROGGI_ITEMS", &items_dir.0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+
+        if let Some(var) = std::env::var_os("PATH") {
+            command.env("PATH", var);
+        }
+
+        if let Some(host) = request.host() {
+            command.env("FROGGI_HOST", host);
+        }
+
+        for (key, value) in request.metadata_entries() {
+            command.env(metadata_var(key), value);
+        }
+
+        if let Some(peer) = request.peer() {
+            command.env("FROGGI_PEER", peer.to_string());
+        }
+
+        if let Some(fingerprint) = request.client_fingerprint() {
+            command.env("FROGGI_CLIENT_FINGERPRINT", fingerprint);
+        }
+
+        let mut child = command.spawn().map_err(|error| CgiError::Spawn {
+            script: path.clone(),
+            error,
+        })?;
+
+        // read on another thread so a script that never closes stdout can't block us past the timeout
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let limit = self.max_output as u64 + 1;
+        let (send, output) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = send.send(stdout.take(limit).read_to_end(&mut output).map(|_| output));
+        });
+
+        let start = Instant::now();
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if start.elapsed() < self.timeout => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Ok(None) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(CgiError::Timeout {
+                        script: path,
+                        timeout: self.timeout,
+                    });
+                }
+                Err(error) => {
+                    return Err(CgiError::Spawn {
+                        script: path,
+                        error,
+                    })
+                }
+            }
+        };
+
+        // anything the script started can keep stdout open after it exits, so the
+        // reader gets what's left of the timeout too
+        let remaining = self.timeout.saturating_sub(start.elapsed());
+        let output = match output.recv_timeout(remaining) {
+            Ok(output) => output.map_err(|error| CgiError::Spawn {
+                script: path.clone(),
+                error,
+            })?,
+            Err(_) => {
+                return Err(CgiError::Timeout {
+                    script: path,
+                    timeout: self.timeout,
+                })
+            }
+        };
+
+        // a script that writes too much is killed by SIGPIPE when we stop reading, so
+        // this has to be checked before whether it succeeded
+        if output.len() > self.max_output {
+            return Err(CgiError::TooLarge {
+                script: path,
+                max_output: self.max_output,
+            });
+        }
+
+        if !status.success() {
+            return Err(CgiError::Failed {
+                script: path,
+                status,
+            });
+        }
+
+        let page = String::from_utf8(output).map_err(|_| CgiError::Encoding {
+            script: path.clone(),
+        })?;
+
+        let items =
+            crate::content::page_items(&page, &[&items_dir.0, &self.dir]).map_err(|errors| {
+                CgiError::Markup {
+                    script: path,
+                    errors,
+                }
+            })?;
+
+        Ok(Response::new(page, items))
+    }
+}
+
+/// The environment variable a metadata entry is passed to scripts in.
+fn metadata_var(key: &str) -> String {
+    let key = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect::<String>();
+
+    format!("FROGGI_META_{}", key)
+}
+
+/// A temporary directory that is removed when dropped.
+struct ItemsDir(PathBuf);
+
+impl ItemsDir {
+    fn new() -> std::io::Result<ItemsDir> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "froggi-cgi-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        std::fs::create_dir_all(&path)?;
+        Ok(ItemsDir(path))
+    }
+}
+
+impl Drop for ItemsDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::test::TempDir;
+
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    fn script(dir: &Path, name: &str, body: &str) {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn cgi(name: &str) -> (Cgi, TempDir) {
+        let dir = TempDir::new(&format!("froggi-cgi-test-{}", name));
+
+        script(
+            &dir,
+            "echo.sh",
+            r#"echo "(\"$FROGGI_PATH_INFO $FROGGI_QUERY\")""#,
+        );
+        script(
+            &dir,
+            "meta.sh",
+            r#"echo "(\"$FROGGI_HOST $FROGGI_META_HOST $FROGGI_META_TOKEN $FROGGI_META_USER_AGENT\")""#,
+        );
+        script(
+            &dir,
+            "item.sh",
+            r#"printf data > "$FROGGI_ITEMS/made.txt"; echo '(& "made.txt")'"#,
+        );
+        script(&dir, "slow.sh", "sleep 5");
+        script(&dir, "bad.sh", "echo '(box'");
+        script(&dir, "big.sh", "echo '(\"0123456789abcdef\")'");
+        script(&dir, "endless.sh", "yes '(\"frog\")'");
+        script(&dir, "orphan.sh", "sleep 5 & echo '(\"a\")'");
+
+        let cgi = Cgi::new(dir.to_path_buf(), Duration::from_millis(500), 16);
+        (cgi, dir)
+    }
+
+    #[test]
+    fn environment() {
+        let request = Request::new("cgi/echo.sh/a/b?q=1").unwrap();
+        let (cgi, _dir) = cgi("environment");
+        let response = cgi.run(&request, "echo.sh", "a/b").unwrap();
+        assert_eq!(response.page(), "(\"a/b q=1\")\n");
+
+        let mut request = Request::with_host("cgi/meta.sh", "example.com:11121").unwrap();
+        request.set_metadata("token", "secret").unwrap();
+        request.set_metadata("user-agent", "frog").unwrap();
+        let cgi = Cgi {
+            max_output: 1024,
+            ..cgi
+        };
+        let response = cgi.run(&request, "meta.sh", "").unwrap();
+        assert_eq!(
+            response.page(),
+            "(\"example.com example.com:11121 secret frog\")\n"
+        );
+    }
+
+    #[test]
+    fn generated_items() {
+        let request = Request::new("cgi/item.sh").unwrap();
+        let (cgi, _dir) = cgi("items");
+        let response = cgi.run(&request, "item.sh", "").unwrap();
+        assert_eq!(response.items()[0].name(), "made.txt");
+        assert_eq!(response.items()[0].data(), b"data");
+    }
+
+    #[test]
+    fn failures() {
+        let (cgi, _dir) = cgi("failures");
+        let request = Request::new("").unwrap();
+
+        match cgi.run(&request, "slow.sh", "") {
+            Err(CgiError::Timeout { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+
+        match cgi.run(&request, "bad.sh", "") {
+            Err(CgiError::Markup { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let response = cgi.handle(&request, "bad.sh", "");
+        assert_eq!(response.status(), Status::ServerError);
+        assert!(!response.page().contains("froggi-cgi-test"));
+
+        match cgi.run(&request, "big.sh", "") {
+            Err(CgiError::TooLarge { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+
+        match cgi.run(&request, "endless.sh", "") {
+            Err(CgiError::TooLarge { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+
+        // the script is done, but something it started still has its stdout
+        let start = Instant::now();
+        match cgi.run(&request, "orphan.sh", "") {
+            Err(CgiError::Timeout { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        match cgi.run(&request, "../echo.sh", "") {
+            Err(CgiError::NotFound { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,1117 @@
+use froggi::server::Network;
+
+use serde::Deserialize;
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const USAGE: &str = "usage: froggi-server [options]
+
+options:
+    -c, --config FILE               read configuration from FILE
+    -l, --listen ADDR               listen on ADDR, may be given more than once
+    -r, --root DIR                  serve pages from DIR
+        --host NAME=DIR             serve pages for NAME from DIR, may be given more than once
+        --default-host NAME         serve requests for unknown hosts from NAME
+        --log-level LEVEL           one of error, warn, info, debug, trace
+        --cache-size N              keep at most N pages in memory
+        --watch                     reload pages when they change on disk
+        --max-connections N         serve at most N clients at once
+        --read-timeout SECS         wait at most SECS for each part of a request
+        --request-deadline SECS     give clients SECS to send their whole request
+        --write-timeout SECS        wait at most SECS for a client to accept a response
+        --max-request-size BYTES    refuse requests bigger than BYTES
+        --tls-cert FILE             serve over tls with the certificate in FILE
+        --tls-key FILE              the private key for --tls-cert
+    -h, --help                      print this message";
+
+/// Server configuration error.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Toml {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    UnknownHost {
+        name: String,
+    },
+    UnknownFlag {
+        flag: String,
+    },
+    MissingValue {
+        flag: String,
+    },
+    MissingFlag {
+        flag: String,
+        needs: String,
+    },
+    InvalidValue {
+        flag: String,
+        value: String,
+    },
+    InvalidListen {
+        addr: String,
+    },
+    NoListen,
+    MissingDirectory {
+        what: String,
+        path: PathBuf,
+    },
+    MissingFile {
+        what: String,
+        path: PathBuf,
+    },
+    DuplicateHost {
+        name: String,
+    },
+    InvalidLimit {
+        limit: String,
+    },
+    InvalidBan {
+        entry: String,
+    },
+    SearchUnavailable,
+    InvalidNetwork {
+        entry: String,
+    },
+    EmptyToken {
+        path: String,
+    },
+    PublishWithoutCredentials,
+    EmptyPublishToken,
+    InvalidFingerprint {
+        entry: String,
+    },
+}
+
+#[rustfmt::skip]
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error }
+                => write!(f, "could not read {} - {}", path.display(), error),
+            ConfigError::Toml { path, error }
+                => write!(f, "could not parse {} - {}", path.display(), error),
+            ConfigError::UnknownHost { name }
+                => write!(f, "default host {:?} is not a configured virtual host", name),
+            ConfigError::UnknownFlag { flag }
+                => write!(f, "unknown option {:?}", flag),
+            ConfigError::MissingValue { flag }
+                => write!(f, "option {} requires a value", flag),
+            ConfigError::MissingFlag { flag, needs }
+                => write!(f, "option {} requires {} too", flag, needs),
+            ConfigError::InvalidValue { flag, value }
+                => write!(f, "invalid value for {}: {:?}", flag, value),
+            ConfigError::InvalidListen { addr }
+                => write!(f, "invalid listen address {:?}, expected e.g. 0.0.0.0:11121 or [::]:11121", addr),
+            ConfigError::NoListen
+                => write!(f, "no listen addresses"),
+            ConfigError::MissingDirectory { what, path }
+                => write!(f, "{} {} is not a directory", what, path.display()),
+            ConfigError::MissingFile { what, path }
+                => write!(f, "{} {} does not exist", what, path.display()),
+            ConfigError::DuplicateHost { name }
+                => write!(f, "virtual host {:?} is configured more than once", name),
+            ConfigError::InvalidLimit { limit }
+                => write!(f, "limit {} must be greater than zero", limit),
+            ConfigError::InvalidBan { entry }
+                => write!(f, "invalid ban {:?}, expected an address or a network like 203.0.113.0/24", entry),
+            ConfigError::SearchUnavailable
+                => write!(f, "search is enabled, but the server was built without the search feature"),
+            ConfigError::InvalidNetwork { entry }
+                => write!(f, "invalid network {:?}, expected an address or a network like 203.0.113.0/24", entry),
+            ConfigError::EmptyToken { path }
+                => write!(f, "access rule for {} has an empty token", path),
+            ConfigError::PublishWithoutCredentials
+                => write!(f, "publishing is enabled, but no tokens or identities may publish"),
+            ConfigError::EmptyPublishToken
+                => write!(f, "publish has an empty token"),
+            ConfigError::InvalidFingerprint { entry }
+                => write!(f, "invalid identity {:?}, expected sha256: followed by 64 hex digits", entry),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> tracing::Level {
+        match level {
+            LogLevel::Error => tracing::Level::ERROR,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Trace => tracing::Level::TRACE,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum number of clients served at once.
+    pub max_connections: usize,
+    /// Seconds to wait for each part of a client's request.
+    pub read_timeout: u64,
+    /// Seconds a client has to send its whole request.
+    pub request_deadline: u64,
+    /// Seconds to wait for a client to accept the response.
+    pub write_timeout: u64,
+    /// Bytes a request may be, including any page being published.
+    pub max_request_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_connections: 256,
+            read_timeout: 10,
+            request_deadline: 20,
+            write_timeout: 10,
+            max_request_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Per-client limits. IPv6 clients are limited by their /64.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    pub enabled: bool,
+    /// Requests a client may make each second, on average.
+    pub requests_per_second: f64,
+    /// Requests a client may make at once after being quiet for a while.
+    pub burst: u32,
+    /// Connections a client may have open at once.
+    pub max_connections: usize,
+    /// Addresses and networks that are refused outright.
+    pub banned: Vec<String>,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            enabled: true,
+            requests_per_second: 5.,
+            burst: 20,
+            max_connections: 8,
+            banned: Vec::new(),
+        }
+    }
+}
+
+/// Full-text search over every page of every host, if built with the `search` feature.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Search {
+    pub enabled: bool,
+    /// The path that search requests are sent to, with the query after a `?`.
+    pub path: String,
+    /// The most results shown for a query.
+    pub results: usize,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Search {
+            enabled: false,
+            path: String::from("/search"),
+            results: 10,
+        }
+    }
+}
+
+/// Who may publish pages to the server.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Publish {
+    pub enabled: bool,
+    pub tokens: Vec<String>,
+    /// Fingerprints of the client certificates that may publish, like `sha256:...`.
+    pub identities: Vec<String>,
+}
+
+/// Generated pages for clients and crawlers that follow a site.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Feed {
+    pub enabled: bool,
+    /// Where the feed of recently updated pages is served.
+    pub path: String,
+    /// Where the list of every page reachable from the index is served.
+    pub sitemap: String,
+    /// The most pages listed in the feed.
+    pub entries: usize,
+}
+
+impl Default for Feed {
+    fn default() -> Self {
+        Feed {
+            enabled: false,
+            path: String::from("/feed"),
+            sitemap: String::from("/sitemap"),
+            entries: 20,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+/// One line per request, written to a file that is rotated when it gets too big.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessLog {
+    pub path: PathBuf,
+    #[serde(default = "default_access_log_format")]
+    pub format: LogFormat,
+    /// Bytes the log may grow to before it is rotated.
+    #[serde(default = "default_access_log_max_size")]
+    pub max_size: u64,
+    /// How many rotated logs to keep.
+    #[serde(default = "default_access_log_keep")]
+    pub keep: usize,
+}
+
+fn default_access_log_format() -> LogFormat {
+    LogFormat::Human
+}
+
+fn default_access_log_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_access_log_keep() -> usize {
+    5
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Host {
+    pub name: String,
+    pub root: PathBuf,
+    /// Overrides the top-level `index`.
+    pub index: Option<String>,
+    /// Overrides the top-level `cache_size`.
+    pub cache_size: Option<usize>,
+    #[serde(default)]
+    pub cgi: Vec<Cgi>,
+    /// Used before the top-level error pages.
+    #[serde(default, rename = "error_page")]
+    pub error_pages: Vec<ErrorPage>,
+    /// Used before the top-level access rules.
+    #[serde(default, rename = "access")]
+    pub access: Vec<AccessRule>,
+}
+
+/// Paths under `path` are produced by running the executables in `dir`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cgi {
+    pub path: String,
+    pub dir: PathBuf,
+    /// Seconds a script may run for.
+    #[serde(default = "default_cgi_timeout")]
+    pub timeout: u64,
+    /// Bytes a script may write.
+    #[serde(default = "default_cgi_max_output")]
+    pub max_output: usize,
+}
+
+fn default_cgi_timeout() -> u64 {
+    5
+}
+
+fn default_cgi_max_output() -> usize {
+    1024 * 1024
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    Name,
+    Size,
+    Modified,
+}
+
+/// Generated listings for directories that have no index page.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listing {
+    pub enabled: bool,
+    pub show_hidden: bool,
+    pub sort: Sort,
+    pub descending: bool,
+    #[serde(rename = "directory")]
+    pub directories: Vec<ListingDirectory>,
+}
+
+impl Default for Listing {
+    fn default() -> Self {
+        Listing {
+            enabled: false,
+            show_hidden: false,
+            sort: Sort::Name,
+            descending: false,
+            directories: Vec::new(),
+        }
+    }
+}
+
+/// Listing settings for a directory and everything below it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListingDirectory {
+    pub path: String,
+    pub enabled: Option<bool>,
+    pub show_hidden: Option<bool>,
+    pub sort: Option<Sort>,
+    pub descending: Option<bool>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorStatus {
+    BadRequest,
+    Forbidden,
+    NotFound,
+    ServerError,
+    RateLimited,
+    Unauthorized,
+}
+
+/// An FML page sent instead of the built-in one when a request under `path` fails.
+///
+/// `${path}` and `${message}` in the page are replaced by the request path and a
+/// description of the error.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorPage {
+    /// Which errors the page is for. If unset, it's used for all of them.
+    pub status: Option<ErrorStatus>,
+    #[serde(default = "default_path")]
+    pub path: String,
+    pub page: PathBuf,
+}
+
+/// Who may see the pages under `path`. Only the rule with the most specific path
+/// applies to a request.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// If not empty, only clients in these networks are let in.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Clients in these networks are turned away.
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// If not empty, clients must send one of these tokens.
+    #[serde(default)]
+    pub tokens: Vec<String>,
+}
+
+fn default_path() -> String {
+    String::from("/")
+}
+
+/// Server configuration, read from a TOML file and overridden by command-line options.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: Vec<String>,
+    pub root: PathBuf,
+    pub index: String,
+    pub log_level: LogLevel,
+    pub cache_size: usize,
+    /// Reload pages and items when they change on disk.
+    pub watch: bool,
+    pub limits: Limits,
+    pub rate_limit: RateLimit,
+    /// If unset, requests are logged with everything else.
+    pub access_log: Option<AccessLog>,
+    pub listing: Listing,
+    pub search: Search,
+    pub feed: Feed,
+    pub publish: Publish,
+    pub tls: Option<Tls>,
+    #[serde(rename = "host")]
+    pub hosts: Vec<Host>,
+    /// The host that serves requests without a hostname or for an unknown one.
+    /// If unset, those requests are served from the top-level `root`.
+    pub default_host: Option<String>,
+    pub cgi: Vec<Cgi>,
+    #[serde(rename = "error_page")]
+    pub error_pages: Vec<ErrorPage>,
+    #[serde(rename = "access")]
+    pub access: Vec<AccessRule>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![String::from("0.0.0.0:11121")],
+            root: PathBuf::from("pages"),
+            index: String::from("index.fml"),
+            log_level: LogLevel::Info,
+            cache_size: 64,
+            watch: false,
+            limits: Limits::default(),
+            rate_limit: RateLimit::default(),
+            access_log: None,
+            listing: Listing::default(),
+            search: Search::default(),
+            feed: Feed::default(),
+            publish: Publish::default(),
+            tls: None,
+            hosts: Vec::new(),
+            default_host: None,
+            cgi: Vec::new(),
+            error_pages: Vec::new(),
+            access: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration from command-line arguments, not including the program name.
+    ///
+    /// Returns `Ok(None)` if the user only asked for help.
+    pub fn from_args(args: &[String]) -> Result<Option<Config>, Vec<ConfigError>> {
+        // the config file is read first so the rest of the options can override it
+        let mut config = match find_flag(args, "-c", "--config")? {
+            Some(path) => Config::from_file(Path::new(&path))?,
+            None => Config::default(),
+        };
+
+        let mut listen = Vec::new();
+        let mut certificate = None;
+        let mut key = None;
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| vec![ConfigError::MissingValue { flag: flag.clone() }])
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-c" | "--config" => {
+                    value()?;
+                }
+                "-l" | "--listen" => listen.push(value()?),
+                "-r" | "--root" => config.root = PathBuf::from(value()?),
+                "--host" => {
+                    let value = value()?;
+                    let (name, root) = match value.split_once('=') {
+                        Some((name, root)) if !name.is_empty() && !root.is_empty() => {
+                            (name, PathBuf::from(root))
+                        }
+                        _ => {
+                            return Err(vec![ConfigError::InvalidValue {
+                                flag: flag.clone(),
+                                value,
+                            }])
+                        }
+                    };
+
+                    match config
+                        .hosts
+                        .iter_mut()
+                        .find(|host| host.name.eq_ignore_ascii_case(name))
+                    {
+                        Some(host) => host.root = root,
+                        None => config.hosts.push(Host {
+                            name: name.to_string(),
+                            root,
+                            index: None,
+                            cache_size: None,
+                            cgi: Vec::new(),
+                            error_pages: Vec::new(),
+                            access: Vec::new(),
+                        }),
+                    }
+                }
+                "--default-host" => config.default_host = Some(value()?),
+                "--watch" => config.watch = true,
+                "--log-level" => config.log_level = parse_value(flag, value()?)?,
+                "--cache-size" => config.cache_size = parse_value(flag, value()?)?,
+                "--max-connections" => config.limits.max_connections = parse_value(flag, value()?)?,
+                "--read-timeout" => config.limits.read_timeout = parse_value(flag, value()?)?,
+                "--request-deadline" => {
+                    config.limits.request_deadline = parse_value(flag, value()?)?
+                }
+                "--write-timeout" => config.limits.write_timeout = parse_value(flag, value()?)?,
+                "--max-request-size" => {
+                    config.limits.max_request_size = parse_value(flag, value()?)?
+                }
+                "--tls-cert" => certificate = Some(PathBuf::from(value()?)),
+                "--tls-key" => key = Some(PathBuf::from(value()?)),
+                _ => return Err(vec![ConfigError::UnknownFlag { flag: flag.clone() }]),
+            }
+        }
+
+        if !listen.is_empty() {
+            config.listen = listen;
+        }
+
+        // either flag can replace part of the file's tls settings, but without them
+        // both are needed
+        config.tls = match (certificate, key, config.tls) {
+            (Some(certificate), Some(key), _) => Some(Tls { certificate, key }),
+            (Some(certificate), None, Some(tls)) => Some(Tls { certificate, ..tls }),
+            (None, Some(key), Some(tls)) => Some(Tls { key, ..tls }),
+            (Some(_), None, None) => {
+                return Err(vec![ConfigError::MissingFlag {
+                    flag: String::from("--tls-cert"),
+                    needs: String::from("--tls-key"),
+                }])
+            }
+            (None, Some(_), None) => {
+                return Err(vec![ConfigError::MissingFlag {
+                    flag: String::from("--tls-key"),
+                    needs: String::from("--tls-cert"),
+                }])
+            }
+            (None, None, tls) => tls,
+        };
+
+        config.validate()?;
+        Ok(Some(config))
+    }
+
+    /// Read a configuration file. Relative paths are resolved against the file's directory.
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let data = std::fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.into(),
+            error,
+        })?;
+
+        let mut config: Config = toml::from_str(&data).map_err(|error| ConfigError::Toml {
+            path: path.into(),
+            error,
+        })?;
+
+        if let Some(dir) = path.parent() {
+            config.root = dir.join(&config.root);
+            if let Some(access_log) = &mut config.access_log {
+                access_log.path = dir.join(&access_log.path);
+            }
+            if let Some(tls) = &mut config.tls {
+                tls.certificate = dir.join(&tls.certificate);
+                tls.key = dir.join(&tls.key);
+            }
+            for host in config.hosts.iter_mut() {
+                host.root = dir.join(&host.root);
+                for cgi in host.cgi.iter_mut() {
+                    cgi.dir = dir.join(&cgi.dir);
+                }
+                for error_page in host.error_pages.iter_mut() {
+                    error_page.page = dir.join(&error_page.page);
+                }
+            }
+            for cgi in config.cgi.iter_mut() {
+                cgi.dir = dir.join(&cgi.dir);
+            }
+            for error_page in config.error_pages.iter_mut() {
+                error_page.page = dir.join(&error_page.page);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Check the configuration for errors, reporting all of them at once.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+
+        if self.listen.is_empty() {
+            errors.push(ConfigError::NoListen);
+        }
+
+        for addr in &self.listen {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(ConfigError::InvalidListen { addr: addr.clone() });
+            }
+        }
+
+        if !self.root.is_dir() {
+            errors.push(ConfigError::MissingDirectory {
+                what: String::from("document root"),
+                path: self.root.clone(),
+            });
+        }
+
+        if self.limits.max_connections == 0 {
+            errors.push(ConfigError::InvalidLimit {
+                limit: String::from("max_connections"),
+            });
+        }
+
+        // a zero timeout can't be set on a socket
+        let timeouts = [
+            ("read_timeout", self.limits.read_timeout),
+            ("request_deadline", self.limits.request_deadline),
+            ("write_timeout", self.limits.write_timeout),
+        ];
+        for (limit, timeout) in timeouts.iter() {
+            if *timeout == 0 {
+                errors.push(ConfigError::InvalidLimit {
+                    limit: limit.to_string(),
+                });
+            }
+        }
+
+        if self.rate_limit.enabled {
+            if self.rate_limit.requests_per_second <= 0. {
+                errors.push(ConfigError::InvalidLimit {
+                    limit: String::from("requests_per_second"),
+                });
+            }
+
+            if self.rate_limit.burst == 0 {
+                errors.push(ConfigError::InvalidLimit {
+                    limit: String::from("burst"),
+                });
+            }
+
+            if self.rate_limit.max_connections == 0 {
+                errors.push(ConfigError::InvalidLimit {
+                    limit: String::from("rate_limit.max_connections"),
+                });
+            }
+        }
+
+        for entry in &self.rate_limit.banned {
+            if entry.parse::<Network>().is_err() {
+                errors.push(ConfigError::InvalidBan {
+                    entry: entry.clone(),
+                });
+            }
+        }
+
+        if self.search.enabled {
+            if !cfg!(feature = "search") {
+                errors.push(ConfigError::SearchUnavailable);
+            }
+
+            if self.search.results == 0 {
+                errors.push(ConfigError::InvalidLimit {
+                    limit: String::from("search.results"),
+                });
+            }
+        }
+
+        if self.feed.enabled && self.feed.entries == 0 {
+            errors.push(ConfigError::InvalidLimit {
+                limit: String::from("feed.entries"),
+            });
+        }
+
+        if self.limits.max_request_size == 0 {
+            errors.push(ConfigError::InvalidLimit {
+                limit: String::from("max_request_size"),
+            });
+        }
+
+        if self.publish.enabled
+            && self.publish.tokens.is_empty()
+            && self.publish.identities.is_empty()
+        {
+            errors.push(ConfigError::PublishWithoutCredentials);
+        }
+
+        if self.publish.tokens.iter().any(|token| token.is_empty()) {
+            errors.push(ConfigError::EmptyPublishToken);
+        }
+
+        for entry in &self.publish.identities {
+            if !is_fingerprint(entry) {
+                errors.push(ConfigError::InvalidFingerprint {
+                    entry: entry.clone(),
+                });
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            if !tls.certificate.is_file() {
+                errors.push(ConfigError::MissingFile {
+                    what: String::from("tls certificate"),
+                    path: tls.certificate.clone(),
+                });
+            }
+
+            if !tls.key.is_file() {
+                errors.push(ConfigError::MissingFile {
+                    what: String::from("tls key"),
+                    path: tls.key.clone(),
+                });
+            }
+        }
+
+        for (i, host) in self.hosts.iter().enumerate() {
+            if self.hosts[..i]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&host.name))
+            {
+                errors.push(ConfigError::DuplicateHost {
+                    name: host.name.clone(),
+                });
+            }
+
+            if !host.root.is_dir() {
+                errors.push(ConfigError::MissingDirectory {
+                    what: format!("document root for {}", host.name),
+                    path: host.root.clone(),
+                });
+            }
+
+            validate_cgi(&host.cgi, &mut errors);
+            validate_error_pages(&host.error_pages, &mut errors);
+            validate_access(&host.access, &mut errors);
+        }
+
+        if let Some(name) = &self.default_host {
+            if !self
+                .hosts
+                .iter()
+                .any(|host| host.name.eq_ignore_ascii_case(name))
+            {
+                errors.push(ConfigError::UnknownHost { name: name.clone() });
+            }
+        }
+
+        validate_cgi(&self.cgi, &mut errors);
+        validate_error_pages(&self.error_pages, &mut errors);
+        validate_access(&self.access, &mut errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        // validate() has already rejected anything that doesn't parse
+        self.listen
+            .iter()
+            .filter_map(|addr| addr.parse().ok())
+            .collect()
+    }
+}
+
+fn validate_cgi(cgi: &[Cgi], errors: &mut Vec<ConfigError>) {
+    for cgi in cgi {
+        if !cgi.dir.is_dir() {
+            errors.push(ConfigError::MissingDirectory {
+                what: format!("cgi directory for {}", cgi.path),
+                path: cgi.dir.clone(),
+            });
+        }
+
+        if cgi.timeout == 0 {
+            errors.push(ConfigError::InvalidLimit {
+                limit: format!("timeout for cgi {}", cgi.path),
+            });
+        }
+    }
+}
+
+fn validate_error_pages(error_pages: &[ErrorPage], errors: &mut Vec<ConfigError>) {
+    for error_page in error_pages {
+        if !error_page.page.is_file() {
+            errors.push(ConfigError::MissingFile {
+                what: format!("error page for {}", error_page.path),
+                path: error_page.page.clone(),
+            });
+        }
+    }
+}
+
+fn validate_access(rules: &[AccessRule], errors: &mut Vec<ConfigError>) {
+    for rule in rules {
+        for entry in rule.allow.iter().chain(&rule.deny) {
+            if entry.parse::<Network>().is_err() {
+                errors.push(ConfigError::InvalidNetwork {
+                    entry: entry.clone(),
+                });
+            }
+        }
+
+        if rule.tokens.iter().any(|token| token.is_empty()) {
+            errors.push(ConfigError::EmptyToken {
+                path: rule.path.clone(),
+            });
+        }
+    }
+}
+
+/// Whether `entry` looks like a certificate fingerprint, `sha256:` and 64 hex digits.
+fn is_fingerprint(entry: &str) -> bool {
+    match entry.strip_prefix("sha256:") {
+        Some(hex) => hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: String) -> Result<T, Vec<ConfigError>> {
+    value.parse().map_err(|_| {
+        vec![ConfigError::InvalidValue {
+            flag: flag.to_string(),
+            value,
+        }]
+    })
+}
+
+fn find_flag(args: &[String], short: &str, long: &str) -> Result<Option<String>, ConfigError> {
+    match args.iter().position(|arg| arg == short || arg == long) {
+        Some(i) => match args.get(i + 1) {
+            Some(value) => Ok(Some(value.clone())),
+            None => Err(ConfigError::MissingValue {
+                flag: args[i].clone(),
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
+impl From<ConfigError> for Vec<ConfigError> {
+    fn from(error: ConfigError) -> Vec<ConfigError> {
+        vec![error]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_file() {
+        let config: Config = toml::from_str(
+            r#"
+            listen = ["127.0.0.1:11121", "[::1]:11121"]
+            root = "pages"
+            log_level = "debug"
+
+            [limits]
+            max_connections = 4
+
+            [rate_limit]
+            burst = 2
+            banned = ["203.0.113.0/24", "2001:db8::1"]
+
+            [access_log]
+            path = "access.log"
+            format = "json"
+
+            [listing]
+            enabled = true
+
+            [[listing.directory]]
+            path = "/downloads"
+            sort = "modified"
+            descending = true
+
+            [search]
+            enabled = true
+            path = "/find"
+
+            [feed]
+            enabled = true
+            entries = 5
+
+            [publish]
+            enabled = true
+            identities = ["sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"]
+
+            [[host]]
+            name = "example.com"
+            root = "example"
+            index = "home.fml"
+
+            [[host.cgi]]
+            path = "/search"
+            dir = "example-cgi"
+
+            [[cgi]]
+            path = "/cgi"
+            dir = "cgi-bin"
+
+            [[error_page]]
+            status = "not_found"
+            page = "errors/not-found.fml"
+
+            [[error_page]]
+            path = "/docs"
+            page = "errors/docs.fml"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.listen_addrs().len(), 2);
+        assert!(config.listen_addrs()[1].is_ipv6());
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.limits.max_connections, 4);
+        assert_eq!(config.limits.read_timeout, 10);
+        assert!(config.rate_limit.enabled);
+        assert_eq!(config.rate_limit.burst, 2);
+        assert_eq!(config.rate_limit.banned.len(), 2);
+        let access_log = config.access_log.as_ref().unwrap();
+        assert_eq!(access_log.format, LogFormat::Json);
+        assert_eq!(access_log.keep, 5);
+        assert!(config.listing.enabled);
+        assert_eq!(config.listing.directories[0].sort, Some(Sort::Modified));
+        assert!(config.search.enabled);
+        assert_eq!(config.search.path, "/find");
+        assert_eq!(config.search.results, 10);
+        assert!(config.feed.enabled);
+        assert_eq!(config.feed.sitemap, "/sitemap");
+        assert_eq!(config.feed.entries, 5);
+        assert!(config.publish.enabled);
+        assert!(config.publish.tokens.is_empty());
+        assert_eq!(config.limits.max_request_size, 16 * 1024 * 1024);
+        assert_eq!(config.hosts[0].name, "example.com");
+        assert_eq!(config.hosts[0].cgi[0].path, "/search");
+        assert_eq!(config.hosts[0].cache_size, None);
+        assert_eq!(config.cgi[0].timeout, 5);
+        assert_eq!(config.error_pages[0].status, Some(ErrorStatus::NotFound));
+        assert_eq!(config.error_pages[0].path, "/");
+        assert_eq!(config.error_pages[1].status, None);
+    }
+
+    #[test]
+    fn unknown_key() {
+        assert!(toml::from_str::<Config>("lsiten = []").is_err());
+    }
+
+    #[test]
+    fn validate_reports_everything() {
+        let config = Config {
+            listen: vec![String::from("localhost"), String::from("0.0.0.0:11121")],
+            root: PathBuf::from("/this/directory/does/not/exist"),
+            limits: Limits {
+                max_connections: 0,
+                read_timeout: 0,
+                write_timeout: 0,
+                ..Limits::default()
+            },
+            rate_limit: RateLimit {
+                banned: vec![String::from("203.0.113.0/33")],
+                ..RateLimit::default()
+            },
+            publish: Publish {
+                enabled: true,
+                tokens: Vec::new(),
+                identities: vec![String::from("9f86d081")],
+            },
+            default_host: Some(String::from("example.com")),
+            ..Config::default()
+        };
+
+        assert_eq!(config.validate().unwrap_err().len(), 8);
+    }
+
+    #[test]
+    fn args_override() {
+        let args = [
+            "-l",
+            "127.0.0.1:1",
+            "--listen",
+            "[::1]:2",
+            "-r",
+            ".",
+            "--cache-size",
+            "3",
+            "--watch",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+
+        let config = Config::from_args(&args).unwrap().unwrap();
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.cache_size, 3);
+        assert!(config.watch);
+
+        let args = vec![String::from("--log-level"), String::from("loud")];
+        assert!(Config::from_args(&args).is_err());
+
+        let args = [
+            "-r",
+            ".",
+            "--host",
+            "example.com=.",
+            "--default-host",
+            "example.com",
+            "--max-connections",
+            "4",
+            "--request-deadline",
+            "7",
+            "--max-request-size",
+            "1024",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+
+        let config = Config::from_args(&args).unwrap().unwrap();
+        assert_eq!(config.hosts[0].name, "example.com");
+        assert_eq!(config.hosts[0].root, PathBuf::from("."));
+        assert_eq!(config.default_host.as_deref(), Some("example.com"));
+        assert_eq!(config.limits.max_connections, 4);
+        assert_eq!(config.limits.request_deadline, 7);
+        assert_eq!(config.limits.max_request_size, 1024);
+
+        let args = vec![String::from("--host"), String::from("example.com")];
+        assert!(Config::from_args(&args).is_err());
+
+        let args = vec![String::from("--tls-cert"), String::from("cert.pem")];
+        assert!(matches!(
+            Config::from_args(&args).unwrap_err()[0],
+            ConfigError::MissingFlag { .. }
+        ));
+    }
+}
@@ -0,0 +1,566 @@
+use froggi::markup::scan::Token;
+use froggi::markup::{ItemPayload, Page};
+use froggi::request::Request;
+use froggi::response::{Item, Response, Status};
+use froggi::server::Handler;
+use froggi::FroggiError;
+use tracing::{info, warn};
+
+use crate::auth::AccessControl;
+use crate::config::Listing;
+use crate::include::IncludeError;
+use crate::watch::Watched;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Error produced while loading a page from the document root.
+#[derive(Debug)]
+pub enum ContentError {
+    NotFound {
+        path: String,
+    },
+    Forbidden {
+        path: String,
+    },
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Markup {
+        path: PathBuf,
+        errors: Vec<FroggiError>,
+    },
+    Include {
+        error: IncludeError,
+    },
+}
+
+impl ContentError {
+    pub fn status(&self) -> Status {
+        match self {
+            ContentError::NotFound { .. } => Status::NotFound,
+            ContentError::Forbidden { .. } => Status::Forbidden,
+            ContentError::Io { error, .. } if error.kind() == std::io::ErrorKind::NotFound => {
+                Status::NotFound
+            }
+            ContentError::Io { .. }
+            | ContentError::Markup { .. }
+            | ContentError::Include { .. } => Status::ServerError,
+        }
+    }
+
+    /// What to tell the client, which leaves out the server's own paths.
+    pub fn message(&self) -> String {
+        match self {
+            ContentError::NotFound { .. } | ContentError::Forbidden { .. } => self.to_string(),
+            _ if self.status() == Status::NotFound => String::from("there is no page here"),
+            _ => String::from("the page could not be loaded"),
+        }
+    }
+}
+
+#[rustfmt::skip]
+impl fmt::Display for ContentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContentError::NotFound { path }
+                => write!(f, "no page at {:?}", path),
+            ContentError::Forbidden { path }
+                => write!(f, "path {:?} is outside of the document root", path),
+            ContentError::Io { path, error }
+                => write!(f, "could not read {} - {}", path.display(), error),
+            ContentError::Markup { path, errors }
+                => write!(f, "{} has {} markup error(s), the first is: {}", path.display(), errors.len(), errors[0]),
+            ContentError::Include { error }
+                => write!(f, "{}", error),
+        }
+    }
+}
+
+/// A page and the items it references, ready to be sent.
+#[derive(Debug)]
+pub struct CachedPage {
+    pub page: String,
+    pub items: Vec<Item>,
+    /// Fragments the page included, which it has to be reloaded along with.
+    pub includes: Vec<PathBuf>,
+}
+
+impl CachedPage {
+    pub fn to_response(&self) -> Response {
+        Response::new(self.page.clone(), self.items.clone())
+    }
+}
+
+/// Pages served from a document root, with a small least-recently-used cache.
+/// Directory listings only list the entries `access` lets the client see.
+pub struct Content {
+    root: PathBuf,
+    index: String,
+    listing: Listing,
+    access: Arc<AccessControl>,
+    cache: Mutex<Cache>,
+}
+
+impl Content {
+    pub fn new(
+        root: PathBuf,
+        index: String,
+        cache_size: usize,
+        listing: Listing,
+        access: Arc<AccessControl>,
+    ) -> Content {
+        Content {
+            root,
+            index,
+            listing,
+            access,
+            cache: Mutex::new(Cache::new(cache_size)),
+        }
+    }
+
+    /// Load the page a request is for, verifying its markup and collecting its items.
+    pub fn load(&self, request: &Request) -> Result<Arc<CachedPage>, ContentError> {
+        let request_path = request.path();
+        let mut path =
+            resolve(&self.root, request_path).ok_or_else(|| ContentError::Forbidden {
+                path: request_path.into(),
+            })?;
+
+        if path.is_dir() {
+            let index = path.join(&self.index);
+            if !index.is_file() {
+                return self.listing(&path, request);
+            }
+            path = index;
+        }
+
+        if let Some(page) = self.cache.lock().unwrap().get(&path) {
+            return Ok(page);
+        }
+
+        if !path.is_file() {
+            return Err(ContentError::NotFound {
+                path: request_path.into(),
+            });
+        }
+
+        let page = Arc::new(load_page(&self.root, &path)?);
+        self.cache.lock().unwrap().insert(path, Arc::clone(&page));
+
+        Ok(page)
+    }
+
+    // listings aren't cached, since they'd go stale as soon as the directory changes, and
+    // clients that may see different pages get different listings
+    fn listing(&self, dir: &Path, request: &Request) -> Result<Arc<CachedPage>, ContentError> {
+        let request_path = request.path();
+        let settings = self.listing.settings_for(request_path);
+        if !settings.enabled {
+            return Err(ContentError::NotFound {
+                path: request_path.into(),
+            });
+        }
+
+        let visible = |path: &str| self.access.allows(request, path);
+        let page =
+            crate::listing::generate(dir, request_path, &settings, visible).map_err(|error| {
+                ContentError::Io {
+                    path: dir.into(),
+                    error,
+                }
+            })?;
+
+        Ok(Arc::new(CachedPage {
+            page,
+            items: Vec::new(),
+            includes: Vec::new(),
+        }))
+    }
+}
+
+impl Watched for Content {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Reload the cached pages affected by a change to a file or directory under the root.
+    ///
+    /// A page is affected if it or one of its includes changed, or if anything in or below
+    /// its directory changed, since that might be one of its items. Pages that no longer
+    /// parse keep being served as they were, so a half-finished edit doesn't take the site
+    /// down.
+    fn changed(&self, relative: &Path) {
+        let changed = self.root.join(relative);
+        let affected = self
+            .cache
+            .lock()
+            .unwrap()
+            .entries()
+            .filter(|(path, page)| {
+                path.starts_with(&changed)
+                    || matches!(path.parent(), Some(dir) if changed.starts_with(dir))
+                    || page
+                        .includes
+                        .iter()
+                        .any(|include| include.starts_with(&changed))
+            })
+            .map(|(path, _)| path.to_path_buf())
+            .collect::<Vec<_>>();
+
+        for path in affected {
+            if !path.is_file() {
+                info!("{} was removed", path.display());
+                self.cache.lock().unwrap().remove(&path);
+                continue;
+            }
+
+            match load_page(&self.root, &path) {
+                Ok(page) => {
+                    info!("reloaded {}", path.display());
+                    self.cache.lock().unwrap().insert(path, Arc::new(page));
+                }
+                Err(error) => warn!("{}, still serving the last good version", error),
+            }
+        }
+    }
+}
+
+impl Handler for Content {
+    fn handle(&self, request: &Request) -> Response {
+        match self.load(request) {
+            Ok(page) => page.to_response(),
+            Err(error) => {
+                warn!("{}", error);
+                Response::error(error.status(), error.message())
+            }
+        }
+    }
+}
+
+/// Map a request path onto a file under the document root.
+///
+/// Returns `None` if the path would escape the root.
+pub fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+
+    for component in request_path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            _ if component.contains('\\') || component.contains(':') => return None,
+            _ => path.push(component),
+        }
+    }
+
+    Some(path)
+}
+
+fn load_page(root: &Path, path: &Path) -> Result<CachedPage, ContentError> {
+    let expanded = crate::include::expand(root, path).map_err(|error| match error {
+        IncludeError::Io { path, error } => ContentError::Io { path, error },
+        error => ContentError::Include { error },
+    })?;
+
+    // items are looked up next to the page that references them, even from fragments
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let items = page_items(&expanded.page, &[dir]).map_err(|mut errors| ContentError::Markup {
+        path: expanded.locate_errors(&mut errors),
+        errors,
+    })?;
+
+    Ok(CachedPage {
+        page: expanded.page,
+        items,
+        includes: expanded.files[1..].to_vec(),
+    })
+}
+
+/// Verify a page's markup and read the items it references from the first directory
+/// that has them. Items that aren't just file names are left out.
+pub fn page_items(page: &str, dirs: &[&Path]) -> Result<Vec<Item>, Vec<FroggiError>> {
+    let names = blob_names(&froggi::markup::parse::parse(page)?);
+
+    let mut items = Vec::new();
+    for name in names {
+        if !is_item_name(&name) {
+            warn!("page references item {:?} outside its directory", name);
+            continue;
+        }
+
+        match dirs
+            .iter()
+            .find_map(|dir| std::fs::read(dir.join(&name)).ok())
+        {
+            Some(data) => items.push(Item::new(name, data)),
+            None => warn!("page references missing item {:?}", name),
+        }
+    }
+
+    Ok(items)
+}
+
+/// Whether an item name is just a file name, which can't go anywhere but next to the
+/// page.
+pub fn is_item_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(&['/', '\\', ':', '\0'][..])
+}
+
+/// The names of the blobs on a page, each once.
+pub fn blob_names(page: &Page) -> Vec<String> {
+    let mut names = Vec::<String>::new();
+    for name in page.blobs() {
+        if !names.iter().any(|n| *n == name.decoded()) {
+            names.push(name.decoded().into_owned());
+        }
+    }
+    names
+}
+
+/// Collect the text of a page, its link text and its blobs' alt text, one line per item.
+pub fn page_text(page: &Page, lines: &mut Vec<String>) {
+    let join = |tokens: &[Token]| {
+        tokens
+            .iter()
+            .map(|token| token.decoded())
+            .collect::<String>()
+    };
+
+    for entry in page.iter_items() {
+        let text = match &entry.item.payload {
+            ItemPayload::Text { text } => join(text),
+            ItemPayload::Link { text, .. } => join(text),
+            ItemPayload::Blob { alt, .. } => join(alt),
+            ItemPayload::Children { .. } | ItemPayload::Anchor { .. } | ItemPayload::Error => {
+                continue
+            }
+        };
+
+        if !text.trim().is_empty() {
+            lines.push(text);
+        }
+    }
+}
+
+struct Cache {
+    capacity: usize,
+    pages: HashMap<PathBuf, Arc<CachedPage>>,
+    order: VecDeque<PathBuf>,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Cache {
+        Cache {
+            capacity,
+            pages: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, path: &Path) -> Option<Arc<CachedPage>> {
+        let page = self.pages.get(path).cloned()?;
+        self.touch(path);
+        Some(page)
+    }
+
+    fn insert(&mut self, path: PathBuf, page: Arc<CachedPage>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.pages.insert(path.clone(), page).is_some() {
+            self.touch(&path);
+            return;
+        }
+
+        self.order.push_back(path);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.pages.remove(&oldest);
+            }
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        self.pages.remove(path);
+        self.order.retain(|other| other != path);
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&Path, &Arc<CachedPage>)> {
+        self.pages.iter().map(|(path, page)| (path.as_path(), page))
+    }
+
+    fn touch(&mut self, path: &Path) {
+        if let Some(i) = self.order.iter().position(|other| other == path) {
+            let path = self.order.remove(i).unwrap();
+            self.order.push_back(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::AccessRule;
+    use crate::test::TempDir;
+
+    #[test]
+    fn resolve_stays_in_root() {
+        let root = Path::new("pages");
+        assert_eq!(
+            resolve(root, "/a/b.fml"),
+            Some(PathBuf::from("pages/a/b.fml"))
+        );
+        assert_eq!(
+            resolve(root, "a//./b.fml"),
+            Some(PathBuf::from("pages/a/b.fml"))
+        );
+        assert_eq!(resolve(root, "../secret"), None);
+        assert_eq!(resolve(root, "a/../../secret"), None);
+        assert_eq!(resolve(root, "..\\secret"), None);
+    }
+
+    #[test]
+    fn cache_evicts_least_recent() {
+        let page = || {
+            Arc::new(CachedPage {
+                page: String::new(),
+                items: Vec::new(),
+                includes: Vec::new(),
+            })
+        };
+
+        let mut cache = Cache::new(2);
+        cache.insert("a".into(), page());
+        cache.insert("b".into(), page());
+        assert!(cache.get(Path::new("a")).is_some());
+        cache.insert("c".into(), page());
+
+        assert!(cache.get(Path::new("a")).is_some());
+        assert!(cache.get(Path::new("b")).is_none());
+        assert!(cache.get(Path::new("c")).is_some());
+    }
+
+    fn serve(root: &Path) -> Content {
+        let access = Arc::new(AccessControl::new(&[]));
+        Content::new(
+            root.into(),
+            "index.fml".into(),
+            4,
+            Listing::default(),
+            access,
+        )
+    }
+
+    fn load(content: &Content, path: &str) -> Result<Arc<CachedPage>, ContentError> {
+        content.load(&Request::new(path).unwrap())
+    }
+
+    #[test]
+    fn changes_keep_last_good_page() {
+        let root = TempDir::new("froggi-content-changed-test");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/page.fml"), "(\"first\")").unwrap();
+
+        let content = serve(&root);
+        assert_eq!(load(&content, "sub/page.fml").unwrap().page, "(\"first\")");
+
+        std::fs::write(root.join("sub/page.fml"), "(\"broken").unwrap();
+        content.changed(Path::new("sub/page.fml"));
+        assert_eq!(load(&content, "sub/page.fml").unwrap().page, "(\"first\")");
+
+        std::fs::write(root.join("sub/page.fml"), "(\"second\")").unwrap();
+        content.changed(Path::new("sub/page.fml"));
+        assert_eq!(load(&content, "sub/page.fml").unwrap().page, "(\"second\")");
+
+        // items are loaded with the page, so a new item reloads it too
+        std::fs::write(root.join("sub/page.fml"), "(& \"item.txt\")").unwrap();
+        content.changed(Path::new("sub/page.fml"));
+        assert!(load(&content, "sub/page.fml").unwrap().items.is_empty());
+
+        std::fs::write(root.join("sub/item.txt"), "data").unwrap();
+        content.changed(Path::new("sub/item.txt"));
+        assert_eq!(load(&content, "sub/page.fml").unwrap().items.len(), 1);
+
+        std::fs::remove_dir_all(root.join("sub")).unwrap();
+        content.changed(Path::new("sub"));
+        match load(&content, "sub/page.fml") {
+            Err(ContentError::NotFound { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn items_stay_next_to_the_page() {
+        let root = TempDir::new("froggi-content-items-test");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("secret.txt"), "secret").unwrap();
+        std::fs::write(root.join("sub/item.txt"), "item").unwrap();
+
+        let absolute = froggi::markup::escape(&root.join("secret.txt").display().to_string());
+        let page = format!(
+            "(& \"item.txt\") (& \"../secret.txt\") (& \"{}\")",
+            absolute
+        );
+        let items = page_items(&page, &[&root.join("sub")]).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].name(), "item.txt");
+    }
+
+    #[test]
+    fn errors_hide_server_paths() {
+        let root = TempDir::new("froggi-content-errors-test");
+        std::fs::write(root.join("broken.fml"), "(\"broken").unwrap();
+
+        let content = serve(&root);
+        let response = content.handle(&Request::new("broken.fml").unwrap());
+        assert_eq!(response.status(), Status::ServerError);
+        assert_eq!(response.message(), Some("the page could not be loaded"));
+        assert!(!response.page().contains("froggi-content-errors-test"));
+
+        let response = content.handle(&Request::new("missing.fml").unwrap());
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(response.message(), Some("no page at \"missing.fml\""));
+    }
+
+    #[test]
+    fn listings_hide_what_the_client_may_not_see() {
+        let root = TempDir::new("froggi-content-listing-test");
+        std::fs::create_dir_all(root.join("team")).unwrap();
+        std::fs::write(root.join("frogs.fml"), "(\"frogs\")").unwrap();
+        std::fs::write(root.join("secret.fml"), "(\"secret\")").unwrap();
+
+        let rule = |path: &str| AccessRule {
+            path: path.into(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            tokens: vec![String::from("frog")],
+        };
+        let access = AccessControl::new(&[rule("/team"), rule("/secret.fml")]);
+        let listing = Listing {
+            enabled: true,
+            ..Listing::default()
+        };
+        let content = Content::new(
+            root.to_path_buf(),
+            "index.fml".into(),
+            4,
+            listing,
+            Arc::new(access),
+        );
+
+        let mut request = Request::new("/").unwrap();
+        let page = content.load(&request).unwrap().page.clone();
+        assert!(page.contains("frogs.fml"));
+        assert!(!page.contains("secret.fml"));
+        assert!(!page.contains("team/"));
+
+        request.set_metadata("token", "frog").unwrap();
+        let page = content.load(&request).unwrap().page.clone();
+        assert!(page.contains("secret.fml"));
+        assert!(page.contains("team/"));
+    }
+}
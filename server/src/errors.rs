@@ -0,0 +1,221 @@
+use froggi::request::Request;
+use froggi::response::{Response, Status};
+use froggi::server::Middleware;
+use tracing::warn;
+
+use crate::config::{ErrorPage, ErrorStatus};
+
+use std::path::Path;
+
+/// Replaces the built-in page of failed responses with the configured error pages.
+///
+/// Virtual hosts use their own error pages first, then the top-level ones. If an
+/// error page can't be read or has bad markup, the built-in page is sent instead.
+pub struct ErrorPages {
+    default: Vec<ErrorPage>,
+    hosts: Vec<(String, Vec<ErrorPage>)>,
+    default_host: Option<String>,
+}
+
+impl ErrorPages {
+    pub fn new(default: Vec<ErrorPage>) -> ErrorPages {
+        ErrorPages {
+            default,
+            hosts: Vec::new(),
+            default_host: None,
+        }
+    }
+
+    /// The host whose pages are used for requests without a configured hostname.
+    pub fn default_host(mut self, name: &str) -> Self {
+        self.default_host = Some(name.into());
+        self
+    }
+
+    pub fn host(mut self, name: &str, pages: Vec<ErrorPage>) -> Self {
+        self.hosts.push((name.into(), pages));
+        self
+    }
+
+    /// Find the page for a failed request. The most specific path wins, then a page
+    /// for the exact status over one for every status.
+    pub fn find(
+        &self,
+        host: Option<&str>,
+        request_path: &str,
+        status: Status,
+    ) -> Option<&ErrorPage> {
+        let find_host = |host: &str| {
+            self.hosts
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        };
+
+        let host_pages = host
+            .and_then(find_host)
+            .or_else(|| self.default_host.as_deref().and_then(find_host));
+
+        host_pages
+            .and_then(|(_, pages)| best_match(pages, request_path, status))
+            .or_else(|| best_match(&self.default, request_path, status))
+    }
+}
+
+fn best_match<'a>(
+    pages: &'a [ErrorPage],
+    request_path: &str,
+    status: Status,
+) -> Option<&'a ErrorPage> {
+    let path = crate::segments(request_path);
+    pages
+        .iter()
+        .filter(|page| path.starts_with(&crate::segments(&page.path)))
+        .filter(|page| match page.status {
+            Some(page_status) => matches(page_status, status),
+            None => true,
+        })
+        .max_by_key(|page| (crate::segments(&page.path).len(), page.status.is_some()))
+}
+
+fn matches(page_status: ErrorStatus, status: Status) -> bool {
+    match page_status {
+        ErrorStatus::BadRequest => status == Status::BadRequest,
+        ErrorStatus::Forbidden => status == Status::Forbidden,
+        ErrorStatus::NotFound => status == Status::NotFound,
+        ErrorStatus::ServerError => status == Status::ServerError,
+        ErrorStatus::RateLimited => status == Status::RateLimited,
+        ErrorStatus::Unauthorized => status == Status::Unauthorized,
+    }
+}
+
+/// Fill in an error page template.
+fn render(template: &str, request_path: &str, message: &str) -> String {
+    template
+        .replace("${path}", &froggi::markup::escape(request_path))
+        .replace("${message}", &froggi::markup::escape(message))
+}
+
+fn load(page: &Path, request_path: &str, message: &str) -> Result<Response, String> {
+    let template = std::fs::read_to_string(page).map_err(|error| error.to_string())?;
+    let page_text = render(&template, request_path, message);
+
+    let dir = page.parent().unwrap_or_else(|| Path::new("."));
+    let items = crate::content::page_items(&page_text, &[dir]).map_err(|errors| {
+        format!(
+            "{} markup error(s), the first is: {}",
+            errors.len(),
+            errors[0]
+        )
+    })?;
+
+    Ok(Response::new(page_text, items))
+}
+
+impl Middleware for ErrorPages {
+    fn after(&self, request: &Request, response: &mut Response) {
+        let status = response.status();
+        if status.is_ok() {
+            return;
+        }
+
+        let error_page = match self.find(request.host(), request.path(), status) {
+            Some(error_page) => error_page,
+            None => return,
+        };
+
+        let message = response
+            .message()
+            .map(String::from)
+            .unwrap_or_else(|| status.to_string());
+
+        match load(&error_page.page, request.path(), &message) {
+            Ok(page) => *response = page.with_status(status),
+            Err(error) => warn!(
+                "could not use error page {} - {}",
+                error_page.page.display(),
+                error
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::TempDir;
+
+    fn page(status: Option<ErrorStatus>, path: &str, page: &str) -> ErrorPage {
+        ErrorPage {
+            status,
+            path: path.into(),
+            page: page.into(),
+        }
+    }
+
+    #[test]
+    fn most_specific_page_wins() {
+        let pages = ErrorPages::new(vec![
+            page(None, "/", "any.fml"),
+            page(Some(ErrorStatus::NotFound), "/", "not-found.fml"),
+            page(None, "/docs", "docs.fml"),
+        ])
+        .host(
+            "example.com",
+            vec![page(Some(ErrorStatus::Forbidden), "/", "example.fml")],
+        )
+        .default_host("example.com");
+
+        let find = |host, path, status| {
+            pages
+                .find(host, path, status)
+                .map(|page| page.page.to_str().unwrap())
+        };
+
+        assert_eq!(find(None, "/a", Status::NotFound), Some("not-found.fml"));
+        assert_eq!(find(None, "/a", Status::ServerError), Some("any.fml"));
+        assert_eq!(find(None, "/docs/a", Status::NotFound), Some("docs.fml"));
+        assert_eq!(
+            find(Some("EXAMPLE.com"), "/a", Status::Forbidden),
+            Some("example.fml")
+        );
+        assert_eq!(
+            find(Some("example.com"), "/a", Status::NotFound),
+            Some("not-found.fml")
+        );
+        assert_eq!(find(None, "/a", Status::Forbidden), Some("example.fml"));
+    }
+
+    #[test]
+    fn substitution() {
+        let dir = TempDir::new("froggi-error-pages-test");
+        std::fs::write(
+            dir.join("error.fml"),
+            "(\"nothing at ${path}\")\n(\"${message}\")",
+        )
+        .unwrap();
+        std::fs::write(dir.join("bad.fml"), "(box").unwrap();
+
+        let error = dir.join("error.fml");
+        let bad = dir.join("bad.fml");
+        let pages = ErrorPages::new(vec![
+            page(Some(ErrorStatus::NotFound), "/", error.to_str().unwrap()),
+            page(Some(ErrorStatus::ServerError), "/", bad.to_str().unwrap()),
+        ]);
+
+        let request = Request::new("/a\"b").unwrap();
+        let mut response = Response::error(Status::NotFound, "no page at \"/a\\\"b\"");
+        pages.after(&request, &mut response);
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(
+            response.page(),
+            "(\"nothing at /a\\\"b\")\n(\"no page at \\\"/a\\\\\\\"b\\\"\")"
+        );
+        response.parse().unwrap();
+
+        // a broken error page falls back to the built-in one
+        let mut response = Response::error(Status::ServerError, "oops");
+        let builtin = response.page().to_string();
+        pages.after(&request, &mut response);
+        assert_eq!(response.page(), builtin);
+    }
+}
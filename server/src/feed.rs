@@ -0,0 +1,359 @@
+//! Generated pages that describe a site: a feed of recently changed pages, and a
+//! sitemap of every page that can be reached by following links from the index.
+//!
+//! Both only list the pages the client asking for them may see.
+
+use froggi::request::Request;
+use froggi::response::Response;
+use froggi::server::Handler;
+use tracing::debug;
+
+use crate::auth::AccessControl;
+use crate::content::resolve;
+
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+const FEED_STYLE: &str = r#"{(feed-title bold (size "20"))
+ (feed-updated mono (fg "757575"))
+ (feed-summary (fg "606060"))}
+"#;
+
+/// How many characters of a page's text go in its summary.
+const SUMMARY_LENGTH: usize = 200;
+
+/// What the feed and sitemap know about a page.
+#[derive(Debug)]
+pub struct PageInfo {
+    /// The path the page is requested at.
+    pub path: String,
+    pub title: String,
+    pub summary: String,
+    pub modified: Option<SystemTime>,
+}
+
+/// Finds the pages of one site by following links from its index.
+pub struct Site {
+    root: PathBuf,
+    index: String,
+}
+
+impl Site {
+    pub fn new(root: PathBuf, index: String) -> Site {
+        Site { root, index }
+    }
+
+    /// Every page reachable from the index, in the order they were found. Links to
+    /// other sites, to items and to pages that are missing or don't parse are skipped.
+    pub fn crawl(&self) -> Vec<PageInfo> {
+        let mut pages = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(String::from("/"));
+
+        while let Some(request_path) = queue.pop_front() {
+            let file = match self.page_file(&request_path) {
+                Some(file) => file,
+                None => continue,
+            };
+
+            if !seen.insert(file.clone()) {
+                continue;
+            }
+
+            let page = match crate::include::expand(&self.root, &file) {
+                Ok(expanded) => expanded.page,
+                Err(error) => {
+                    debug!("not following links in {} - {}", file.display(), error);
+                    continue;
+                }
+            };
+
+            let parsed = match froggi::markup::parse::parse(&page) {
+                Ok(parsed) => parsed,
+                Err(errors) => {
+                    debug!("not following links in {} - {}", file.display(), errors[0]);
+                    continue;
+                }
+            };
+
+            // links on a directory's index page are relative to the directory
+            let base = match resolve(&self.root, &request_path) {
+                Some(dir) if dir.is_dir() && !request_path.ends_with('/') => {
+                    format!("{}/", request_path)
+                }
+                _ => request_path.clone(),
+            };
+
+            for link in parsed.links() {
+                if let Some(target) = link_target(&base, &link.decoded()) {
+                    queue.push_back(target);
+                }
+            }
+
+            let mut lines = Vec::new();
+            crate::content::page_text(&parsed, &mut lines);
+            let title = if lines.is_empty() {
+                request_path.clone()
+            } else {
+                lines.remove(0)
+            };
+
+            pages.push(PageInfo {
+                path: request_path,
+                title,
+                summary: summary(&lines),
+                modified: std::fs::metadata(&file)
+                    .and_then(|metadata| metadata.modified())
+                    .ok(),
+            });
+        }
+
+        pages
+    }
+
+    /// The page file served for a request path, if it's a page.
+    fn page_file(&self, request_path: &str) -> Option<PathBuf> {
+        let mut file = resolve(&self.root, request_path)?;
+        if file.is_dir() {
+            file = file.join(&self.index);
+        }
+
+        if file.is_file() && file.extension().map(|ext| ext == "fml") == Some(true) {
+            Some(file)
+        } else {
+            None
+        }
+    }
+
+    /// The most recently changed pages whose paths are `visible`, newest first.
+    pub fn feed(&self, entries: usize, visible: impl Fn(&str) -> bool) -> String {
+        let mut pages = self.crawl();
+        pages.retain(|info| visible(&info.path));
+        pages.sort_by_key(|info| std::cmp::Reverse(info.modified));
+        pages.truncate(entries);
+
+        let mut page = String::from(FEED_STYLE);
+        page.push_str("\n({feed-title} \"recently updated\")\n\n");
+        for info in pages {
+            page.push_str(&format!(
+                "(vbox (^ \"{}\" \"{}\")\n ({{feed-updated}} \"{}\")\n ({{feed-summary}} \"{}\"))\n",
+                froggi::markup::escape(&info.path),
+                froggi::markup::escape(&info.title),
+                info.modified
+                    .map(crate::time::format_rfc3339)
+                    .unwrap_or_default(),
+                froggi::markup::escape(&info.summary),
+            ));
+        }
+
+        page
+    }
+
+    /// Every reachable page whose path is `visible`, sorted by path.
+    pub fn sitemap(&self, visible: impl Fn(&str) -> bool) -> String {
+        let mut pages = self.crawl();
+        pages.retain(|info| visible(&info.path));
+        pages.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut page = String::from(FEED_STYLE);
+        page.push_str("\n({feed-title} \"sitemap\")\n\n");
+        for info in pages {
+            page.push_str(&format!(
+                "(^ \"{}\" \"{}\")\n",
+                froggi::markup::escape(&info.path),
+                froggi::markup::escape(&info.title),
+            ));
+        }
+
+        page
+    }
+}
+
+/// Serves the feed for a site.
+pub struct Feed {
+    pub site: Site,
+    pub entries: usize,
+    pub access: Arc<AccessControl>,
+}
+
+impl Handler for Feed {
+    fn handle(&self, request: &Request) -> Response {
+        let feed = self
+            .site
+            .feed(self.entries, |path| self.access.allows(request, path));
+        Response::new(feed, Vec::new())
+    }
+}
+
+/// Serves the sitemap for a site.
+pub struct Sitemap {
+    pub site: Site,
+    pub access: Arc<AccessControl>,
+}
+
+impl Handler for Sitemap {
+    fn handle(&self, request: &Request) -> Response {
+        let sitemap = self.site.sitemap(|path| self.access.allows(request, path));
+        Response::new(sitemap, Vec::new())
+    }
+}
+
+/// The request path a link on the page at `from` points to, or `None` if it's on
+/// another site.
+fn link_target(from: &str, link: &str) -> Option<String> {
+    // frgi://, https:// and so on
+    let external = match (link.find(':'), link.find('/')) {
+        (Some(colon), Some(slash)) => colon < slash,
+        (Some(_), None) => true,
+        _ => false,
+    };
+
+    if external {
+        return None;
+    }
+
+    let link = link.split(&['?', '#'][..]).next().unwrap_or_default();
+    if link.is_empty() {
+        return None;
+    }
+
+    let mut segments = if link.starts_with('/') {
+        Vec::new()
+    } else {
+        // relative to the directory of the linking page
+        let mut segments = crate::segments(from);
+        if !from.ends_with('/') {
+            segments.pop();
+        }
+        segments
+    };
+
+    for segment in crate::segments(link) {
+        if segment == ".." {
+            segments.pop()?;
+        } else {
+            segments.push(segment);
+        }
+    }
+
+    if link.ends_with('/') && !segments.is_empty() {
+        Some(format!("/{}/", segments.join("/")))
+    } else {
+        Some(format!("/{}", segments.join("/")))
+    }
+}
+
+/// The start of a page's text, without its title, cut at a word boundary.
+fn summary(lines: &[String]) -> String {
+    let text = lines
+        .iter()
+        .map(|line| line.trim())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if text.chars().count() <= SUMMARY_LENGTH {
+        return text;
+    }
+
+    let end = text
+        .char_indices()
+        .nth(SUMMARY_LENGTH)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    let cut = text[..end].rfind(' ').unwrap_or(end);
+    format!("{}...", &text[..cut])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::TempDir;
+
+    #[test]
+    fn links() {
+        assert_eq!(link_target("/", "a.fml"), Some(String::from("/a.fml")));
+        assert_eq!(
+            link_target("/docs/index.fml", "b.fml#part"),
+            Some(String::from("/docs/b.fml"))
+        );
+        assert_eq!(
+            link_target("/docs/", "../c.fml"),
+            Some(String::from("/c.fml"))
+        );
+        assert_eq!(link_target("/docs/a.fml", "/d/"), Some(String::from("/d/")));
+        assert_eq!(link_target("/", "../escape.fml"), None);
+        assert_eq!(link_target("/", "frgi://froggi.io/"), None);
+        assert_eq!(link_target("/", "https://zphixon.com/lemon"), None);
+        assert_eq!(link_target("/", "mailto:frog@froggi.io"), None);
+        assert_eq!(link_target("/", "#anchor"), None);
+    }
+
+    #[test]
+    fn crawls_from_index() {
+        let root = TempDir::new("froggi-feed-test");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        let write = |path: &str, contents: &str| std::fs::write(root.join(path), contents).unwrap();
+
+        write(
+            "index.fml",
+            "(\"home\")\n(^ \"docs/\" \"docs\")\n(^ \"frgi://elsewhere/\" \"away\")\n",
+        );
+        write(
+            "docs/index.fml",
+            "(\"docs\")\n(\"all about \\\"froggi\\\"\")\n(^ \"../index.fml\" \"home\")\n(^ \"a.fml\" \"a\")\n",
+        );
+        write(
+            "docs/a.fml",
+            "(box (\"page a\") (^ \"/missing.fml\" \"gone\"))\n",
+        );
+        write("orphan.fml", "(\"nobody links here\")\n");
+
+        let site = Site::new(root.to_path_buf(), String::from("index.fml"));
+        let pages = site.crawl();
+        let paths = pages
+            .iter()
+            .map(|page| page.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/", "/docs/", "/docs/a.fml"]);
+        assert_eq!(pages[1].title, "docs");
+        assert!(pages[1].summary.starts_with("all about"));
+        assert_eq!(pages[2].title, "page a");
+
+        froggi::markup::parse::parse(&site.feed(10, |_| true)).unwrap();
+        let sitemap = site.sitemap(|_| true);
+        froggi::markup::parse::parse(&sitemap).unwrap();
+        assert!(sitemap.contains("(^ \"/docs/a.fml\" \"page a\")"));
+        assert!(!sitemap.contains("orphan"));
+
+        // pages behind an access rule are left out for clients that can't see them
+        let access = Arc::new(AccessControl::new(&[crate::config::AccessRule {
+            path: String::from("/docs"),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            tokens: vec![String::from("frog")],
+        }]));
+        let sitemap = Sitemap {
+            site,
+            access: Arc::clone(&access),
+        };
+
+        let mut request = Request::new("/sitemap").unwrap();
+        let page = sitemap.handle(&request).page().to_string();
+        assert!(page.contains("\"/\""));
+        assert!(!page.contains("/docs"));
+
+        request.set_metadata("token", "frog").unwrap();
+        assert!(sitemap.handle(&request).page().contains("/docs/a.fml"));
+    }
+
+    #[test]
+    fn summaries() {
+        let long = vec![String::from("word ").repeat(60)];
+        let summary = summary(&long);
+        assert!(summary.len() <= SUMMARY_LENGTH + 3);
+        assert!(summary.ends_with("word..."));
+    }
+}
@@ -0,0 +1,424 @@
+//! `(include "fragment.fml")` directives, expanded before a page is sent.
+//!
+//! The fragment's items replace the directive, and its page styles are merged into the
+//! including page's, so clients only ever see plain FML. If a style is defined more
+//! than once, the including page's definition wins. Fragment paths are relative to the
+//! file that includes them, and can't leave the document root.
+
+use froggi::markup::scan::{Scanner, Token, TokenKind};
+use froggi::FroggiError;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Error produced while expanding includes.
+#[derive(Debug)]
+pub enum IncludeError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Cycle {
+        chain: Vec<PathBuf>,
+    },
+    OutsideRoot {
+        path: PathBuf,
+        include: String,
+    },
+    Malformed {
+        path: PathBuf,
+        line: usize,
+    },
+    Markup {
+        path: PathBuf,
+        error: Box<FroggiError>,
+    },
+}
+
+#[rustfmt::skip]
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IncludeError::Io { path, error }
+                => write!(f, "could not read {} - {}", path.display(), error),
+            IncludeError::Cycle { chain }
+                => write!(f, "include cycle: {}", chain.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(" -> ")),
+            IncludeError::OutsideRoot { path, include }
+                => write!(f, "{} includes {:?}, which is outside of the document root", path.display(), include),
+            IncludeError::Malformed { path, line }
+                => write!(f, "{} line {}: expected (include \"file.fml\")", path.display(), line),
+            IncludeError::Markup { path, error }
+                => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+/// A page with its includes expanded.
+#[derive(Debug)]
+pub struct Expanded {
+    pub page: String,
+    /// The page itself, then every fragment it included.
+    pub files: Vec<PathBuf>,
+    marks: Vec<Mark>,
+}
+
+impl Expanded {
+    /// Find the file and line that a line of the expanded page came from.
+    pub fn locate(&self, line: usize) -> (&Path, usize) {
+        match self.marks.iter().rev().find(|mark| mark.line <= line) {
+            Some(mark) => (
+                &self.files[mark.file],
+                mark.source_line + (line - mark.line),
+            ),
+            None => (&self.files[0], line),
+        }
+    }
+
+    /// Point markup errors in the expanded page at the file they came from. Returns the
+    /// file of the first error.
+    pub fn locate_errors(&self, errors: &mut [FroggiError]) -> PathBuf {
+        let mut first = None;
+        for error in errors.iter_mut() {
+            if let Some(line) = error.line() {
+                let (path, line) = self.locate(line);
+                error.set_line(line);
+                first.get_or_insert_with(|| path.to_path_buf());
+            }
+        }
+
+        first.unwrap_or_else(|| self.files[0].clone())
+    }
+}
+
+/// Read the page at `path` under `root` and expand its includes.
+pub fn expand(root: &Path, path: &Path) -> Result<Expanded, IncludeError> {
+    let mut expander = Expander {
+        root,
+        files: Vec::new(),
+        stack: Vec::new(),
+        styles: Output::default(),
+        body: Output::default(),
+    };
+
+    expander.file(path)?;
+
+    let Expander {
+        files,
+        styles,
+        body,
+        ..
+    } = expander;
+
+    let mut page = String::new();
+    let mut marks = Vec::new();
+
+    if !styles.text.trim().is_empty() {
+        page.push('{');
+        page.push_str(&styles.text);
+        page.push_str("}\n");
+        marks = styles.marks;
+    }
+
+    let offset = page.matches('\n').count();
+    page.push_str(&body.text);
+    marks.extend(body.marks.into_iter().map(|mark| Mark {
+        line: mark.line + offset,
+        ..mark
+    }));
+
+    Ok(Expanded { page, files, marks })
+}
+
+/// Where a run of lines in the expanded page came from.
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+    line: usize,
+    file: usize,
+    source_line: usize,
+}
+
+struct Output {
+    text: String,
+    line: usize,
+    marks: Vec<Mark>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            text: String::new(),
+            line: 1,
+            marks: Vec::new(),
+        }
+    }
+}
+
+impl Output {
+    fn push(&mut self, text: &str, file: usize, source_line: usize) {
+        if text.is_empty() {
+            return;
+        }
+
+        self.marks.push(Mark {
+            line: self.line,
+            file,
+            source_line,
+        });
+        self.line += text.matches('\n').count();
+        self.text.push_str(text);
+    }
+}
+
+struct Expander<'r> {
+    root: &'r Path,
+    files: Vec<PathBuf>,
+    stack: Vec<PathBuf>,
+    styles: Output,
+    body: Output,
+}
+
+impl Expander<'_> {
+    fn file(&mut self, path: &Path) -> Result<(), IncludeError> {
+        if self.stack.iter().any(|other| other == path) {
+            let mut chain = self.stack.clone();
+            chain.push(path.into());
+            return Err(IncludeError::Cycle { chain });
+        }
+
+        let source = std::fs::read_to_string(path).map_err(|error| IncludeError::Io {
+            path: path.into(),
+            error,
+        })?;
+
+        let tokens = tokens(&source).map_err(|error| IncludeError::Markup {
+            path: path.into(),
+            error: Box::new(error),
+        })?;
+
+        let file = self.files.len();
+        self.files.push(path.into());
+        self.stack.push(path.into());
+
+        let line_of = |offset: usize| source[..offset].matches('\n').count() + 1;
+
+        // the page style block, which has to come first
+        let mut cursor = 0;
+        let mut i = 0;
+        let mut own_styles = None;
+        if let Some(left) = tokens
+            .first()
+            .filter(|token| token.kind() == TokenKind::LeftBrace)
+        {
+            if let Some(right) = tokens
+                .iter()
+                .position(|token| token.kind() == TokenKind::RightBrace)
+            {
+                let start = left.span().start.offset + 1;
+                let end = tokens[right].span().start.offset;
+                own_styles = Some((start, end));
+                cursor = end + 1;
+                i = right + 1;
+            }
+        }
+
+        while i < tokens.len() {
+            let is_include = tokens[i].kind() == TokenKind::LeftParen
+                && matches!(tokens.get(i + 1), Some(token)
+                    if token.kind() == TokenKind::Identifier && token.lexeme() == "include");
+
+            if !is_include {
+                i += 1;
+                continue;
+            }
+
+            let name = match (tokens.get(i + 2), tokens.get(i + 3)) {
+                (Some(name), Some(right))
+                    if name.kind() == TokenKind::String
+                        && right.kind() == TokenKind::RightParen =>
+                {
+                    name
+                }
+                _ => {
+                    return Err(IncludeError::Malformed {
+                        path: path.into(),
+                        line: tokens[i].line(),
+                    })
+                }
+            };
+
+            let start = tokens[i].span().start.offset;
+            self.body
+                .push(&source[cursor..start], file, line_of(cursor));
+
+            // fragments go on their own lines so that errors can be traced back to them
+            let fragment = self.resolve(path, &name.decoded())?;
+            self.body.push("\n", file, tokens[i].line());
+            self.file(&fragment)?;
+            self.body.push("\n", file, tokens[i].line());
+
+            cursor = tokens[i + 3].span().start.offset + 1;
+            i += 4;
+        }
+
+        self.body.push(&source[cursor..], file, line_of(cursor));
+
+        // pushed after the fragments' styles, so that the including page's win
+        if let Some((start, end)) = own_styles {
+            self.styles.push(&source[start..end], file, line_of(start));
+            self.styles.push("\n", file, line_of(end));
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Find an included file relative to the file including it, without leaving the root.
+    fn resolve(&self, including: &Path, include: &str) -> Result<PathBuf, IncludeError> {
+        let outside = || IncludeError::OutsideRoot {
+            path: including.into(),
+            include: include.into(),
+        };
+
+        let relative = including.strip_prefix(self.root).map_err(|_| outside())?;
+        let mut segments = relative
+            .parent()
+            .map(|dir| {
+                dir.iter()
+                    .map(|segment| segment.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if include.starts_with('/') {
+            segments.clear();
+        }
+
+        for segment in include.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop().ok_or_else(outside)?;
+                }
+                _ if segment.contains('\\') || segment.contains(':') => return Err(outside()),
+                _ => segments.push(segment.into()),
+            }
+        }
+
+        let mut path = self.root.to_path_buf();
+        path.extend(segments);
+        Ok(path)
+    }
+}
+
+fn tokens(source: &str) -> Result<Vec<Token<'_>>, FroggiError> {
+    let mut scanner = Scanner::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        let token = scanner.next_token()?;
+        if token.kind() == TokenKind::End {
+            return Ok(tokens);
+        }
+        tokens.push(token);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::TempDir;
+
+    #[test]
+    fn includes_are_spliced() {
+        let root = TempDir::with_files(
+            "froggi-include-splice",
+            &[
+                (
+                    "index.fml",
+                    "{(title (fg \"00ff00\"))}\n(include \"parts/header.fml\")\n(\"body\")\n",
+                ),
+                (
+                    "parts/header.fml",
+                    "{(title (fg \"ff0000\"))\n (small (size \"8\"))}\n({title} \"header\")\n",
+                ),
+            ],
+        );
+
+        let expanded = expand(&root, &root.join("index.fml")).unwrap();
+        assert!(!expanded.page.contains("include"));
+        assert!(expanded.page.contains("({title} \"header\")"));
+        assert!(expanded.page.contains("(\"body\")"));
+        assert_eq!(
+            expanded.files,
+            vec![root.join("index.fml"), root.join("parts/header.fml")]
+        );
+
+        // the including page's style comes last, so it wins
+        let fragment = expanded.page.find("ff0000").unwrap();
+        let page = expanded.page.find("00ff00").unwrap();
+        assert!(fragment < page);
+
+        froggi::markup::parse::parse(&expanded.page).unwrap();
+    }
+
+    #[test]
+    fn cycles_and_escapes() {
+        let root = TempDir::with_files(
+            "froggi-include-errors",
+            &[
+                ("a.fml", "(include \"b.fml\")"),
+                ("b.fml", "(include \"/a.fml\")"),
+                ("out.fml", "(include \"../../etc/passwd\")"),
+                ("bad.fml", "(include)"),
+            ],
+        );
+
+        match expand(&root, &root.join("a.fml")) {
+            Err(IncludeError::Cycle { chain }) => assert_eq!(chain.len(), 3),
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+        assert!(matches!(
+            expand(&root, &root.join("out.fml")),
+            Err(IncludeError::OutsideRoot { .. })
+        ));
+        assert!(matches!(
+            expand(&root, &root.join("bad.fml")),
+            Err(IncludeError::Malformed { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn lines_map_back_to_files() {
+        let root = TempDir::with_files(
+            "froggi-include-lines",
+            &[
+                (
+                    "index.fml",
+                    "{(a (fg \"000000\"))}\n(\"one\")\n(include \"part.fml\")\n(\"two\")\n",
+                ),
+                ("part.fml", "{(b (fg \"ffffff\"))}\n(\"x\")\n\n(\"y\")\n"),
+                ("broken.fml", "(\"one\")\n\n(include \"broken-part.fml\")"),
+                ("broken-part.fml", "(\"x\")\n(^)\n"),
+            ],
+        );
+
+        let expanded = expand(&root, &root.join("index.fml")).unwrap();
+        for (i, line) in expanded.page.lines().enumerate() {
+            let (file, source_line) = expanded.locate(i + 1);
+            let source = std::fs::read_to_string(file).unwrap();
+            if line.contains('"') && !line.contains("(fg") {
+                assert_eq!(
+                    source.lines().nth(source_line - 1),
+                    Some(line),
+                    "line {}",
+                    i + 1
+                );
+            }
+        }
+
+        let expanded = expand(&root, &root.join("broken.fml")).unwrap();
+        let mut errors = froggi::markup::parse::parse(&expanded.page).unwrap_err();
+        let file = expanded.locate_errors(&mut errors);
+        assert_eq!(file, root.join("broken-part.fml"));
+        assert_eq!(errors[0].line(), Some(2));
+    }
+}
@@ -0,0 +1,247 @@
+use crate::config::{Listing, Sort};
+use crate::segments;
+
+use std::cmp::Ordering;
+use std::path::Path;
+use std::time::SystemTime;
+
+const LISTING_STYLE: &str = r#"{(listing-title bold (size "20"))
+ (listing-heading bold)
+ (listing-name (fill "4"))
+ (listing-size mono)
+ (listing-modified mono (fill "2"))}
+"#;
+
+/// How a single directory is listed, after applying per-directory rules.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ListingSettings {
+    pub enabled: bool,
+    pub show_hidden: bool,
+    pub sort: Sort,
+    pub descending: bool,
+}
+
+impl Listing {
+    /// Find the settings for a request path. The most specific directory rule wins.
+    pub fn settings_for(&self, request_path: &str) -> ListingSettings {
+        let mut settings = ListingSettings {
+            enabled: self.enabled,
+            show_hidden: self.show_hidden,
+            sort: self.sort,
+            descending: self.descending,
+        };
+
+        let path = segments(request_path);
+        let mut rules = self
+            .directories
+            .iter()
+            .filter(|rule| path.starts_with(&segments(&rule.path)))
+            .collect::<Vec<_>>();
+        rules.sort_by_key(|rule| segments(&rule.path).len());
+
+        for rule in rules {
+            settings.enabled = rule.enabled.unwrap_or(settings.enabled);
+            settings.show_hidden = rule.show_hidden.unwrap_or(settings.show_hidden);
+            settings.sort = rule.sort.unwrap_or(settings.sort);
+            settings.descending = rule.descending.unwrap_or(settings.descending);
+        }
+
+        settings
+    }
+}
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Generate an FML page listing the contents of a directory, leaving out the entries
+/// whose paths aren't `visible`.
+pub fn generate(
+    dir: &Path,
+    request_path: &str,
+    settings: &ListingSettings,
+    visible: impl Fn(&str) -> bool,
+) -> std::io::Result<String> {
+    let path = segments(request_path);
+    let base = path.join("/");
+    let link = |name: &str| {
+        if base.is_empty() {
+            format!("/{}", name)
+        } else {
+            format!("/{}/{}", base, name)
+        }
+    };
+
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && !settings.show_hidden || !visible(&link(&name)) {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    entries.sort_by(|a, b| {
+        // directories always come first
+        let order = match settings.sort {
+            Sort::Name => a.name.cmp(&b.name),
+            Sort::Size => a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name)),
+            Sort::Modified => a.modified.cmp(&b.modified),
+        };
+
+        let order = if settings.descending {
+            order.reverse()
+        } else {
+            order
+        };
+
+        match (a.is_dir, b.is_dir) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => order,
+        }
+    });
+
+    let title = format!("/{}", base);
+
+    let mut page = String::from(LISTING_STYLE);
+    page.push_str(&format!(
+        "\n({{listing-title}} \"index of {}\")\n\n",
+        froggi::markup::escape(&title)
+    ));
+    page.push_str(
+        "(box {listing-heading}\n \
+         ({listing-name} \"name\")\n \
+         ({listing-size} \"size\")\n \
+         ({listing-modified} \"modified\"))\n",
+    );
+
+    if let Some((_, parent)) = path.split_last() {
+        page.push_str(&format!(
+            "(box (^ \"/{}\" {{listing-name}} \"../\") ({{listing-size}} \"\") ({{listing-modified}} \"\"))\n",
+            froggi::markup::escape(&parent.join("/"))
+        ));
+    }
+
+    for entry in entries {
+        let (name, size) = if entry.is_dir {
+            (format!("{}/", entry.name), String::from("-"))
+        } else {
+            (entry.name.clone(), format_size(entry.size))
+        };
+
+        page.push_str(&format!(
+            "(box (^ \"{}\" {{listing-name}} \"{}\") ({{listing-size}} \"{}\") ({{listing-modified}} \"{}\"))\n",
+            froggi::markup::escape(&link(&entry.name)),
+            froggi::markup::escape(&name),
+            size,
+            entry
+                .modified
+                .map(crate::time::format_minutes)
+                .unwrap_or_default(),
+        ));
+    }
+
+    Ok(page)
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+
+    let mut size = size as f64 / 1024.;
+    let mut unit = 0;
+    while size >= 1024. && unit + 1 < UNITS.len() {
+        size /= 1024.;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ListingDirectory;
+    use crate::test::TempDir;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let listing = Listing {
+            enabled: true,
+            directories: vec![
+                ListingDirectory {
+                    path: String::from("/private/"),
+                    enabled: Some(false),
+                    ..ListingDirectory::default()
+                },
+                ListingDirectory {
+                    path: String::from("/private/shared"),
+                    enabled: Some(true),
+                    sort: Some(Sort::Size),
+                    ..ListingDirectory::default()
+                },
+            ],
+            ..Listing::default()
+        };
+
+        assert!(listing.settings_for("/public").enabled);
+        assert!(!listing.settings_for("private/stuff").enabled);
+        assert!(listing.settings_for("/privateer").enabled);
+
+        let shared = listing.settings_for("/private/shared/files");
+        assert!(shared.enabled);
+        assert_eq!(shared.sort, Sort::Size);
+    }
+
+    #[test]
+    fn generated_page_parses() {
+        let dir = TempDir::new("froggi-listing-test");
+        std::fs::create_dir_all(dir.join("subdir")).unwrap();
+        std::fs::write(dir.join("b \"quoted\".fml"), "(\"b\")").unwrap();
+        std::fs::write(dir.join("a.png"), [0u8; 2048].as_ref()).unwrap();
+        std::fs::write(dir.join(".hidden"), "").unwrap();
+
+        let settings = ListingSettings {
+            enabled: true,
+            show_hidden: false,
+            sort: Sort::Name,
+            descending: false,
+        };
+
+        let page = generate(&dir, "/files/", &settings, |_| true).unwrap();
+        froggi::markup::parse::parse(&page).unwrap();
+
+        assert!(!page.contains(".hidden"));
+        assert!(page.contains("(^ \"/\" {listing-name} \"../\")"));
+        assert!(page.contains("(^ \"/files/a.png\""));
+        assert!(page.contains("2.0 KiB"));
+
+        let subdir = page.find("subdir/").unwrap();
+        let a = page.find("a.png").unwrap();
+        let b = page.find("b \\\"quoted\\\".fml").unwrap();
+        assert!(subdir < a && a < b);
+    }
+}
@@ -1,45 +1,367 @@
-use froggi::response::{Item, Response};
-
-use std::io::Write;
-use std::net::{TcpListener, TcpStream};
-
-fn handle_client(mut stream: TcpStream) {
-    let request = froggi::request::Request::from_bytes(&mut stream).unwrap();
-
-    println!(
-        "request (version {}, length {}): {}",
-        request.version(),
-        request.path().len(),
-        request.path()
-    );
-
-    // todo: verify markup is correct
-    // todo: some sort of page and page data cache
-    let page = String::from(include_str!("../pages/test_markup.fml"));
-    let header_img_data = include_bytes!("../pages/red_toy_small.png");
-    let mut header_img = Vec::new();
-    header_img.extend_from_slice(header_img_data);
-
-    let response = Response::new(
-        page,
-        vec![Item::new(String::from("red_toy_small.png"), header_img)],
-    );
-
-    stream.write_all(&response.into_bytes()).unwrap();
+use froggi::request::Request;
+use froggi::server::{Handler, Logger, Params, RateLimit, Router, Server, VirtualHosts};
+use froggi::tls::Identity;
+
+use tracing::info;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod access;
+mod auth;
+mod cgi;
+mod config;
+mod content;
+mod errors;
+mod feed;
+mod include;
+mod listing;
+mod publish;
+#[cfg(feature = "search")]
+mod search;
+mod time;
+mod watch;
+
+use access::AccessLogger;
+use auth::AccessControl;
+use cgi::Cgi;
+use config::{Config, LogLevel};
+use content::Content;
+use errors::ErrorPages;
+use publish::Publisher;
+use watch::{ContentWatcher, Watched};
+
+/// Split a request path into its segments, ignoring empty ones and `.`.
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect()
+}
+
+/// The pages and scripts served for one host, and what the watcher should tell about
+/// changes to them. Pages that list other pages only list the ones `access` lets the
+/// client see.
+fn site(
+    config: &Config,
+    root: &Path,
+    index: &str,
+    cache_size: usize,
+    cgi: &[config::Cgi],
+    access: &Arc<AccessControl>,
+) -> (Router, Vec<Arc<dyn Watched>>) {
+    let (listing, search, feed) = (&config.listing, &config.search, &config.feed);
+    let mut router = Router::new();
+    let mut watched = Vec::<Arc<dyn Watched>>::new();
+    for cgi in cgi {
+        let pattern = format!("{}/:script/*info", cgi.path.trim_end_matches('/'));
+        let runner = Cgi::new(
+            cgi.dir.clone(),
+            Duration::from_secs(cgi.timeout),
+            cgi.max_output,
+        );
+
+        router = router.route(&pattern, move |request, params: &Params| {
+            runner.handle(
+                request,
+                params.get("script").unwrap(),
+                params.get("info").unwrap(),
+            )
+        });
+    }
+
+    if feed.enabled {
+        let site = || feed::Site::new(root.into(), index.into());
+        router = router
+            .mount(
+                &feed.path,
+                feed::Feed {
+                    site: site(),
+                    entries: feed.entries,
+                    access: Arc::clone(access),
+                },
+            )
+            .mount(
+                &feed.sitemap,
+                feed::Sitemap {
+                    site: site(),
+                    access: Arc::clone(access),
+                },
+            );
+    }
+
+    #[cfg(feature = "search")]
+    if search.enabled {
+        let index = match search::Search::new(root.into(), search.results) {
+            Ok(index) => Arc::new(index),
+            Err(error) => {
+                eprintln!("error: could not index {} - {}", root.display(), error);
+                std::process::exit(1);
+            }
+        };
+
+        let handler = Arc::clone(&index);
+        let access = Arc::clone(access);
+        router = router.mount(&search.path, move |request: &Request| {
+            handler.handle(request, &access)
+        });
+        watched.push(index);
+    }
+
+    // validate() has made sure search isn't enabled without it being built
+    #[cfg(not(feature = "search"))]
+    let _ = search;
+
+    let content = Arc::new(Content::new(
+        root.into(),
+        index.into(),
+        cache_size,
+        listing.clone(),
+        Arc::clone(access),
+    ));
+
+    let handler = Arc::clone(&content);
+    let router = router.mount("/*path", move |request: &Request| handler.handle(request));
+    watched.push(content);
+    (router, watched)
+}
+
+fn host_site(
+    config: &Config,
+    host: &config::Host,
+    access: &Arc<AccessControl>,
+) -> (Router, Vec<Arc<dyn Watched>>) {
+    site(
+        config,
+        &host.root,
+        host.index.as_ref().unwrap_or(&config.index),
+        host.cache_size.unwrap_or(config.cache_size),
+        &host.cgi,
+        access,
+    )
 }
 
 fn main() {
-    let listener = TcpListener::bind("0.0.0.0:11121").unwrap();
-    println!("listening at {}", listener.local_addr().unwrap());
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                println!("new client");
-                std::thread::spawn(move || handle_client(stream));
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let config = match Config::from_args(&args) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            println!("{}", config::USAGE);
+            return;
+        }
+        Err(errors) => {
+            for error in errors {
+                eprintln!("error: {}", error);
+            }
+            std::process::exit(1);
+        }
+    };
+
+    // span timings are only interesting when looking for slow requests
+    let span_events = if config.log_level >= LogLevel::Debug {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::from(config.log_level))
+        .with_span_events(span_events)
+        .init();
+
+    let addrs = config.listen_addrs();
+    let mut server = match Server::bind(&addrs[..]) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("error: could not listen - {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(tls) = &config.tls {
+        server = match Identity::from_pem_files("server", &tls.certificate, &tls.key)
+            .and_then(|identity| server.tls(&identity))
+        {
+            Ok(server) => server,
+            Err(error) => {
+                eprintln!("error: could not set up tls - {}", error);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    for addr in server.local_addrs() {
+        info!("listening at {}", addr);
+    }
+
+    // the search, feed and sitemap need the access rules too
+    let mut access = AccessControl::new(&config.access);
+    if let Some(name) = &config.default_host {
+        access = access.default_host(name);
+    }
+    for host in &config.hosts {
+        access = access.host(&host.name, &host.access);
+    }
+    let access = Arc::new(access);
+
+    let (default_site, default_watched) = match &config.default_host {
+        Some(name) => {
+            let host = config
+                .hosts
+                .iter()
+                .find(|host| host.name.eq_ignore_ascii_case(name))
+                .expect("validated default host");
+            host_site(&config, host, &access)
+        }
+        None => site(
+            &config,
+            &config.root,
+            &config.index,
+            config.cache_size,
+            &config.cgi,
+            &access,
+        ),
+    };
+
+    let mut hosts = VirtualHosts::new(default_site);
+    let mut watched = default_watched;
+    let mut error_pages = ErrorPages::new(config.error_pages.clone());
+    let mut publisher = Publisher::new(&config.publish, &config.root);
+    if let Some(name) = &config.default_host {
+        error_pages = error_pages.default_host(name);
+        publisher = publisher.default_host(name);
+    }
+
+    for host in &config.hosts {
+        info!("serving {} from {}", host.name, host.root.display());
+        let (site, site_watched) = host_site(&config, host, &access);
+        hosts = hosts.host(&host.name, site);
+        watched.extend(site_watched);
+        error_pages = error_pages.host(&host.name, host.error_pages.clone());
+        publisher = publisher.host(&host.name, &host.root);
+    }
+    publisher = publisher.watched(watched.clone());
+
+    // kept alive until the server stops
+    let _watcher = if config.watch {
+        match ContentWatcher::new(watched) {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                eprintln!("error: could not watch for changes - {}", error);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut server = server
+        .read_timeout(Duration::from_secs(config.limits.read_timeout))
+        .request_deadline(Duration::from_secs(config.limits.request_deadline))
+        .write_timeout(Duration::from_secs(config.limits.write_timeout))
+        .max_connections(config.limits.max_connections)
+        .max_request_size(config.limits.max_request_size);
+
+    // rate limiting goes first so that limited clients cost as little as possible
+    if config.rate_limit.enabled || !config.rate_limit.banned.is_empty() {
+        let mut rate_limit = if config.rate_limit.enabled {
+            RateLimit::new(
+                config.rate_limit.requests_per_second,
+                config.rate_limit.burst,
+            )
+            .max_connections(config.rate_limit.max_connections)
+        } else {
+            RateLimit::unlimited()
+        };
+
+        for entry in &config.rate_limit.banned {
+            rate_limit = rate_limit.ban(entry.parse().expect("validated ban"));
+        }
+
+        server = server.middleware(rate_limit);
+    }
+
+    let server = server
+        .middleware(access)
+        .middleware(publisher)
+        .middleware(error_pages);
+
+    let server = match &config.access_log {
+        Some(access_log) => match AccessLogger::new(access_log) {
+            Ok(logger) => server.middleware(logger),
+            Err(error) => {
+                eprintln!(
+                    "error: could not open access log {} - {}",
+                    access_log.path.display(),
+                    error
+                );
+                std::process::exit(1);
             }
-            Err(e) => {
-                println!("error {}", e);
+        },
+        None => server.middleware(Logger::new(|line| info!("{}", line))),
+    };
+
+    let result = server.serve(hosts);
+
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A directory only one test uses, so tests can run at the same time, even in
+    /// different processes. It's removed when dropped.
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> TempDir {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "{}-{}-{}",
+                name,
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        /// A directory with some files in it, by their paths relative to it.
+        pub fn with_files(name: &str, files: &[(&str, &str)]) -> TempDir {
+            let dir = TempDir::new(name);
+            for (path, contents) in files {
+                let path = dir.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
             }
+            dir
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TempDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
         }
     }
 }
@@ -0,0 +1,509 @@
+//! Publish requests, which put a page and its items under a site's document root.
+//!
+//! The page is checked like any page being served, with its includes expanded and
+//! every item it references either uploaded or already next to it. Items can't be
+//! pages, so they can't replace anything that isn't checked. Every item is written
+//! before any of them replace anything, and the page is replaced last, each by renaming
+//! a finished file over the old one, so clients never see half of an update.
+
+use froggi::request::{Request, Upload};
+use froggi::response::{Item, Response, Status};
+use froggi::server::Middleware;
+use tracing::{info, warn};
+
+use crate::config;
+use crate::content::{blob_names, is_item_name, resolve};
+use crate::watch::Watched;
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Accepts publish requests from clients with the right credentials.
+///
+/// Publish requests never reach the handler, even when publishing is disabled.
+pub struct Publisher {
+    enabled: bool,
+    tokens: Vec<String>,
+    identities: Vec<String>,
+    root: PathBuf,
+    hosts: Vec<(String, PathBuf)>,
+    default_host: Option<String>,
+    /// Told about published pages and items, whether or not the roots are watched.
+    watched: Vec<Arc<dyn Watched>>,
+    /// Keeps temporary files from concurrent requests apart.
+    counter: AtomicUsize,
+}
+
+impl Publisher {
+    pub fn new(publish: &config::Publish, root: &Path) -> Publisher {
+        Publisher {
+            enabled: publish.enabled,
+            tokens: publish.tokens.clone(),
+            identities: publish.identities.clone(),
+            root: root.into(),
+            hosts: Vec::new(),
+            default_host: None,
+            watched: Vec::new(),
+            counter: AtomicUsize::new(0),
+        }
+    }
+
+    /// The host whose root is published to for requests without a configured hostname.
+    pub fn default_host(mut self, name: &str) -> Self {
+        self.default_host = Some(name.into());
+        self
+    }
+
+    pub fn host(mut self, name: &str, root: &Path) -> Self {
+        self.hosts.push((name.into(), root.into()));
+        self
+    }
+
+    /// Tell these about everything that's published, so that it's served straight away.
+    pub fn watched(mut self, watched: Vec<Arc<dyn Watched>>) -> Self {
+        self.watched = watched;
+        self
+    }
+
+    fn changed(&self, path: &Path) {
+        for watched in &self.watched {
+            if let Ok(relative) = path.strip_prefix(watched.root()) {
+                watched.changed(relative);
+            }
+        }
+    }
+
+    fn root(&self, host: Option<&str>) -> &Path {
+        let find_host = |host: &str| {
+            self.hosts
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        };
+
+        host.and_then(find_host)
+            .or_else(|| self.default_host.as_deref().and_then(find_host))
+            .map(|(_, root)| root.as_path())
+            .unwrap_or(&self.root)
+    }
+
+    fn authenticate(&self, request: &Request) -> Result<(), Response> {
+        let token = request.token().map(|sent| {
+            self.tokens
+                .iter()
+                .any(|token| crate::auth::same(token, sent))
+        });
+        let identity = request.client_fingerprint().map(|sent| {
+            self.identities
+                .iter()
+                .any(|identity| identity.eq_ignore_ascii_case(sent))
+        });
+
+        match (token, identity) {
+            (Some(true), _) | (_, Some(true)) => Ok(()),
+            (None, None) => Err(Response::error(
+                Status::Unauthorized,
+                "publishing needs a token or an identity",
+            )),
+            _ => Err(Response::error(
+                Status::Unauthorized,
+                "the credentials for publishing were not accepted",
+            )),
+        }
+    }
+
+    fn publish(&self, request: &Request, upload: &Upload) -> Result<Response, Response> {
+        if !self.enabled {
+            return Err(Response::error(
+                Status::Forbidden,
+                "publishing is not enabled on this server",
+            ));
+        }
+
+        self.authenticate(request)?;
+
+        let path = request.path();
+        let hidden = crate::segments(path)
+            .iter()
+            .any(|segment| segment.starts_with('.'));
+        if !path.ends_with(".fml") || hidden {
+            return Err(Response::error(
+                Status::BadRequest,
+                format!("{} is not a page that can be published", path),
+            ));
+        }
+
+        for item in upload.items() {
+            if !is_item_name(item.name()) || is_page_name(item.name()) {
+                return Err(Response::error(
+                    Status::BadRequest,
+                    format!("{:?} is not a valid item name", item.name()),
+                ));
+            }
+        }
+
+        let root = self.root(request.host());
+        let file = resolve(root, path).ok_or_else(|| {
+            Response::error(
+                Status::Forbidden,
+                format!("{} is outside of the document root", path),
+            )
+        })?;
+
+        let dir = file.parent().expect("resolved paths are under the root");
+        std::fs::create_dir_all(dir).map_err(|error| server_error(dir, error))?;
+
+        // checked where it will live, so that its includes are found
+        let page = self.temporary(&file);
+        std::fs::write(&page, upload.page()).map_err(|error| server_error(&page, error))?;
+
+        if let Err(message) = check(root, &page, dir, upload.items()) {
+            // the publisher doesn't need to know where the root is or what we called
+            // the page while checking it
+            let message = message
+                .replace(&page.display().to_string(), path)
+                .replace(&root.display().to_string(), "");
+            remove(&page);
+            return Err(Response::error(Status::BadRequest, message));
+        }
+
+        let mut written = Vec::new();
+        let discard = |written: &[(PathBuf, PathBuf)]| {
+            for (temporary, _) in written {
+                remove(temporary);
+            }
+            remove(&page);
+        };
+
+        for item in upload.items() {
+            let target = dir.join(item.name());
+            let temporary = self.temporary(&target);
+            if let Err(error) = std::fs::write(&temporary, item.data()) {
+                let _ = std::fs::remove_file(&temporary);
+                discard(&written);
+                return Err(server_error(&target, error));
+            }
+            written.push((temporary, target));
+        }
+
+        for (i, (temporary, target)) in written.iter().enumerate() {
+            if let Err(error) = std::fs::rename(temporary, target) {
+                discard(&written[i..]);
+                return Err(server_error(target, error));
+            }
+        }
+
+        std::fs::rename(&page, &file).map_err(|error| {
+            remove(&page);
+            server_error(&file, error)
+        })?;
+
+        for item in upload.items() {
+            self.changed(&dir.join(item.name()));
+        }
+        self.changed(&file);
+
+        info!(
+            "published {} with {} items to {}",
+            path,
+            upload.items().len(),
+            file.display()
+        );
+
+        Ok(Response::new(
+            format!("(\"published {}\")", froggi::markup::escape(path)),
+            Vec::new(),
+        ))
+    }
+
+    /// A hidden file next to `path` to write to before renaming it into place.
+    fn temporary(&self, path: &Path) -> PathBuf {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        path.with_file_name(format!(
+            ".{}.publish-{}-{}",
+            name,
+            std::process::id(),
+            self.counter.fetch_add(1, Ordering::SeqCst)
+        ))
+    }
+}
+
+impl Middleware for Publisher {
+    fn before(&self, request: &Request) -> Option<Response> {
+        let upload = request.upload()?;
+        Some(match self.publish(request, upload) {
+            Ok(response) => response,
+            Err(response) => {
+                info!(
+                    "refused to publish {} - {}",
+                    request.path(),
+                    response.message().unwrap_or_default()
+                );
+                response
+            }
+        })
+    }
+}
+
+/// Check the page written to `page` like it will be when it's served, with `items`
+/// about to be written next to it.
+fn check(root: &Path, page: &Path, dir: &Path, items: &[Item]) -> Result<(), String> {
+    let expanded = crate::include::expand(root, page).map_err(|error| error.to_string())?;
+
+    let names = froggi::markup::parse::parse(&expanded.page)
+        .map(|parsed| blob_names(&parsed))
+        .map_err(|mut errors| {
+            expanded.locate_errors(&mut errors);
+            errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })?;
+
+    for name in names {
+        if !is_item_name(&name) {
+            return Err(format!(
+                "the page references item {:?} outside its directory",
+                name
+            ));
+        }
+
+        if !items.iter().any(|item| item.name() == name) && !dir.join(&name).is_file() {
+            return Err(format!("the page references missing item {:?}", name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether an item would be served as a page, which only the page being published may
+/// be, after it's checked.
+fn is_page_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("fml"))
+        .unwrap_or(false)
+}
+
+fn server_error(path: &Path, error: io::Error) -> Response {
+    warn!("could not publish to {} - {}", path.display(), error);
+    Response::error(Status::ServerError, "the page could not be saved")
+}
+
+fn remove(path: &Path) {
+    if let Err(error) = std::fs::remove_file(path) {
+        warn!("could not remove {} - {}", path.display(), error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::auth::AccessControl;
+    use crate::config::Listing;
+    use crate::content::Content;
+    use crate::test::TempDir;
+
+    fn publisher(name: &str) -> (Publisher, TempDir) {
+        let root = TempDir::new(name);
+        std::fs::write(root.join("footer.fml"), "(\"the end\")\n").unwrap();
+
+        let config = config::Publish {
+            enabled: true,
+            tokens: vec![String::from("secret")],
+            identities: Vec::new(),
+        };
+        (Publisher::new(&config, &root), root)
+    }
+
+    fn publish(publisher: &Publisher, path: &str, page: &str, items: Vec<Item>) -> Response {
+        let mut request = Request::publish(path, Upload::new(page.into(), items)).unwrap();
+        request.set_metadata("token", "secret").unwrap();
+        publisher.before(&request).unwrap()
+    }
+
+    #[test]
+    fn pages_are_written() {
+        let (publisher, root) = publisher("froggi-publish-test");
+
+        let frog = Item::new(String::from("frog.png"), vec![1, 2, 3]);
+        let page = "(\"frogs\")\n(& \"frog.png\" \"a frog\")\n(include \"../footer.fml\")\n";
+        let response = publish(&publisher, "/docs/frogs.fml", page, vec![frog]);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            std::fs::read_to_string(root.join("docs/frogs.fml")).unwrap(),
+            page
+        );
+        assert_eq!(
+            std::fs::read(root.join("docs/frog.png")).unwrap(),
+            [1, 2, 3]
+        );
+
+        // the item is already there
+        let page = "(& \"frog.png\" \"the same frog\")\n";
+        let response = publish(&publisher, "/docs/frogs.fml", page, Vec::new());
+        assert_eq!(response.status(), Status::Ok);
+
+        // pages are served as they were published, even if they were cached
+        let content = Arc::new(Content::new(
+            root.to_path_buf(),
+            String::from("index.fml"),
+            4,
+            Listing::default(),
+            Arc::new(AccessControl::new(&[])),
+        ));
+        let watched: Arc<dyn Watched> = content.clone();
+        let publisher = publisher.watched(vec![watched]);
+        assert_eq!(
+            content
+                .load(&Request::new("/docs/frogs.fml").unwrap())
+                .unwrap()
+                .page,
+            page
+        );
+
+        let page = "(& \"frog.png\" \"a new frog\")\n";
+        let frog = Item::new(String::from("frog.png"), vec![4, 5, 6]);
+        let response = publish(&publisher, "/docs/frogs.fml", page, vec![frog]);
+        assert_eq!(response.status(), Status::Ok);
+        let published = content
+            .load(&Request::new("/docs/frogs.fml").unwrap())
+            .unwrap();
+        assert_eq!(published.page, page);
+        assert_eq!(published.items[0].data(), [4, 5, 6]);
+
+        // nothing is left behind
+        let mut names = std::fs::read_dir(root.join("docs"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["frog.png", "frogs.fml"]);
+    }
+
+    #[test]
+    fn bad_pages_are_refused() {
+        let (publisher, root) = publisher("froggi-publish-refused-test");
+        std::fs::write(root.join("index.fml"), "(\"home\")\n").unwrap();
+
+        let refused = |path: &str, page: &str, items: Vec<Item>| {
+            let status = publish(&publisher, path, page, items).status();
+            assert_eq!(
+                std::fs::read_to_string(root.join("index.fml")).unwrap(),
+                "(\"home\")\n"
+            );
+            status
+        };
+
+        assert_eq!(
+            refused("/index.fml", "(\"unclosed", Vec::new()),
+            Status::BadRequest
+        );
+        assert_eq!(
+            refused("/index.fml", "(& \"gone.png\" \"missing\")", Vec::new()),
+            Status::BadRequest
+        );
+        assert_eq!(
+            refused("/index.fml", "(include \"nowhere.fml\")", Vec::new()),
+            Status::BadRequest
+        );
+        assert_eq!(
+            refused(
+                "/index.fml",
+                "(\"home\")",
+                vec![Item::new(String::from("../escape.png"), Vec::new())]
+            ),
+            Status::BadRequest
+        );
+        assert_eq!(
+            refused(
+                "/a.fml",
+                "(\"a\")",
+                vec![Item::new(String::from("index.fml"), b"(\"gone\"".to_vec())]
+            ),
+            Status::BadRequest
+        );
+        assert_eq!(
+            refused(
+                "/a.fml",
+                "(\"a\")",
+                vec![Item::new(String::from("INDEX.FML"), Vec::new())]
+            ),
+            Status::BadRequest
+        );
+        // blobs can't reach files outside the page's directory, even ones that exist
+        let footer = root.join("footer.fml");
+        let outside = [
+            footer.display().to_string(),
+            format!(
+                "../{}/footer.fml",
+                root.file_name().unwrap().to_string_lossy()
+            ),
+        ];
+        for name in outside.iter() {
+            let page = format!("(& \"{}\")", froggi::markup::escape(name));
+            assert_eq!(refused("/index.fml", &page, Vec::new()), Status::BadRequest);
+        }
+
+        assert_eq!(
+            refused("/index.txt", "(\"home\")", Vec::new()),
+            Status::BadRequest
+        );
+        assert_eq!(
+            refused("/.hidden/a.fml", "(\"home\")", Vec::new()),
+            Status::BadRequest
+        );
+        assert_eq!(
+            refused("/c:/index.fml", "(\"home\")", Vec::new()),
+            Status::Forbidden
+        );
+
+        let request =
+            Request::publish("/index.fml", Upload::new(String::new(), Vec::new())).unwrap();
+        assert_eq!(
+            publisher.before(&request).unwrap().status(),
+            Status::Unauthorized
+        );
+
+        // only the page that was already there
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn failed_items_replace_nothing() {
+        let (publisher, root) = publisher("froggi-publish-failed-test");
+        std::fs::write(root.join("frog.png"), [1]).unwrap();
+
+        // too long to write once it's made into a temporary name
+        let items = vec![
+            Item::new(String::from("frog.png"), vec![2]),
+            Item::new("a".repeat(250), vec![3]),
+        ];
+        let response = publish(&publisher, "/frogs.fml", "(\"frogs\")", items);
+        assert_eq!(response.status(), Status::ServerError);
+
+        assert_eq!(std::fs::read(root.join("frog.png")).unwrap(), [1]);
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn reads_pass_through() {
+        let (publisher, _) = publisher("froggi-publish-reads-test");
+        assert!(publisher
+            .before(&Request::new("/index.fml").unwrap())
+            .is_none());
+
+        let disabled = Publisher::new(&config::Publish::default(), Path::new("."));
+        let request =
+            Request::publish("/index.fml", Upload::new(String::new(), Vec::new())).unwrap();
+        assert_eq!(
+            disabled.before(&request).unwrap().status(),
+            Status::Forbidden
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Full-text search over the pages of a site, built with the `search` feature.
+//!
+//! Every page under the document root is indexed by its text, link text and blob alt
+//! text. Requests for the search path with a query, like `/search?froggi+markup`, get
+//! a page of links to the best matches that the client may see.
+
+use froggi::markup::Page;
+use froggi::request::Request;
+use froggi::response::{Response, Status};
+
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::{Field, Schema, Value, STORED, STRING, TEXT};
+use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy, SnippetGenerator, Term};
+use tantivy::{TantivyDocument, TantivyError};
+use tracing::{debug, warn};
+
+use crate::auth::AccessControl;
+use crate::include;
+use crate::watch::Watched;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const SEARCH_STYLE: &str = r#"{(search-title bold (size "20"))
+ (search-snippet (fg "606060"))}
+"#;
+
+/// How many bytes of a page's text are shown under each result.
+const SNIPPET_LENGTH: usize = 160;
+
+/// A search result.
+#[derive(Debug, PartialEq)]
+pub struct Hit {
+    pub path: String,
+    pub title: String,
+    pub snippet: String,
+}
+
+struct Fields {
+    path: Field,
+    title: Field,
+    body: Field,
+}
+
+/// The search index for one document root, kept in memory.
+pub struct Search {
+    root: PathBuf,
+    results: usize,
+    fields: Fields,
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    /// Every indexed page and the fragments it includes.
+    pages: Mutex<HashMap<PathBuf, Vec<PathBuf>>>,
+}
+
+impl Search {
+    /// Index every page under `root`.
+    pub fn new(root: PathBuf, results: usize) -> tantivy::Result<Search> {
+        let mut schema = Schema::builder();
+        let fields = Fields {
+            path: schema.add_text_field("path", STRING | STORED),
+            title: schema.add_text_field("title", TEXT | STORED),
+            body: schema.add_text_field("body", TEXT | STORED),
+        };
+
+        let index = Index::create_in_ram(schema.build());
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let writer = index.writer_with_num_threads(1, 15_000_000)?;
+
+        let search = Search {
+            root,
+            results,
+            fields,
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            pages: Mutex::new(HashMap::new()),
+        };
+
+        search.reindex(&[], &pages_in(&search.root))?;
+        Ok(search)
+    }
+
+    /// Remove `stale` pages from the index and add the ones in `fresh` that still exist.
+    fn reindex(&self, stale: &[PathBuf], fresh: &[PathBuf]) -> tantivy::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let mut pages = self.pages.lock().unwrap();
+
+        for page in stale {
+            writer.delete_term(Term::from_field_text(self.fields.path, &self.url(page)));
+            pages.remove(page);
+        }
+
+        for page in fresh.iter().filter(|page| page.is_file()) {
+            let expanded = match include::expand(&self.root, page) {
+                Ok(expanded) => expanded,
+                Err(error) => {
+                    debug!("not indexing {} - {}", page.display(), error);
+                    continue;
+                }
+            };
+
+            let (title, body) = match froggi::markup::parse::parse(&expanded.page) {
+                Ok(parsed) => page_text(&parsed),
+                Err(errors) => {
+                    debug!("not indexing {} - {}", page.display(), errors[0]);
+                    continue;
+                }
+            };
+
+            writer.add_document(doc!(
+                self.fields.path => self.url(page),
+                self.fields.title => title,
+                self.fields.body => body,
+            ))?;
+            pages.insert(page.clone(), expanded.files[1..].to_vec());
+        }
+
+        writer.commit()?;
+        self.reader.reload()
+    }
+
+    /// The path a page is requested at.
+    fn url(&self, page: &Path) -> String {
+        let relative = page.strip_prefix(&self.root).unwrap_or(page);
+        let segments = relative
+            .iter()
+            .map(|segment| segment.to_string_lossy())
+            .collect::<Vec<_>>();
+        format!("/{}", segments.join("/"))
+    }
+
+    /// Find the pages that best match a query, leaving out the ones whose paths
+    /// aren't `visible`. Mistakes in the query, like unbalanced quotes, are ignored.
+    pub fn search(
+        &self,
+        query: &str,
+        visible: impl Fn(&str) -> bool,
+    ) -> Result<Vec<Hit>, TantivyError> {
+        let parser = QueryParser::for_index(&self.index, vec![self.fields.title, self.fields.body]);
+        let (query, _) = parser.parse_query_lenient(query);
+
+        let searcher = self.reader.searcher();
+        let mut snippets = SnippetGenerator::create(&searcher, &*query, self.fields.body)?;
+        snippets.set_max_num_chars(SNIPPET_LENGTH);
+
+        // keep looking past pages that aren't visible until there are enough results
+        let mut hits = Vec::new();
+        let mut offset = 0;
+        loop {
+            let top = TopDocs::with_limit(self.results).and_offset(offset);
+            let found = searcher.search(&query, &top)?;
+
+            for (_, address) in &found {
+                let doc = searcher.doc::<TantivyDocument>(*address)?;
+                let text = |field| {
+                    doc.get_first(field)
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+
+                let path = text(self.fields.path);
+                if !visible(&path) {
+                    continue;
+                }
+
+                hits.push(Hit {
+                    path,
+                    title: text(self.fields.title),
+                    snippet: snippets
+                        .snippet_from_doc(&doc)
+                        .fragment()
+                        .trim()
+                        .to_string(),
+                });
+
+                if hits.len() == self.results {
+                    return Ok(hits);
+                }
+            }
+
+            if found.len() < self.results {
+                return Ok(hits);
+            }
+            offset += found.len();
+        }
+    }
+}
+
+impl Watched for Search {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Update the index after something under the root changed.
+    fn changed(&self, relative: &Path) {
+        let changed = self.root.join(relative);
+        let stale = self
+            .pages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(page, includes)| {
+                page.starts_with(&changed)
+                    || includes.iter().any(|include| include.starts_with(&changed))
+            })
+            .map(|(page, _)| page.clone())
+            .collect::<Vec<_>>();
+
+        let mut fresh = stale.clone();
+        for page in pages_in(&changed) {
+            if !fresh.contains(&page) {
+                fresh.push(page);
+            }
+        }
+
+        if let Err(error) = self.reindex(&stale, &fresh) {
+            warn!("could not update the search index - {}", error);
+        }
+    }
+}
+
+impl Search {
+    /// Answer a search request with the results `access` lets its client see.
+    pub fn handle(&self, request: &Request, access: &AccessControl) -> Response {
+        let query = decode_query(request.query().unwrap_or_default());
+        if query.trim().is_empty() {
+            return Response::error(
+                Status::BadRequest,
+                "nothing to search for, put the query after a ?",
+            );
+        }
+
+        let hits = match self.search(&query, |path| access.allows(request, path)) {
+            Ok(hits) => hits,
+            Err(error) => {
+                warn!("search for {:?} failed - {}", query, error);
+                return Response::error(Status::ServerError, "search failed");
+            }
+        };
+
+        Response::new(results_page(&query, &hits), Vec::new())
+    }
+}
+
+/// Every `.fml` file at or under `path`, skipping hidden ones.
+fn pages_in(path: &Path) -> Vec<PathBuf> {
+    let mut pages = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    if path.is_file() {
+        pages.push(path.to_path_buf());
+        dirs.clear();
+    }
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                pages.push(path);
+            }
+        }
+    }
+
+    pages.retain(|page| page.extension().map(|ext| ext == "fml") == Some(true));
+    pages
+}
+
+/// A page's title, which is its first line of text, and all of its text.
+fn page_text(page: &Page) -> (String, String) {
+    let mut lines = Vec::new();
+    crate::content::page_text(page, &mut lines);
+    let title = lines.first().cloned().unwrap_or_default();
+    (title, lines.join("\n"))
+}
+
+/// Queries are sent like `frog+markup` or `frog%20markup`.
+fn decode_query(query: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = query.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => match std::str::from_utf8(&rest[..rest.len().min(2)])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(decoded) if rest.len() >= 2 => {
+                    bytes.push(decoded);
+                    rest = &rest[2..];
+                }
+                _ => bytes.push(byte),
+            },
+            _ => bytes.push(byte),
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn results_page(query: &str, hits: &[Hit]) -> String {
+    let mut page = String::from(SEARCH_STYLE);
+    page.push_str(&format!(
+        "\n({{search-title}} \"results for {}\")\n\n",
+        froggi::markup::escape(query)
+    ));
+
+    if hits.is_empty() {
+        page.push_str("(\"nothing matched\")\n");
+    }
+
+    for hit in hits {
+        let title = if hit.title.trim().is_empty() {
+            &hit.path
+        } else {
+            &hit.title
+        };
+
+        page.push_str(&format!(
+            "(vbox (^ \"{}\" \"{}\") ({{search-snippet}} \"{}\"))\n",
+            froggi::markup::escape(&hit.path),
+            froggi::markup::escape(title),
+            froggi::markup::escape(&hit.snippet.replace('\n', " ")),
+        ));
+    }
+
+    page
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::TempDir;
+
+    #[test]
+    fn finds_pages() {
+        let root = TempDir::with_files(
+            "froggi-search-test",
+            &[
+                (
+                    "index.fml",
+                    "(\"welcome\")\n(^ \"frogs.fml\" \"all about frogs\")\n",
+                ),
+                (
+                    "frogs.fml",
+                    "(\"frogs\")\n(box (\"frogs are amphibians\"))\n",
+                ),
+                (
+                    "toads/index.fml",
+                    "(\"toads\")\n(& \"toad.png\" \"a warty toad\")\n",
+                ),
+                (".hidden/secret.fml", "(\"hidden frogs\")\n"),
+            ],
+        );
+
+        let search = Search::new(root.to_path_buf(), 10).unwrap();
+        let paths = |query: &str| {
+            let mut paths = search
+                .search(query, |_| true)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit.path)
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(paths("frogs"), vec!["/frogs.fml", "/index.fml"]);
+        assert_eq!(paths("warty"), vec!["/toads/index.fml"]);
+
+        let hit = &search.search("amphibians", |_| true).unwrap()[0];
+        assert_eq!(hit.title, "frogs");
+        assert!(hit.snippet.contains("amphibians"));
+
+        // pages the client may not see are left out, without fewer results
+        let limited = Search::new(root.to_path_buf(), 1).unwrap();
+        let hits = limited
+            .search("frogs", |path| path != "/frogs.fml")
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, "/index.fml");
+
+        let access = AccessControl::new(&[crate::config::AccessRule {
+            path: String::from("/toads"),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            tokens: vec![String::from("toad")],
+        }]);
+        let response = search.handle(&Request::new("/search?warty").unwrap(), &access);
+        assert!(response.page().contains("nothing matched"));
+
+        std::fs::write(root.join("frogs.fml"), "(\"newts\")\n").unwrap();
+        search.changed(Path::new("frogs.fml"));
+        assert!(paths("amphibians").is_empty());
+        assert_eq!(paths("newts"), vec!["/frogs.fml"]);
+
+        std::fs::remove_dir_all(root.join("toads")).unwrap();
+        search.changed(Path::new("toads"));
+        assert!(paths("warty").is_empty());
+    }
+
+    #[test]
+    fn queries() {
+        assert_eq!(decode_query("frog+markup"), "frog markup");
+        assert_eq!(decode_query("frog%20%22markup%22"), "frog \"markup\"");
+        assert_eq!(decode_query("100%"), "100%");
+
+        let page = results_page(
+            "frog",
+            &[Hit {
+                path: String::from("/frogs.fml"),
+                title: String::from("frogs"),
+                snippet: String::from("frogs are\namphibians"),
+            }],
+        );
+        froggi::markup::parse::parse(&page).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Split a time into UTC (year, month, day, hour, minute, second).
+pub fn utc(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(error) => -(error.duration().as_secs() as i64),
+    };
+
+    let days = secs.div_euclid(86400);
+    let secs = secs.rem_euclid(86400) as u32;
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Format a time like `2020-07-19 21:04`, in UTC.
+pub fn format_minutes(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, _) = utc(time);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year, month, day, hour, minute
+    )
+}
+
+/// Format a time like `2020-07-19T21:04:05.123Z`, in UTC.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc(time);
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_millis())
+        .unwrap_or(0);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, hour, minute, second, millis
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn civil() {
+        assert_eq!(utc(UNIX_EPOCH), (1970, 1, 1, 0, 0, 0));
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_595_192_645);
+        assert_eq!(utc(time), (2020, 7, 19, 21, 4, 5));
+        assert_eq!(format_minutes(time), "2020-07-19 21:04");
+        assert_eq!(
+            format_rfc3339(time + Duration::from_millis(7)),
+            "2020-07-19T21:04:05.007Z"
+        );
+
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(utc(leap_day), (2000, 2, 29, 0, 0, 0));
+    }
+}
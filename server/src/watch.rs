@@ -0,0 +1,88 @@
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{info, warn};
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Something that needs to know when files under a document root change.
+pub trait Watched: Send + Sync {
+    fn root(&self) -> &Path;
+    /// Called with the changed path, relative to the root.
+    fn changed(&self, relative: &Path);
+}
+
+/// Watches document roots and tells everything serving from them when files change.
+///
+/// Watching stops when this is dropped.
+pub struct ContentWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl ContentWatcher {
+    pub fn new(sites: Vec<Arc<dyn Watched>>) -> notify::Result<ContentWatcher> {
+        let (tx, rx) = mpsc::channel();
+        // editors often write a file in several steps, so wait for them to finish
+        let mut watcher = notify::watcher(tx, Duration::from_millis(250))?;
+
+        // events come with absolute paths, so compare against the canonical roots
+        let mut roots = Vec::new();
+        for site in sites {
+            let root = std::fs::canonicalize(site.root())?;
+            // a root can be shared, for example by its pages and their search index
+            if !roots.iter().any(|(other, _)| *other == root) {
+                watcher.watch(&root, RecursiveMode::Recursive)?;
+                info!("watching {} for changes", site.root().display());
+            }
+            roots.push((root, site));
+        }
+
+        std::thread::spawn(move || {
+            for event in rx {
+                handle(event, &roots);
+            }
+        });
+
+        Ok(ContentWatcher { _watcher: watcher })
+    }
+}
+
+fn handle(event: DebouncedEvent, roots: &[(PathBuf, Arc<dyn Watched>)]) {
+    let paths = match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Chmod(path)
+        | DebouncedEvent::Remove(path) => vec![path],
+        DebouncedEvent::Rename(from, to) => vec![from, to],
+        // some events were missed, so anything could have changed
+        DebouncedEvent::Rescan => {
+            let mut paths = roots
+                .iter()
+                .map(|(root, _)| root.clone())
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths.dedup();
+            paths
+        }
+        DebouncedEvent::Error(error, path) => {
+            warn!(
+                "error watching {} - {}",
+                path.as_deref()
+                    .unwrap_or_else(|| Path::new("files"))
+                    .display(),
+                error
+            );
+            Vec::new()
+        }
+        DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) => Vec::new(),
+    };
+
+    for path in paths {
+        for (root, site) in roots {
+            if let Ok(relative) = path.strip_prefix(root) {
+                site.changed(relative);
+            }
+        }
+    }
+}